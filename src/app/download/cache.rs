@@ -0,0 +1,137 @@
+use actix_web::{
+    HttpRequest,
+    http::header::{self, EntityTag, Header, HttpDate},
+};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Validators of a rendered directory listing
+pub struct Validators {
+    pub etag: EntityTag,
+    pub last_modified: Option<HttpDate>,
+}
+
+impl Validators {
+    // The listing changes with the directory itself and with anything else rendered into it,
+    // such as the entries and the visitor's quota usage
+    pub fn new(modified: Option<SystemTime>, content: impl Hash) -> Self {
+        let mut hasher = DefaultHasher::new();
+        modified.hash(&mut hasher);
+        content.hash(&mut hasher);
+
+        Validators {
+            etag: EntityTag::new_weak(format!("{:x}", hasher.finish())),
+            // HTTP dates have a one second resolution
+            last_modified: modified.map(|time| HttpDate::from(truncate_to_secs(time))),
+        }
+    }
+
+    // If-None-Match takes precedence, If-Modified-Since is only checked without it
+    pub fn is_not_modified(&self, req: &HttpRequest) -> bool {
+        if req.headers().contains_key(header::IF_NONE_MATCH) {
+            return match header::IfNoneMatch::parse(req) {
+                Ok(header::IfNoneMatch::Any) => true,
+                Ok(header::IfNoneMatch::Items(tags)) => {
+                    tags.iter().any(|tag| tag.weak_eq(&self.etag))
+                }
+                Err(_) => false,
+            };
+        }
+
+        match (
+            header::IfModifiedSince::parse(req),
+            self.last_modified.map(SystemTime::from),
+        ) {
+            (Ok(header::IfModifiedSince(since)), Some(modified)) => {
+                modified <= SystemTime::from(since)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn truncate_to_secs(time: SystemTime) -> SystemTime {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since| UNIX_EPOCH + Duration::from_secs(since.as_secs()))
+        .unwrap_or(time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn time(secs: u64, millis: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_millis(millis)
+    }
+
+    fn http_date(secs: u64) -> String {
+        HttpDate::from(UNIX_EPOCH + Duration::from_secs(secs)).to_string()
+    }
+
+    #[test]
+    fn tags_change_with_time_and_content() {
+        let validators = Validators::new(Some(time(1000, 500)), "listing");
+        assert!(validators.etag.weak);
+        assert_eq!(
+            validators.last_modified.map(SystemTime::from),
+            Some(time(1000, 0))
+        );
+
+        let same = Validators::new(Some(time(1000, 500)), "listing");
+        let content = Validators::new(Some(time(1000, 500)), "other listing");
+        let modified = Validators::new(Some(time(1001, 500)), "listing");
+        assert_eq!(validators.etag, same.etag);
+        assert_ne!(validators.etag, content.etag);
+        assert_ne!(validators.etag, modified.etag);
+
+        assert!(Validators::new(None, "listing").last_modified.is_none());
+    }
+
+    #[test]
+    fn answers_conditional_requests() {
+        let validators = Validators::new(Some(time(1000, 500)), "listing");
+        let etag = validators.etag.to_string();
+        let strong = etag.trim_start_matches("W/").to_string();
+        let other = Validators::new(None, "other").etag.to_string();
+
+        // If-None-Match, If-Modified-Since, then whether the listing is unchanged
+        let cases = [
+            (None, None, false),
+            (Some(etag.as_str()), None, true),
+            (Some(strong.as_str()), None, true),
+            (Some("*"), None, true),
+            (Some(other.as_str()), None, false),
+            (Some(&*format!("{}, {}", other, etag)), None, true),
+            (None, Some(http_date(1000)), true),
+            (None, Some(http_date(2000)), true),
+            (None, Some(http_date(999)), false),
+            (None, Some("yesterday".to_string()), false),
+            // If-None-Match takes precedence
+            (Some(other.as_str()), Some(http_date(2000)), false),
+        ];
+
+        for (if_none_match, if_modified_since, expected) in cases {
+            let mut req = TestRequest::default();
+            if let Some(value) = if_none_match {
+                req = req.insert_header((header::IF_NONE_MATCH, value));
+            }
+            if let Some(value) = &if_modified_since {
+                req = req.insert_header((header::IF_MODIFIED_SINCE, value.as_str()));
+            }
+            assert_eq!(
+                validators.is_not_modified(&req.to_http_request()),
+                expected,
+                "{:?} {:?}",
+                if_none_match,
+                if_modified_since
+            );
+        }
+
+        // Without a modification time only the ETag can match
+        let req = TestRequest::default()
+            .insert_header((header::IF_MODIFIED_SINCE, http_date(2000)))
+            .to_http_request();
+        assert!(!Validators::new(None, "listing").is_not_modified(&req));
+    }
+}
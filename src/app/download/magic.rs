@@ -0,0 +1,183 @@
+use serde::Serialize;
+use std::path::Path;
+
+// Bytes needed to recognize every signature, the tar magic is the furthest in
+pub const HEADER_LEN: usize = 512;
+
+// Kind of content recognized by its first bytes
+#[derive(Debug, Serialize)]
+pub struct FileType {
+    pub description: &'static str,
+    pub extension: Option<&'static str>, // None for formats without a customary extension
+}
+
+// All parts have to match, each at its offset from the start of the file
+struct Signature {
+    parts: &'static [(usize, &'static [u8])],
+    file_type: FileType,
+}
+
+const fn signature(
+    parts: &'static [(usize, &'static [u8])],
+    description: &'static str,
+    extension: Option<&'static str>,
+) -> Signature {
+    Signature {
+        parts,
+        file_type: FileType {
+            description,
+            extension,
+        },
+    }
+}
+
+// Longer and more specific signatures come first, the first match wins
+const SIGNATURES: &[Signature] = &[
+    signature(&[(0, b"\x7fELF")], "ELF executable", None),
+    signature(&[(0, b"\xfe\xed\xfa\xce")], "Mach-O executable", None),
+    signature(&[(0, b"\xfe\xed\xfa\xcf")], "Mach-O executable", None),
+    signature(&[(0, b"\xce\xfa\xed\xfe")], "Mach-O executable", None),
+    signature(&[(0, b"\xcf\xfa\xed\xfe")], "Mach-O executable", None),
+    signature(&[(0, b"\0asm")], "WebAssembly module", Some("wasm")),
+    signature(&[(0, b"!<arch>\ndebian")], "Debian package", Some("deb")),
+    signature(&[(0, b"!<arch>\n")], "ar archive", Some("a")),
+    signature(&[(0, b"\xed\xab\xee\xdb")], "RPM package", Some("rpm")),
+    signature(
+        &[(0, b"SQLite format 3\0")],
+        "SQLite database",
+        Some("sqlite"),
+    ),
+    signature(&[(0, b"\x89PNG\r\n\x1a\n")], "PNG image", Some("png")),
+    signature(&[(0, b"\xff\xd8\xff")], "JPEG image", Some("jpg")),
+    signature(&[(0, b"GIF87a")], "GIF image", Some("gif")),
+    signature(&[(0, b"GIF89a")], "GIF image", Some("gif")),
+    signature(&[(0, b"RIFF"), (8, b"WEBP")], "WebP image", Some("webp")),
+    signature(&[(0, b"RIFF"), (8, b"WAVE")], "WAV audio", Some("wav")),
+    signature(&[(0, b"RIFF"), (8, b"AVI ")], "AVI video", Some("avi")),
+    signature(&[(0, b"II*\0")], "TIFF image", Some("tiff")),
+    signature(&[(0, b"MM\0*")], "TIFF image", Some("tiff")),
+    signature(&[(0, b"%PDF-")], "PDF document", Some("pdf")),
+    signature(&[(0, b"PK\x03\x04")], "ZIP archive", Some("zip")),
+    signature(&[(0, b"PK\x05\x06")], "ZIP archive (empty)", Some("zip")),
+    signature(&[(0, b"7z\xbc\xaf\x27\x1c")], "7-Zip archive", Some("7z")),
+    signature(&[(0, b"Rar!\x1a\x07")], "RAR archive", Some("rar")),
+    signature(&[(257, b"ustar")], "tar archive", Some("tar")),
+    signature(&[(0, b"\x1f\x8b")], "gzip compressed data", Some("gz")),
+    signature(&[(0, b"BZh")], "bzip2 compressed data", Some("bz2")),
+    signature(&[(0, b"\xfd7zXZ\0")], "xz compressed data", Some("xz")),
+    signature(
+        &[(0, b"\x28\xb5\x2f\xfd")],
+        "Zstandard compressed data",
+        Some("zst"),
+    ),
+    signature(
+        &[(0, b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1")],
+        "Microsoft Office document (legacy)",
+        None,
+    ),
+    signature(&[(0, b"ID3")], "MP3 audio", Some("mp3")),
+    signature(&[(0, b"OggS")], "Ogg media", Some("ogg")),
+    signature(&[(0, b"fLaC")], "FLAC audio", Some("flac")),
+    signature(&[(4, b"ftyp")], "MP4 media", Some("mp4")),
+    signature(
+        &[(0, b"\x1a\x45\xdf\xa3")],
+        "Matroska or WebM video",
+        Some("mkv"),
+    ),
+    signature(&[(0, b"\0\0\x01\0")], "Windows icon", Some("ico")),
+    signature(&[(0, b"MZ")], "Windows executable (PE)", Some("exe")),
+    signature(&[(0, b"BM")], "BMP image", Some("bmp")),
+];
+
+// Extensions that say nothing about the content, e.g. the default name of raw uploads
+const GENERIC_EXTENSIONS: &[&str] = &["bin", "dat", "raw", "tmp"];
+
+// Identify content by the magic bytes at the start of a file
+pub fn identify(header: &[u8]) -> Option<&'static FileType> {
+    SIGNATURES
+        .iter()
+        .find(|signature| {
+            signature.parts.iter().all(|(offset, magic)| {
+                header
+                    .get(*offset..offset + magic.len())
+                    .is_some_and(|bytes| bytes == *magic)
+            })
+        })
+        .map(|signature| &signature.file_type)
+}
+
+// Better name for a file without a meaningful extension, None if it already has one
+pub fn suggested_name(path: &Path, file_type: &FileType) -> Option<String> {
+    let extension = file_type.extension?;
+    let generic = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_none_or(|ext| {
+            GENERIC_EXTENSIONS
+                .iter()
+                .any(|generic| ext.eq_ignore_ascii_case(generic))
+        });
+    if !generic {
+        return None;
+    }
+    Some(
+        path.with_extension(extension)
+            .file_name()?
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifies_by_magic_bytes() {
+        let mut tar = vec![0u8; HEADER_LEN];
+        tar[257..262].copy_from_slice(b"ustar");
+
+        let cases: [(&[u8], Option<&str>); 10] = [
+            (b"\x89PNG\r\n\x1a\n....", Some("PNG image")),
+            (b"\x7fELF\x02\x01", Some("ELF executable")),
+            (b"RIFF\0\0\0\0WEBPVP8 ", Some("WebP image")),
+            (b"RIFF\0\0\0\0WAVEfmt ", Some("WAV audio")),
+            (b"!<arch>\ndebian-binary", Some("Debian package")),
+            (b"!<arch>\nfoo.o", Some("ar archive")),
+            (&tar, Some("tar archive")),
+            (b"\0\0\0\x20ftypisom", Some("MP4 media")),
+            (b"RIFF", None),
+            (b"hello world", None),
+        ];
+        for (header, expected) in cases {
+            assert_eq!(
+                identify(header).map(|file_type| file_type.description),
+                expected,
+                "{:?}",
+                header
+            );
+        }
+    }
+
+    #[test]
+    fn suggests_names_for_generic_files() {
+        let png = identify(b"\x89PNG\r\n\x1a\n").unwrap();
+        let elf = identify(b"\x7fELF").unwrap();
+        let cases = [
+            ("upload", png, Some("upload.png")),
+            ("upload.bin", png, Some("upload.png")),
+            ("dir/capture.DAT", png, Some("capture.png")),
+            ("photo.jpg", png, None),
+            ("photo.png", png, None),
+            ("program", elf, None),
+        ];
+        for (path, file_type, expected) in cases {
+            assert_eq!(
+                suggested_name(Path::new(path), file_type).as_deref(),
+                expected,
+                "{}",
+                path
+            );
+        }
+    }
+}
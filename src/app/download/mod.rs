@@ -1,3 +1,9 @@
+pub mod cache;
 pub mod forms;
+pub mod magic;
+pub mod one_time;
+pub mod precompressed;
+pub mod preview;
+pub mod thumbnails;
 pub mod urls;
 pub mod views;
@@ -0,0 +1,328 @@
+use actix_files::NamedFile;
+use actix_web::{
+    HttpResponse,
+    body::{BodySize, BoxBody, MessageBody},
+    http::header,
+    web,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+
+use crate::app::errors::AppError;
+
+// Size of the blocks one-time files are read in
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// On-disk format of the one-time file registry
+#[derive(Default, Serialize, Deserialize)]
+struct RegistryFile {
+    files: BTreeSet<String>, // Paths relative to the root directory
+}
+
+#[derive(Default)]
+struct Registry {
+    files: BTreeSet<String>,
+    in_progress: HashSet<String>, // Files currently being downloaded
+}
+
+// Uploads that are deleted once they were downloaded completely
+pub struct OneTimeFiles {
+    base_path: PathBuf,
+    registry_path: PathBuf,
+    registry: Mutex<Registry>,
+}
+
+impl OneTimeFiles {
+    pub fn load(base_path: PathBuf, registry_path: PathBuf) -> std::io::Result<Self> {
+        let files = match std::fs::read(&registry_path) {
+            Ok(content) => {
+                serde_json::from_slice::<RegistryFile>(&content)
+                    .map_err(|e| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("One-time file registry {}: {}", registry_path.display(), e),
+                        )
+                    })?
+                    .files
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => BTreeSet::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(OneTimeFiles {
+            base_path,
+            registry_path,
+            registry: Mutex::new(Registry {
+                files,
+                in_progress: HashSet::new(),
+            }),
+        })
+    }
+
+    // Path of a file relative to the root directory, as used for the registry
+    fn relative_key(&self, file_path: &Path) -> Option<String> {
+        file_path
+            .strip_prefix(&self.base_path)
+            .ok()
+            .map(|p| p.to_string_lossy().replace("\\", "/"))
+    }
+
+    // Mark a freshly uploaded file as one-time, or clear the mark when it was replaced
+    pub fn set(&self, file_path: &Path, one_time: bool) {
+        let Some(key) = self.relative_key(file_path) else {
+            return;
+        };
+
+        let mut registry = self.registry.lock().unwrap();
+        let changed = if one_time {
+            registry.files.insert(key)
+        } else {
+            registry.files.remove(&key)
+        };
+        if changed {
+            self.persist(&registry.files);
+        }
+    }
+
+    // Registry key of `file_path` if it is a one-time file
+    pub fn key_for(&self, file_path: &Path) -> Option<String> {
+        let key = self.relative_key(file_path)?;
+        let registry = self.registry.lock().unwrap();
+        registry.files.contains(&key).then_some(key)
+    }
+
+    // Reserve the single download of a file, false while another one is running
+    pub fn claim(&self, key: &str) -> bool {
+        let mut registry = self.registry.lock().unwrap();
+        registry.files.contains(key) && registry.in_progress.insert(key.to_string())
+    }
+
+    // End a download, deleting the file if its body was sent completely. Returns whether
+    // it was deleted, aborted downloads leave the file in place for another attempt.
+    pub fn finish(&self, key: &str, completed: bool) -> bool {
+        let mut registry = self.registry.lock().unwrap();
+        registry.in_progress.remove(key);
+        // The file may have been replaced by a plain upload in the meantime
+        if !completed || !registry.files.remove(key) {
+            return false;
+        }
+
+        let file_path = self.base_path.join(key);
+        match std::fs::remove_file(&file_path) {
+            Ok(()) => log::info!("Deleted one-time file {}", file_path.display()),
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => log::error!(
+                "Failed to delete one-time file {}: {}",
+                file_path.display(),
+                e
+            ),
+        }
+        self.persist(&registry.files);
+        true
+    }
+
+    fn persist(&self, files: &BTreeSet<String>) {
+        let content = RegistryFile {
+            files: files.clone(),
+        };
+
+        let result = (|| -> std::io::Result<()> {
+            if let Some(parent) = self.registry_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            // Write to a temporary file first so a crash never leaves a truncated registry
+            let tmp_path = self.registry_path.with_extension("tmp");
+            std::fs::write(&tmp_path, serde_json::to_vec(&content)?)?;
+            std::fs::rename(&tmp_path, &self.registry_path)
+        })();
+
+        if let Err(e) = result {
+            log::error!(
+                "Failed to write one-time file registry {}: {}",
+                self.registry_path.display(),
+                e
+            );
+        }
+    }
+}
+
+// Body that reports, once dropped, whether it was read to the end.
+// The server drops it only after the last chunk was handed to the connection.
+pub struct CompletionBody {
+    body: BoxBody,
+    completed: bool,
+    on_drop: Option<Box<dyn FnOnce(bool)>>,
+}
+
+impl CompletionBody {
+    pub fn new(body: BoxBody, on_drop: impl FnOnce(bool) + 'static) -> Self {
+        CompletionBody {
+            body,
+            completed: false,
+            on_drop: Some(Box::new(on_drop)),
+        }
+    }
+}
+
+impl MessageBody for CompletionBody {
+    type Error = Box<dyn std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<web::Bytes, Self::Error>>> {
+        let poll = Pin::new(&mut self.body).poll_next(cx);
+        if let Poll::Ready(None) = poll {
+            self.completed = true;
+        }
+        poll
+    }
+}
+
+impl Drop for CompletionBody {
+    fn drop(&mut self) {
+        if let Some(on_drop) = self.on_drop.take() {
+            on_drop(self.completed);
+        }
+    }
+}
+
+// The whole file in a single response. Range and conditional requests are
+// ignored, a file that is only handed out once must not be fetched in pieces.
+pub async fn whole_file_response(path: &Path) -> Result<HttpResponse, AppError> {
+    let named = NamedFile::open_async(path).await?;
+    let length = named.metadata().len();
+    let file = named.file().try_clone()?;
+
+    let chunks = futures_util::stream::try_unfold(file, |mut file| async move {
+        let (file, chunk) = web::block(move || {
+            let mut chunk = vec![0u8; CHUNK_SIZE];
+            let read = file.read(&mut chunk)?;
+            chunk.truncate(read);
+            Ok::<_, std::io::Error>((file, chunk))
+        })
+        .await
+        .map_err(std::io::Error::other)??;
+
+        Ok::<_, std::io::Error>((!chunk.is_empty()).then(|| (web::Bytes::from(chunk), file)))
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type(named.content_type().to_string())
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            named.content_disposition().to_string(),
+        ))
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .no_chunking(length)
+        .streaming(chunks))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{body, http::StatusCode};
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use tempfile::TempDir;
+
+    fn registry(dir: &TempDir) -> OneTimeFiles {
+        OneTimeFiles::load(
+            dir.path().to_path_buf(),
+            dir.path().join(".holonet/one_time.json"),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn deletes_files_after_a_complete_download() {
+        let dir = TempDir::new().unwrap();
+        let file_path = dir.path().join("a.txt");
+        std::fs::write(&file_path, "secret").unwrap();
+
+        let files = registry(&dir);
+        assert_eq!(files.key_for(&file_path), None);
+        files.set(&file_path, true);
+        let key = files.key_for(&file_path).unwrap();
+        assert_eq!(key, "a.txt");
+
+        // One download at a time, an aborted one leaves the file for another attempt
+        assert!(files.claim(&key));
+        assert!(!files.claim(&key));
+        assert!(!files.finish(&key, false));
+        assert!(file_path.exists());
+
+        // The mark survives a restart
+        let files = registry(&dir);
+        assert!(files.claim(&key));
+        assert!(files.finish(&key, true));
+        assert!(!file_path.exists());
+        assert_eq!(files.key_for(&file_path), None);
+        assert!(!files.claim(&key));
+    }
+
+    #[test]
+    fn replaced_files_lose_their_mark() {
+        let dir = TempDir::new().unwrap();
+        let file_path = dir.path().join("a.txt");
+        std::fs::write(&file_path, "secret").unwrap();
+
+        let files = registry(&dir);
+        files.set(&file_path, true);
+        assert!(files.claim("a.txt"));
+        files.set(&file_path, false);
+        assert!(!files.finish("a.txt", true));
+        assert!(file_path.exists());
+
+        // Files outside the root are never registered
+        files.set(Path::new("/elsewhere/a.txt"), true);
+        assert_eq!(files.key_for(Path::new("/elsewhere/a.txt")), None);
+    }
+
+    #[actix_web::test]
+    async fn reports_whether_bodies_completed() {
+        for (read_to_end, expected) in [(true, true), (false, false)] {
+            let completed = Rc::new(Cell::new(None));
+            let report = completed.clone();
+            let mut completion =
+                CompletionBody::new(BoxBody::new("hello"), move |done| report.set(Some(done)));
+
+            if read_to_end {
+                let mut completion = Pin::new(&mut completion);
+                while futures::future::poll_fn(|cx| completion.as_mut().poll_next(cx))
+                    .await
+                    .is_some()
+                {}
+            }
+            drop(completion);
+            assert_eq!(completed.get(), Some(expected), "{}", read_to_end);
+        }
+    }
+
+    #[actix_web::test]
+    async fn sends_whole_files() {
+        let dir = TempDir::new().unwrap();
+        let file_path = dir.path().join("a.txt");
+        let content = "x".repeat(3 * CHUNK_SIZE + 1);
+        std::fs::write(&file_path, &content).unwrap();
+
+        let res = whole_file_response(&file_path).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get(header::CACHE_CONTROL).unwrap(),
+            "no-store"
+        );
+        assert!(!res.headers().contains_key(header::ACCEPT_RANGES));
+        let body = body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body, content);
+    }
+}
@@ -0,0 +1,135 @@
+use actix_web::{
+    HttpMessage, HttpRequest,
+    http::header::{self, AcceptEncoding, ContentEncoding, Encoding},
+};
+use std::path::{Path, PathBuf};
+
+// Sibling file suffixes and the encoding they are stored in
+const VARIANTS: &[(&str, ContentEncoding)] = &[
+    ("br", ContentEncoding::Brotli),
+    ("gz", ContentEncoding::Gzip),
+];
+
+// Precompressed sibling the client accepts, such as "style.css.br" for "style.css"
+pub async fn find_variant(
+    req: &HttpRequest,
+    path: &Path,
+    base_path: &Path,
+) -> Option<(PathBuf, ContentEncoding)> {
+    // A range refers to the file itself, never to a compressed sibling
+    if req.headers().contains_key(header::RANGE) {
+        return None;
+    }
+    let accept_encoding = req.get_header::<AcceptEncoding>()?;
+
+    let mut available = vec![(Encoding::identity(), path.to_path_buf())];
+    for (suffix, encoding) in VARIANTS {
+        let mut variant = path.as_os_str().to_owned();
+        variant.push(".");
+        variant.push(suffix);
+
+        // Symlinked siblings must not lead out of the served directory
+        let Ok(variant) = tokio::fs::canonicalize(PathBuf::from(variant)).await else {
+            continue;
+        };
+        let is_file = tokio::fs::metadata(&variant)
+            .await
+            .is_ok_and(|meta| meta.is_file());
+        if is_file && variant.starts_with(base_path) {
+            available.push((Encoding::Known(*encoding), variant));
+        }
+    }
+
+    let chosen = accept_encoding.negotiate(available.iter().map(|(encoding, _)| encoding))?;
+    available
+        .into_iter()
+        .find(|(encoding, _)| *encoding == chosen)
+        .and_then(|(encoding, variant)| match encoding {
+            Encoding::Known(ContentEncoding::Identity) => None,
+            Encoding::Known(encoding) => Some((variant, encoding)),
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use tempfile::TempDir;
+
+    async fn variant(
+        dir: &TempDir,
+        accept_encoding: Option<&str>,
+        range: bool,
+    ) -> Option<(String, ContentEncoding)> {
+        let mut req = TestRequest::default();
+        if let Some(accept_encoding) = accept_encoding {
+            req = req.insert_header((header::ACCEPT_ENCODING, accept_encoding));
+        }
+        if range {
+            req = req.insert_header((header::RANGE, "bytes=0-10"));
+        }
+        let base_path = dir.path().canonicalize().unwrap();
+        find_variant(
+            &req.to_http_request(),
+            &base_path.join("style.css"),
+            &base_path,
+        )
+        .await
+        .map(|(path, encoding)| {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, encoding)
+        })
+    }
+
+    #[actix_web::test]
+    async fn finds_accepted_variants() {
+        let dir = TempDir::new().unwrap();
+        for name in ["style.css", "style.css.gz", "style.css.br"] {
+            std::fs::write(dir.path().join(name), name).unwrap();
+        }
+
+        let gzip = Some(("style.css.gz".to_string(), ContentEncoding::Gzip));
+        let brotli = Some(("style.css.br".to_string(), ContentEncoding::Brotli));
+        let cases = [
+            (Some("gzip"), false, gzip.clone()),
+            (Some("gzip, br"), false, brotli.clone()),
+            (Some("br;q=0.5, gzip"), false, gzip),
+            (Some("zstd"), false, None),
+            (Some("identity"), false, None),
+            (None, false, None),
+            // Ranges always refer to the file itself
+            (Some("br"), true, None),
+        ];
+        for (accept_encoding, range, expected) in cases {
+            assert_eq!(
+                variant(&dir, accept_encoding, range).await,
+                expected,
+                "{:?} {}",
+                accept_encoding,
+                range
+            );
+        }
+
+        // Directories of that name are no variant
+        std::fs::remove_file(dir.path().join("style.css.br")).unwrap();
+        std::fs::create_dir(dir.path().join("style.css.br")).unwrap();
+        assert_eq!(variant(&dir, Some("br"), false).await, None);
+    }
+
+    #[cfg(unix)]
+    #[actix_web::test]
+    async fn ignores_variants_outside_the_root() {
+        let dir = TempDir::new().unwrap();
+        let outside = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("style.css"), "css").unwrap();
+        std::fs::write(outside.path().join("secret"), "secret").unwrap();
+        std::os::unix::fs::symlink(
+            outside.path().join("secret"),
+            dir.path().join("style.css.gz"),
+        )
+        .unwrap();
+
+        assert_eq!(variant(&dir, Some("gzip"), false).await, None);
+    }
+}
@@ -0,0 +1,569 @@
+use actix_web::{HttpResponse, mime, web};
+use pulldown_cmark::{CowStr, Event, Options, Parser, Tag};
+use serde::Serialize;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::highlighted_html_for_string;
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+
+use super::magic::{self, FileType};
+use crate::app::errors::AppError;
+use crate::{PREVIEW_TEMPLATE_NAME, State};
+
+// Largest text, Markdown or source file rendered in the page
+const MAX_TEXT_PREVIEW: u64 = 1024 * 1024;
+// Bytes looked at to tell text from binary files without a known extension
+const SNIFF_LEN: usize = 8 * 1024;
+// Bytes shown on one page of a hex dump, 16 per row
+const HEX_PAGE_LEN: u64 = 4096;
+const HEX_ROW_LEN: usize = 16;
+// Matches the dark page background
+const THEME_NAME: &str = "base16-ocean.dark";
+
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+static THEME: LazyLock<Theme> = LazyLock::new(|| {
+    ThemeSet::load_defaults()
+        .themes
+        .remove(THEME_NAME)
+        .unwrap_or_default()
+});
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum PreviewKind {
+    Markdown,
+    Code,
+    Text,
+    Image,
+    Audio,
+    Video,
+    Pdf,
+    Hex,
+    TooLarge,
+}
+
+// One line of a hex dump
+#[derive(Serialize)]
+struct HexRow {
+    offset: String,
+    hex: String,
+    ascii: String, // Printable bytes, dots for the rest
+}
+
+// Page of a hex dump, pages are numbered from 1
+#[derive(Serialize)]
+struct HexDump {
+    page: u64,
+    pages: u64,
+    rows: Vec<HexRow>,
+}
+
+// Struct for Tera context of the preview page
+#[derive(Serialize)]
+struct PreviewContext {
+    base_url: String,
+    name: String,
+    size: u64,
+    file_url: String,           // Downloads the file
+    parent_url: Option<String>, // Listing the file is in, none for a shared file
+    kind: PreviewKind,
+    content: Option<String>, // Rendered HTML for Markdown and code, plain text otherwise
+    hex: Option<HexDump>,
+    file_type: Option<&'static FileType>, // Recognized by its magic bytes
+    suggested_name: Option<String>,       // For files with a generic name, e.g. raw uploads
+}
+
+// Everything `render` found out about a file
+struct Rendered {
+    kind: PreviewKind,
+    content: Option<String>,
+    hex: Option<HexDump>,
+    file_type: Option<&'static FileType>,
+}
+
+// Kind of preview from the name alone, None if the content has to decide
+fn kind_from_name(path: &Path) -> Option<PreviewKind> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    if ext == "md" || ext == "markdown" {
+        return Some(PreviewKind::Markdown);
+    }
+
+    let mime = actix_files::file_extension_to_mime(&ext);
+    match (mime.type_(), mime.subtype()) {
+        (mime::IMAGE, _) => Some(PreviewKind::Image),
+        (mime::AUDIO, _) => Some(PreviewKind::Audio),
+        (mime::VIDEO, _) => Some(PreviewKind::Video),
+        (mime::APPLICATION, mime::PDF) => Some(PreviewKind::Pdf),
+        _ => None,
+    }
+}
+
+// README shown below a directory listing
+#[derive(Serialize)]
+pub struct Readme {
+    name: String,
+    html: Option<String>, // Rendered Markdown
+    text: Option<String>, // Plain text, escaped by the template
+}
+
+// File names shown as the README of a folder, the first one found wins
+const README_NAMES: &[&str] = &["README.md", "README.txt"];
+
+// Preference of a file name as README, lower is better, matched regardless of case
+pub fn readme_rank(name: &str) -> Option<usize> {
+    README_NAMES
+        .iter()
+        .position(|readme| name.eq_ignore_ascii_case(readme))
+}
+
+// Read and render a README, None if it is too large or not text.
+// Runs on the blocking pool like `render`.
+pub fn load_readme(path: &Path) -> Option<Readme> {
+    let metadata = std::fs::metadata(path).ok()?;
+    if metadata.len() > MAX_TEXT_PREVIEW {
+        return None;
+    }
+    let text = std::fs::read_to_string(path).ok()?;
+    let name = path.file_name()?.to_string_lossy().into_owned();
+
+    if kind_from_name(path) == Some(PreviewKind::Markdown) {
+        Some(Readme {
+            name,
+            html: Some(render_markdown(&text)),
+            text: None,
+        })
+    } else {
+        Some(Readme {
+            name,
+            html: None,
+            text: Some(text),
+        })
+    }
+}
+
+// Syntax of a source file by extension or special file name, e.g. Makefile
+fn find_syntax(path: &Path, first_line: &str) -> Option<&'static SyntaxReference> {
+    let by_name = |name: Option<&std::ffi::OsStr>| {
+        name.and_then(|name| name.to_str())
+            .and_then(|name| SYNTAXES.find_syntax_by_extension(name))
+    };
+    by_name(path.extension())
+        .or_else(|| by_name(path.file_name()))
+        .or_else(|| SYNTAXES.find_syntax_by_first_line(first_line))
+        .filter(|syntax| syntax.name != "Plain Text")
+}
+
+// Only plain web and mail links survive, javascript: and friends would run in the page
+fn is_safe_url(url: &str) -> bool {
+    let url = url.trim_start();
+    match url.find([':', '/', '?', '#']) {
+        Some(index) if url[index..].starts_with(':') => {
+            let scheme = url[..index].to_ascii_lowercase();
+            matches!(scheme.as_str(), "http" | "https" | "mailto" | "ftp")
+        }
+        _ => true,
+    }
+}
+
+// Markdown to HTML, with embedded HTML shown as text instead of rendered
+fn render_markdown(text: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+
+    let events = Parser::new_ext(text, options).map(|event| match event {
+        Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) if !is_safe_url(&dest_url) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: CowStr::Borrowed("#"),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) if !is_safe_url(&dest_url) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: CowStr::Borrowed("#"),
+            title,
+            id,
+        }),
+        event => event,
+    });
+
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, events);
+    html
+}
+
+// Hex dump of page `page` (counted from 0), past the end shows the last page
+fn hex_dump(file: &mut std::fs::File, size: u64, page: u64) -> std::io::Result<HexDump> {
+    let pages = size.div_ceil(HEX_PAGE_LEN).max(1);
+    let page = page.min(pages - 1);
+    let start = page * HEX_PAGE_LEN;
+
+    let mut bytes = Vec::new();
+    file.seek(SeekFrom::Start(start))?;
+    file.take(HEX_PAGE_LEN).read_to_end(&mut bytes)?;
+
+    let rows = bytes
+        .chunks(HEX_ROW_LEN)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut hex = String::new();
+            for (position, byte) in chunk.iter().enumerate() {
+                // An extra space splits the row in halves of eight
+                if position == HEX_ROW_LEN / 2 {
+                    hex.push(' ');
+                }
+                hex.push_str(&format!("{:02x} ", byte));
+            }
+            // Keep the text column aligned on a short last row
+            let width = HEX_ROW_LEN * 3 + 1;
+            hex.push_str(&" ".repeat(width.saturating_sub(hex.len())));
+            HexRow {
+                offset: format!("{:08x}", start + (index * HEX_ROW_LEN) as u64),
+                hex,
+                ascii: chunk
+                    .iter()
+                    .map(|&byte| {
+                        if byte.is_ascii_graphic() || byte == b' ' {
+                            byte as char
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect(),
+            }
+        })
+        .collect();
+
+    Ok(HexDump {
+        page: page + 1,
+        pages,
+        rows,
+    })
+}
+
+// Whether the start of a file looks like binary data rather than text
+fn is_binary(header: &[u8]) -> bool {
+    if header.contains(&0) {
+        return true;
+    }
+    // A character cut off at the end of the header is still text
+    matches!(std::str::from_utf8(header), Err(e) if e.error_len().is_some())
+}
+
+// Read and render the file, this is CPU bound and runs on the blocking pool.
+// `hex_page` asks for a hex dump whatever the content is.
+fn render(path: &Path, size: u64, hex_page: Option<u64>) -> std::io::Result<Rendered> {
+    let mut file = std::fs::File::open(path)?;
+    let mut header = Vec::new();
+    (&mut file)
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut header)?;
+    let file_type = magic::identify(&header[..header.len().min(magic::HEADER_LEN)]);
+
+    let rendered = |kind, content| Rendered {
+        kind,
+        content,
+        hex: None,
+        file_type,
+    };
+    let hex = |file: &mut std::fs::File, page| -> std::io::Result<Rendered> {
+        Ok(Rendered {
+            kind: PreviewKind::Hex,
+            content: None,
+            hex: Some(hex_dump(file, size, page)?),
+            file_type,
+        })
+    };
+
+    if let Some(page) = hex_page {
+        return hex(&mut file, page);
+    }
+
+    let named_kind = kind_from_name(path);
+    if let Some(kind) = named_kind
+        && kind != PreviewKind::Markdown
+    {
+        // Media is embedded by URL, the page does not need the content
+        return Ok(rendered(kind, None));
+    }
+
+    // Binary files have no business in a text view
+    if is_binary(&header) {
+        return hex(&mut file, 0);
+    }
+    if size > MAX_TEXT_PREVIEW {
+        return Ok(rendered(PreviewKind::TooLarge, None));
+    }
+
+    let mut content = header;
+    (&mut file)
+        .take(MAX_TEXT_PREVIEW)
+        .read_to_end(&mut content)?;
+    let Ok(text) = String::from_utf8(content) else {
+        return hex(&mut file, 0);
+    };
+
+    if named_kind == Some(PreviewKind::Markdown) {
+        return Ok(rendered(
+            PreviewKind::Markdown,
+            Some(render_markdown(&text)),
+        ));
+    }
+
+    let first_line = text.lines().next().unwrap_or("");
+    if let Some(syntax) = find_syntax(path, first_line)
+        && let Ok(html) = highlighted_html_for_string(&text, &SYNTAXES, syntax, &THEME)
+    {
+        return Ok(rendered(PreviewKind::Code, Some(html)));
+    }
+    Ok(rendered(PreviewKind::Text, Some(text)))
+}
+
+// HTML page presenting a file, next to a button downloading it
+pub async fn preview_page(
+    state: &State,
+    path: &Path,
+    size: u64,
+    file_url: String,
+    parent_url: Option<String>,
+    hex_page: Option<u64>,
+) -> Result<HttpResponse, AppError> {
+    let source: PathBuf = path.to_path_buf();
+    let rendered = web::block(move || render(&source, size, hex_page))
+        .await
+        .map_err(std::io::Error::other)??;
+
+    let context = PreviewContext {
+        base_url: state.base_url.clone(),
+        name: path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        size,
+        file_url,
+        parent_url,
+        kind: rendered.kind,
+        content: rendered.content,
+        hex: rendered.hex,
+        file_type: rendered.file_type,
+        suggested_name: rendered
+            .file_type
+            .and_then(|file_type| magic::suggested_name(path, file_type)),
+    };
+    let rendered_body = state.tera.render(
+        PREVIEW_TEMPLATE_NAME,
+        &tera::Context::from_serialize(&context).unwrap(),
+    )?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(rendered_body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn picks_kinds_by_name() {
+        let cases = [
+            ("README.md", Some(PreviewKind::Markdown)),
+            ("notes.MARKDOWN", Some(PreviewKind::Markdown)),
+            ("photo.JPG", Some(PreviewKind::Image)),
+            ("song.mp3", Some(PreviewKind::Audio)),
+            ("clip.mp4", Some(PreviewKind::Video)),
+            ("paper.pdf", Some(PreviewKind::Pdf)),
+            ("main.rs", None),
+            ("notes.txt", None),
+            ("Makefile", None),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(kind_from_name(Path::new(name)), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn keeps_only_safe_links() {
+        let cases = [
+            ("https://example.com", true),
+            ("HTTP://example.com", true),
+            ("mailto:someone@example.com", true),
+            ("docs/page.md", true),
+            ("/docs/page.md", true),
+            ("#section", true),
+            ("page?a=b:c", true),
+            ("javascript:alert(1)", false),
+            (" JavaScript:alert(1)", false),
+            ("data:text/html,<script>", false),
+            ("vbscript:msgbox", false),
+        ];
+        for (url, expected) in cases {
+            assert_eq!(is_safe_url(url), expected, "{}", url);
+        }
+    }
+
+    #[test]
+    fn renders_markdown_without_html() {
+        let html = render_markdown(
+            "# Title\n\n<script>alert(1)</script>\n\n[click](javascript:alert(1)) [ok](https://example.com)",
+        );
+        assert!(html.contains("<h1>Title</h1>"));
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("javascript:"));
+        assert!(html.contains("href=\"#\""));
+        assert!(html.contains("href=\"https://example.com\""));
+    }
+
+    #[test]
+    fn renders_by_content() {
+        let dir = TempDir::new().unwrap();
+        let write = |name: &str, content: &[u8]| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, content).unwrap();
+            path
+        };
+
+        let cases = [
+            ("main.rs", &b"fn main() {}\n"[..], PreviewKind::Code),
+            ("script", b"#!/bin/sh\necho hi\n", PreviewKind::Code),
+            ("notes.txt", b"just text\n", PreviewKind::Text),
+            ("README.md", b"# Title\n", PreviewKind::Markdown),
+            // Binary content gets a hex dump, whatever the name says
+            ("data.bin", b"\x00\x01\x02", PreviewKind::Hex),
+            ("latin1.txt", b"caf\xe9 au lait", PreviewKind::Hex),
+            ("photo.png", b"not read", PreviewKind::Image),
+        ];
+        for (name, content, expected) in cases {
+            let path = write(name, content);
+            let rendered = render(&path, content.len() as u64, None).unwrap();
+            assert_eq!(rendered.kind, expected, "{}", name);
+            assert_eq!(
+                rendered.content.is_some(),
+                matches!(
+                    expected,
+                    PreviewKind::Code | PreviewKind::Text | PreviewKind::Markdown
+                ),
+                "{}",
+                name
+            );
+            assert_eq!(
+                rendered.hex.is_some(),
+                expected == PreviewKind::Hex,
+                "{}",
+                name
+            );
+        }
+
+        let path = write("big.txt", b"text");
+        let rendered = render(&path, MAX_TEXT_PREVIEW + 1, None).unwrap();
+        assert_eq!(rendered.kind, PreviewKind::TooLarge);
+        assert_eq!(rendered.content, None);
+
+        // Asking for a hex dump gets one for any file
+        let rendered = render(&path, 4, Some(0)).unwrap();
+        assert_eq!(rendered.kind, PreviewKind::Hex);
+    }
+
+    #[test]
+    fn ranks_readmes() {
+        let cases = [
+            ("README.md", Some(0)),
+            ("readme.MD", Some(0)),
+            ("README.txt", Some(1)),
+            ("Readme.TXT", Some(1)),
+            ("README", None),
+            ("README.rst", None),
+            ("my-README.md", None),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(readme_rank(name), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn loads_readmes() {
+        let dir = TempDir::new().unwrap();
+        let markdown = dir.path().join("README.md");
+        std::fs::write(&markdown, "# Hello <b>").unwrap();
+        let readme = load_readme(&markdown).unwrap();
+        assert_eq!(readme.name, "README.md");
+        assert!(readme.html.unwrap().contains("<h1>Hello &lt;b&gt;</h1>"));
+        assert_eq!(readme.text, None);
+
+        let text = dir.path().join("README.txt");
+        std::fs::write(&text, "Hello <b>").unwrap();
+        let readme = load_readme(&text).unwrap();
+        assert_eq!(readme.html, None);
+        assert_eq!(readme.text.as_deref(), Some("Hello <b>"));
+
+        // Binary, oversized and missing files are left out
+        std::fs::write(&text, b"caf\xe9").unwrap();
+        assert!(load_readme(&text).is_none());
+        std::fs::write(&text, vec![b'a'; MAX_TEXT_PREVIEW as usize + 1]).unwrap();
+        assert!(load_readme(&text).is_none());
+        assert!(load_readme(&dir.path().join("missing.md")).is_none());
+    }
+
+    #[test]
+    fn tells_binary_from_text() {
+        let cases: [(&[u8], bool); 6] = [
+            (b"plain text", false),
+            ("gr\u{fc}\u{df}e".as_bytes(), false),
+            // A character cut off by the end of the header
+            (&"\u{fc}".as_bytes()[..1], false),
+            (b"caf\xe9 au lait", true),
+            (b"text\0more", true),
+            (b"", false),
+        ];
+        for (header, expected) in cases {
+            assert_eq!(is_binary(header), expected, "{:?}", header);
+        }
+    }
+
+    #[test]
+    fn dumps_pages_of_hex() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("data.bin");
+        let content: Vec<u8> = (0..HEX_PAGE_LEN + 20).map(|i| i as u8).collect();
+        std::fs::write(&path, &content).unwrap();
+        let size = content.len() as u64;
+        let mut file = std::fs::File::open(&path).unwrap();
+
+        let first = hex_dump(&mut file, size, 0).unwrap();
+        assert_eq!((first.page, first.pages), (1, 2));
+        assert_eq!(first.rows.len(), HEX_PAGE_LEN as usize / HEX_ROW_LEN);
+        let row = &first.rows[2];
+        assert_eq!(row.offset, "00000020");
+        assert_eq!(row.hex, "20 21 22 23 24 25 26 27  28 29 2a 2b 2c 2d 2e 2f ");
+        assert_eq!(row.ascii, " !\"#$%&'()*+,-./");
+        assert_eq!(first.rows[0].ascii, "................");
+
+        // The last page is short, and asking past the end shows it too
+        for page in [1, 7] {
+            let last = hex_dump(&mut file, size, page).unwrap();
+            assert_eq!(last.page, 2);
+            assert_eq!(last.rows.len(), 2);
+            assert_eq!(last.rows[0].offset, "00001000");
+            assert_eq!(last.rows[1].hex.len(), HEX_ROW_LEN * 3 + 1);
+        }
+
+        let empty = hex_dump(&mut file, 0, 0).unwrap();
+        assert_eq!((empty.page, empty.pages), (1, 1));
+    }
+}
@@ -0,0 +1,354 @@
+use actix_web::web;
+use futures::channel::oneshot;
+use image::{ImageReader, Limits, codecs::jpeg::JpegEncoder};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::UNIX_EPOCH;
+
+use crate::app::errors::AppError;
+
+// Longest edge of a thumbnail in pixels
+const THUMBNAIL_SIZE: u32 = 256;
+const JPEG_QUALITY: u8 = 80;
+// Memory a single image may take up while decoding
+const MAX_DECODE_BYTES: u64 = 256 * 1024 * 1024;
+// Threads generating thumbnails, decoding large images is slow
+const WORKERS: usize = 2;
+// Size the cache is pruned to, oldest thumbnails first
+const MAX_CACHE_BYTES: u64 = 256 * 1024 * 1024;
+// Thumbnails generated between two pruning runs
+const PRUNE_INTERVAL: usize = 100;
+
+// Extensions of the formats thumbnails are generated for
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "webp", "bmp"];
+
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            IMAGE_EXTENSIONS
+                .iter()
+                .any(|image_ext| ext.eq_ignore_ascii_case(image_ext))
+        })
+}
+
+// Images waiting for a worker, with the requests waiting for them
+type Pending = Mutex<HashMap<PathBuf, Vec<oneshot::Sender<bool>>>>;
+
+// Thumbnail cache in the data directory, filled by background workers
+pub struct Thumbnails {
+    cache_dir: PathBuf,
+    jobs: mpsc::Sender<PathBuf>,
+    pending: Arc<Pending>,
+}
+
+impl Thumbnails {
+    pub fn start(cache_dir: PathBuf) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&cache_dir)?;
+        prune(&cache_dir, MAX_CACHE_BYTES)?;
+
+        let (jobs, receiver) = mpsc::channel::<PathBuf>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let pending: Arc<Pending> = Arc::default();
+        let generated_count = Arc::new(AtomicUsize::new(0));
+
+        for index in 0..WORKERS {
+            let receiver = receiver.clone();
+            let pending = pending.clone();
+            let cache_dir = cache_dir.clone();
+            let generated_count = generated_count.clone();
+            std::thread::Builder::new()
+                .name(format!("thumbnails-{}", index))
+                .spawn(move || {
+                    loop {
+                        // The lock is only held while waiting, never while decoding
+                        let Ok(source) = receiver.lock().unwrap().recv() else {
+                            return;
+                        };
+
+                        let generated = match generate(&cache_dir, &source) {
+                            Ok(true) => {
+                                if generated_count.fetch_add(1, Ordering::Relaxed) % PRUNE_INTERVAL
+                                    == PRUNE_INTERVAL - 1
+                                    && let Err(e) = prune(&cache_dir, MAX_CACHE_BYTES)
+                                {
+                                    log::warn!("Failed to prune thumbnail cache: {}", e);
+                                }
+                                true
+                            }
+                            Ok(false) => true,
+                            Err(e) => {
+                                log::debug!("No thumbnail for {}: {}", source.display(), e);
+                                false
+                            }
+                        };
+
+                        let waiters = pending.lock().unwrap().remove(&source);
+                        for waiter in waiters.into_iter().flatten() {
+                            let _ = waiter.send(generated);
+                        }
+                    }
+                })?;
+        }
+
+        Ok(Thumbnails {
+            cache_dir,
+            jobs,
+            pending,
+        })
+    }
+
+    // Queue an image unless it is cached or queued already, `waiter` is told when it is done
+    fn enqueue(&self, source: &Path, waiter: Option<oneshot::Sender<bool>>) {
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(waiters) = pending.get_mut(source) {
+            waiters.extend(waiter);
+            return;
+        }
+
+        pending.insert(source.to_path_buf(), waiter.into_iter().collect());
+        if self.jobs.send(source.to_path_buf()).is_err() {
+            log::error!("Thumbnail workers are gone");
+            pending.remove(source);
+        }
+    }
+
+    // Generate thumbnails ahead of time, e.g. for the images of a listing.
+    // Workers skip images that are cached already.
+    pub fn warm(&self, source: &Path) {
+        self.enqueue(source, None);
+    }
+
+    // Cached thumbnail of an image, waiting for a worker to generate it if needed
+    pub async fn thumbnail(&self, source: &Path) -> Result<PathBuf, AppError> {
+        let cache_dir = self.cache_dir.clone();
+        let image = source.to_path_buf();
+        let (path, cached) = web::block(move || {
+            cache_path(&cache_dir, &image).map(|path| {
+                let cached = path.exists();
+                (path, cached)
+            })
+        })
+        .await
+        .map_err(std::io::Error::other)?
+        .ok_or(AppError::ThumbnailUnavailable)?;
+        if cached {
+            return Ok(path);
+        }
+
+        let (waiter, generated) = oneshot::channel();
+        self.enqueue(source, Some(waiter));
+        match generated.await {
+            Ok(true) => Ok(path),
+            _ => Err(AppError::ThumbnailUnavailable),
+        }
+    }
+}
+
+// Cache file of an image, named after its path, size and modification time
+// so replacing the image never serves an outdated thumbnail
+fn cache_path(cache_dir: &Path, source: &Path) -> Option<PathBuf> {
+    let metadata = std::fs::metadata(source).ok()?;
+    let modified = metadata
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_nanos();
+
+    let mut hasher = Sha256::new();
+    hasher.update(source.as_os_str().as_encoded_bytes());
+    hasher.update(metadata.len().to_le_bytes());
+    hasher.update(modified.to_le_bytes());
+    let hex: String = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    Some(cache_dir.join(format!("{}.jpg", hex)))
+}
+
+// Generate the thumbnail of an image, false if it was cached already
+fn generate(cache_dir: &Path, source: &Path) -> Result<bool, Box<dyn std::error::Error>> {
+    let target = cache_path(cache_dir, source).ok_or("image is gone")?;
+    if target.exists() {
+        return Ok(false);
+    }
+
+    let mut reader = ImageReader::open(source)?.with_guessed_format()?;
+    let mut limits = Limits::default();
+    limits.max_alloc = Some(MAX_DECODE_BYTES);
+    reader.limits(limits);
+    let thumbnail = reader
+        .decode()?
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .to_rgb8();
+
+    // Write to a temporary file first so a half written thumbnail is never served
+    let tmp_path = target.with_extension("tmp");
+    let mut writer = BufWriter::new(std::fs::File::create(&tmp_path)?);
+    JpegEncoder::new_with_quality(&mut writer, JPEG_QUALITY).encode_image(&thumbnail)?;
+    writer.flush()?;
+    drop(writer);
+    std::fs::rename(&tmp_path, &target)?;
+
+    log::debug!("Generated thumbnail for {}", source.display());
+    Ok(true)
+}
+
+// Remove the oldest thumbnails until the cache fits in `max_bytes`. Thumbnails of
+// replaced or deleted images are never asked for again, so they go first eventually.
+fn prune(cache_dir: &Path, max_bytes: u64) -> std::io::Result<()> {
+    let mut files = Vec::new();
+    let mut total = 0;
+    for entry in std::fs::read_dir(cache_dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().is_none_or(|ext| ext != "jpg") {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        total += metadata.len();
+        files.push((
+            metadata.modified().unwrap_or(UNIX_EPOCH),
+            metadata.len(),
+            path,
+        ));
+    }
+
+    files.sort();
+    let mut removed = 0;
+    for (_, len, path) in files {
+        if total <= max_bytes {
+            break;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => removed += 1,
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        total -= len;
+    }
+
+    if removed > 0 {
+        log::info!("Pruned {} thumbnails from the cache", removed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, RgbImage};
+    use tempfile::TempDir;
+
+    fn write_image(path: &Path, width: u32, height: u32) {
+        RgbImage::from_pixel(width, height, image::Rgb([200, 40, 40]))
+            .save_with_format(path, ImageFormat::Png)
+            .unwrap();
+    }
+
+    #[test]
+    fn detects_images_by_extension() {
+        let cases = [
+            ("photo.jpg", true),
+            ("photo.JPEG", true),
+            ("a/b/scan.png", true),
+            ("anim.gif", true),
+            ("photo.webp", true),
+            ("photo.tiff", false),
+            ("notes.txt", false),
+            ("png", false),
+            ("archive.png.zip", false),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(is_image(Path::new(path)), expected, "{}", path);
+        }
+    }
+
+    #[test]
+    fn cache_names_follow_the_image() {
+        let dir = TempDir::new().unwrap();
+        let cache_dir = dir.path().join("cache");
+        let source = dir.path().join("a.png");
+        assert_eq!(cache_path(&cache_dir, &source), None);
+
+        write_image(&source, 4, 4);
+        let first = cache_path(&cache_dir, &source).unwrap();
+        assert!(first.starts_with(&cache_dir));
+        assert_eq!(cache_path(&cache_dir, &source), Some(first.clone()));
+
+        // Replacing the image gives it a new cache file
+        write_image(&source, 8, 8);
+        assert_ne!(cache_path(&cache_dir, &source), Some(first.clone()));
+
+        let other = dir.path().join("b.png");
+        std::fs::copy(&source, &other).unwrap();
+        assert_ne!(
+            cache_path(&cache_dir, &other),
+            cache_path(&cache_dir, &source)
+        );
+    }
+
+    #[actix_web::test]
+    async fn generates_thumbnails() {
+        let dir = TempDir::new().unwrap();
+        let source = dir.path().join("large.png");
+        write_image(&source, 1024, 512);
+        let broken = dir.path().join("broken.png");
+        std::fs::write(&broken, "not an image").unwrap();
+
+        let thumbnails = Thumbnails::start(dir.path().join("cache")).unwrap();
+        let path = thumbnails.thumbnail(&source).await.unwrap();
+        let thumbnail = image::open(&path).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (256, 128));
+        assert_eq!(ImageFormat::from_path(&path).unwrap(), ImageFormat::Jpeg);
+
+        // Cached from now on
+        assert_eq!(thumbnails.thumbnail(&source).await.unwrap(), path);
+
+        assert!(matches!(
+            thumbnails.thumbnail(&broken).await,
+            Err(AppError::ThumbnailUnavailable)
+        ));
+        assert!(matches!(
+            thumbnails.thumbnail(&dir.path().join("missing.png")).await,
+            Err(AppError::ThumbnailUnavailable)
+        ));
+    }
+
+    #[test]
+    fn prunes_the_oldest_thumbnails() {
+        let dir = TempDir::new().unwrap();
+        let now = std::time::SystemTime::now();
+        for (name, age) in [("old.jpg", 300), ("new.jpg", 0), ("mid.jpg", 100)] {
+            let path = dir.path().join(name);
+            std::fs::write(&path, [0u8; 10]).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(now - std::time::Duration::from_secs(age))
+                .unwrap();
+        }
+        std::fs::write(dir.path().join("other.txt"), [0u8; 100]).unwrap();
+
+        prune(dir.path(), 30).unwrap();
+        assert!(dir.path().join("old.jpg").exists());
+
+        prune(dir.path(), 20).unwrap();
+        assert!(!dir.path().join("old.jpg").exists());
+        assert!(dir.path().join("mid.jpg").exists());
+
+        prune(dir.path(), 5).unwrap();
+        assert!(!dir.path().join("mid.jpg").exists());
+        assert!(!dir.path().join("new.jpg").exists());
+        // Only thumbnails are touched
+        assert!(dir.path().join("other.txt").exists());
+    }
+}
@@ -1,28 +1,179 @@
 use actix_files::NamedFile;
-use actix_web::{HttpRequest, HttpResponse, Responder, Result, web};
+use actix_web::{
+    HttpRequest, HttpResponse, HttpResponseBuilder,
+    body::BoxBody,
+    http::{StatusCode, header},
+    mime, web,
+};
 use futures_util::StreamExt;
 use path_clean::PathClean; // For path cleaning
-use serde::Serialize; // For Tera context
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize}; // For Tera context
+use std::path::{Path, PathBuf};
 use tokio::fs; // Use tokio's async fs for reading directories // For processing directory stream
 
 // Import shared state structs (adjust path if needed)
+use super::cache::Validators;
+use super::one_time::{self, CompletionBody};
+use super::precompressed;
+use super::preview;
+use super::thumbnails;
 use crate::State;
+use crate::app::errors::AppError;
+use crate::app::upload::quota::{self, UsageSummary};
+use crate::app::{ServerMode, is_reserved};
+use crate::middleware::rate_limit::Download;
+use crate::utils::throttle::ThrottledBody;
 
 // Struct for Tera context when listing directories
 #[derive(Serialize)]
 struct DirContext {
     current_path: String,        // The requested path relative to the base
+    base_url: String,            // Prefix the server is mounted under, empty for the root
     parent_path: Option<String>, // Link to parent dir, if not root
     entries: Vec<DirEntry>,
+    mode: ServerMode,
+    usage: Option<UsageSummary>, // Quota usage of the visitor and this top-level directory
+    sharing: bool,               // Offer the Share action on entries
+    thumbnails: bool,            // Offer the gallery layout
+    previews: bool,              // Offer the Preview action on files
+    readme: Option<preview::Readme>, // Shown below the entries
 }
 
 // Struct for individual directory entries
 #[derive(Serialize)]
 struct DirEntry {
     name: String,
-    url: String, // URL relative to the site root
+    url: String,  // URL relative to the site root
+    path: String, // Path relative to the scope root
     is_dir: bool,
+    is_image: bool, // Shown as a thumbnail in the gallery layout
+}
+
+// Query string of file and directory URLs, any value of a parameter asks for that variant
+#[derive(Deserialize)]
+pub struct PathQuery {
+    thumb: Option<String>,
+    preview: Option<String>,
+    inline: Option<String>,
+    hex: Option<String>,
+    page: Option<u64>, // Page of a hex dump, counted from 1
+}
+
+// What to answer a request for a file with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileVariant {
+    Original,
+    Inline,            // The file, shown by the browser instead of saved
+    Thumbnail,         // Small JPEG of an image
+    Preview,           // HTML page presenting the file
+    Hex { page: u64 }, // Preview page with a hex dump, pages counted from 0
+}
+
+impl PathQuery {
+    pub fn variant(&self) -> FileVariant {
+        if self.thumb.is_some() {
+            FileVariant::Thumbnail
+        } else if self.hex.is_some() {
+            FileVariant::Hex {
+                page: self.page.unwrap_or(1).saturating_sub(1),
+            }
+        } else if self.preview.is_some() {
+            FileVariant::Preview
+        } else if self.inline.is_some() {
+            FileVariant::Inline
+        } else {
+            FileVariant::Original
+        }
+    }
+}
+
+// Part of the tree a request may see and where its links point to,
+// the whole root directory or the file or folder of a share link
+pub struct Scope {
+    pub root: PathBuf,       // Canonical path requested paths are resolved against
+    pub link_prefix: String, // URL of the root, entry paths are appended to it
+    pub mode: ServerMode,
+    pub redirect_not_found: bool,
+    pub show_usage: bool,
+    pub sharing: bool,
+    pub single_use: bool, // Files are only served whole, for links that burn after one download
+    pub limited: bool,    // Files are only served whole, for links with a download limit
+    pub variant: FileVariant, // Only used for files, listings look the same for every variant
+}
+
+// Answer a missing path, with a redirect to the root only if asked for
+fn not_found(state: &State, scope: &Scope) -> Result<HttpResponse, AppError> {
+    if scope.redirect_not_found {
+        return Ok(HttpResponse::SeeOther()
+            .append_header(("Location", format!("{}/", state.base_url)))
+            .finish());
+    }
+    Err(AppError::PathNotFound)
+}
+
+// Listings are always revalidated, the validators make that cheap
+fn listing_cache_headers(response: &mut HttpResponseBuilder, validators: &Validators) {
+    response.insert_header(header::ETag(validators.etag.clone()));
+    if let Some(last_modified) = validators.last_modified {
+        response.insert_header(header::LastModified(last_modified));
+    }
+    response.insert_header((header::CACHE_CONTROL, "no-cache"));
+}
+
+fn not_modified_listing(validators: &Validators) -> HttpResponse {
+    let mut response = HttpResponse::NotModified();
+    listing_cache_headers(&mut response, validators);
+    response.finish()
+}
+
+// Thumbnail in place of an image. One-time files and links limited in downloads are
+// never previewed, the thumbnail would give their content away without counting.
+async fn serve_thumbnail(
+    state: &State,
+    req: &HttpRequest,
+    scope: &Scope,
+    path: &Path,
+) -> Result<HttpResponse, AppError> {
+    let Some(thumbnails) = &state.thumbnails else {
+        return Err(AppError::ThumbnailUnavailable);
+    };
+    if !thumbnails::is_image(path)
+        || scope.single_use
+        || scope.limited
+        || state.one_time.key_for(path).is_some()
+    {
+        return Err(AppError::ThumbnailUnavailable);
+    }
+
+    let thumbnail = thumbnails.thumbnail(path).await?;
+    Ok(NamedFile::open_async(&thumbnail)
+        .await?
+        .set_content_type(mime::IMAGE_JPEG)
+        .disable_content_disposition()
+        .into_response(req))
+}
+
+// URL of a path below the root of `scope`, "." being the root itself
+fn scope_url(scope: &Scope, relative_path: &Path) -> String {
+    if relative_path == Path::new(".") {
+        return scope.link_prefix.clone();
+    }
+    format!(
+        "{}/{}",
+        scope.link_prefix,
+        relative_path.to_string_lossy().replace("\\", "/")
+    )
+}
+
+// Mark a file response as a download and apply the bandwidth limit to it
+pub fn throttled_download(state: &State, mut response: HttpResponse) -> HttpResponse {
+    response.extensions_mut().insert(Download);
+
+    if state.download_bandwidth.is_limited() {
+        let throttle = state.download_bandwidth.connection();
+        return response.map_body(|_, body| BoxBody::new(ThrottledBody::new(body, throttle)));
+    }
+    response
 }
 
 // The main handler
@@ -30,48 +181,96 @@ pub async fn display_dir(
     state: web::Data<State>,
     req: HttpRequest,
     tail: web::Path<String>, // Capture the path segment(s) after the base URL
-) -> Result<impl Responder> {
-    // Use impl Responder for flexibility
-    let canonical_base_path: PathBuf = state.base_path.clone();
+    query: web::Query<PathQuery>,
+) -> Result<HttpResponse, AppError> {
+    let scope = Scope {
+        root: state.base_path.clone(),
+        link_prefix: state.base_url.clone(),
+        mode: state.mode,
+        redirect_not_found: state.redirect_not_found,
+        show_usage: true,
+        sharing: state.shares.is_some(),
+        single_use: false,
+        limited: false,
+        variant: query.variant(),
+    };
+    serve_path(&state, &req, &scope, &tail.into_inner()).await
+}
 
-    let requested_path_str = tail.into_inner();
+// Serve a file or directory listing below the root of `scope`
+pub async fn serve_path(
+    state: &State,
+    req: &HttpRequest,
+    scope: &Scope,
+    requested_path_str: &str,
+) -> Result<HttpResponse, AppError> {
+    let canonical_base_path: &Path = &scope.root;
 
     // --- 1. Path Construction and Security Check ---
     let cleaned_relative_path = PathBuf::from(requested_path_str).clean();
 
     log::debug!("cleaned_relative_path: {}", cleaned_relative_path.display());
 
-    // Join the cleaned relative path to the canonical base path
-    let requested_absolute_path = canonical_base_path.join(&cleaned_relative_path);
+    // Join the cleaned relative path to the canonical base path.
+    // The root itself may be a file, nothing can be joined to it.
+    let requested_absolute_path = if cleaned_relative_path == Path::new(".") {
+        canonical_base_path.to_path_buf()
+    } else {
+        canonical_base_path.join(&cleaned_relative_path)
+    };
 
     // Convert to canonical path for security check
     let canonical_requested_path = match requested_absolute_path.canonicalize() {
         Ok(p) => p,
-        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
+        Err(ref e)
+            if matches!(
+                e.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::NotADirectory
+            ) =>
+        {
             // Path doesn't exist, return 404 Not Found
             log::debug!("Path not found: {}", requested_absolute_path.display());
-            return Ok(HttpResponse::SeeOther()
-                .append_header(("Location", "/"))
-                .finish());
+            return not_found(state, scope);
         }
         Err(e) => {
             // Other error (e.g., permission denied) during canonicalization
-            log::error!(
+            log::debug!(
                 "Error canonicalizing requested path {}: {}",
                 requested_absolute_path.display(),
                 e
             );
-            return Ok(HttpResponse::InternalServerError().body("Error accessing path information"));
+            return Err(e.into());
         }
     };
 
     // Check if the canonical path starts with the canonical base path
-    if !canonical_requested_path.starts_with(&canonical_base_path) {
+    if !canonical_requested_path.starts_with(canonical_base_path) {
         log::debug!(
             "Path traversal attempt detected: {}",
             canonical_requested_path.display()
         );
-        return Ok(HttpResponse::Forbidden().body("Forbidden access"));
+        return Err(AppError::PathForbidden);
+    }
+
+    // The metadata directory is not part of the share, nor is the reserved route segment
+    if canonical_requested_path.starts_with(&state.data_dir)
+        || is_reserved(&state.base_path, &requested_absolute_path)
+        || is_reserved(&state.base_path, &canonical_requested_path)
+    {
+        log::debug!(
+            "Access to data directory denied: {}",
+            canonical_requested_path.display()
+        );
+        return Err(AppError::PathForbidden);
+    }
+
+    // Inboxes are write-only for everyone but admins
+    if !state.inboxes.can_view(req, &canonical_requested_path) {
+        log::debug!(
+            "Access to inbox denied: {}",
+            canonical_requested_path.display()
+        );
+        return Err(AppError::InboxForbidden);
     }
 
     // --- 2. Check if Path is File or Directory ---
@@ -83,26 +282,120 @@ pub async fn display_dir(
                 "Metadata check failed (Not Found): {}",
                 canonical_requested_path.display()
             );
-            return Ok(HttpResponse::SeeOther()
-                .append_header(("Location", "/"))
-                .finish());
+            return not_found(state, scope);
         }
         Err(e) => {
-            log::error!(
+            log::debug!(
                 "Failed to get metadata for {}: {}",
                 canonical_requested_path.display(),
                 e
             );
-            return Ok(HttpResponse::InternalServerError().body("Failed to read path metadata"));
+            return Err(e.into());
         }
     };
 
     // --- 3. Serve File if it's a file ---
     if metadata.is_file() {
         log::debug!("Serving file: {}", canonical_requested_path.display());
-        return Ok(NamedFile::open_async(canonical_requested_path)
-            .await?
-            .into_response(&req));
+        match scope.variant {
+            FileVariant::Thumbnail => {
+                return serve_thumbnail(state, req, scope, &canonical_requested_path).await;
+            }
+            FileVariant::Preview | FileVariant::Hex { .. } => {
+                // Previews and hex dumps would give the content of one-time files away without
+                // burning them, and of download-limited links without counting. Hex pages in
+                // particular read any part of the file, so both variants share this guard.
+                if scope.single_use
+                    || scope.limited
+                    || state.one_time.key_for(&canonical_requested_path).is_some()
+                {
+                    return Err(AppError::PreviewUnavailable);
+                }
+                let file_url = scope_url(scope, &cleaned_relative_path);
+                let parent_url = (cleaned_relative_path != Path::new(".")).then(|| {
+                    scope_url(
+                        scope,
+                        cleaned_relative_path.parent().unwrap_or(Path::new("")),
+                    )
+                });
+                return preview::preview_page(
+                    state,
+                    &canonical_requested_path,
+                    metadata.len(),
+                    file_url,
+                    parent_url,
+                    match scope.variant {
+                        FileVariant::Hex { page } => Some(page),
+                        _ => None,
+                    },
+                )
+                .await;
+            }
+            FileVariant::Original | FileVariant::Inline => {}
+        }
+
+        let one_time_key = state.one_time.key_for(&canonical_requested_path);
+        // Range requests would let a counted download be fetched again piece by piece
+        if scope.single_use || scope.limited || one_time_key.is_some() {
+            let mut response = one_time::whole_file_response(&canonical_requested_path).await?;
+            if let Some(key) = one_time_key {
+                // A second download must not start before the first one finished
+                if !state.one_time.claim(&key) {
+                    return Err(AppError::OneTimeInProgress);
+                }
+                let one_time = state.one_time.clone();
+                let quotas = state.quotas.clone();
+                response = response.map_body(|_, body| {
+                    BoxBody::new(CompletionBody::new(body, move |completed| {
+                        // The deleted file no longer counts against the uploader's quota
+                        if one_time.finish(&key, completed)
+                            && let Some(quotas) = quotas
+                        {
+                            quotas.record_delete(&key);
+                        }
+                    }))
+                });
+            }
+            return Ok(throttled_download(state, response));
+        }
+
+        let mut file = NamedFile::open_async(&canonical_requested_path).await?;
+        if scope.variant == FileVariant::Inline {
+            let disposition = file.content_disposition().clone();
+            file = file.set_content_disposition(header::ContentDisposition {
+                disposition: header::DispositionType::Inline,
+                ..disposition
+            });
+        }
+        if state.precompressed
+            && let Some((variant, encoding)) =
+                precompressed::find_variant(req, &canonical_requested_path, &state.base_path).await
+        {
+            log::debug!("Serving precompressed variant: {}", variant.display());
+            // Name, type and disposition still come from the requested file
+            file = NamedFile::from_file(std::fs::File::open(&variant)?, &canonical_requested_path)?
+                .set_content_encoding(encoding);
+        }
+
+        // NamedFile answers conditional and range requests on its own
+        let mut response = file.into_response(req);
+        if state.precompressed {
+            response.headers_mut().append(
+                header::VARY,
+                header::HeaderValue::from_static("accept-encoding"),
+            );
+        }
+        if let Some(cache_control) = &state.cache_control {
+            response
+                .headers_mut()
+                .insert(header::CACHE_CONTROL, cache_control.clone());
+        }
+
+        // A 304 carries no body, so there is no transfer to limit
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(response);
+        }
+        return Ok(throttled_download(state, response));
     }
 
     // Ok(HttpResponse::Ok().finish()) // Placeholder for actual response
@@ -119,12 +412,14 @@ pub async fn display_dir(
                     e
                 );
                 // Could be permission denied
-                return Ok(HttpResponse::Forbidden().body("Cannot read directory listing"));
+                return Err(e.into());
             }
         };
 
         // Use `try_for_each` for cleaner async iteration over directory entries
         let mut dir_stream = tokio_stream::wrappers::ReadDirStream::new(read_dir);
+        // Best README found so far, with its rank
+        let mut readme_path: Option<(usize, PathBuf)> = None;
 
         while let Some(entry_result) = dir_stream.next().await {
             // Handle errors in reading directory entries
@@ -146,20 +441,53 @@ pub async fn display_dir(
                 }
             };
 
+            // Hide the metadata directory and the reserved route segment from the listing
+            if entry_result.path() == state.data_dir
+                || is_reserved(&state.base_path, &entry_result.path())
+            {
+                continue;
+            }
+
             let is_dir = file_type.is_dir();
+            let is_image = !is_dir && thumbnails::is_image(Path::new(&file_name));
+
+            // Have the thumbnails ready by the time the gallery asks for them
+            if let Some(thumbnails) = &state.thumbnails
+                && is_image
+                && file_type.is_file()
+                && !scope.single_use
+                && !scope.limited
+                && state.one_time.key_for(&entry_result.path()).is_none()
+            {
+                thumbnails.warm(&entry_result.path());
+            }
+
+            // Rendering a one-time file would give it away without burning it, and
+            // a file behind a download limit without counting
+            if let Some(rank) = preview::readme_rank(&file_name)
+                && file_type.is_file()
+                && !scope.single_use
+                && !scope.limited
+                && readme_path.as_ref().is_none_or(|(best, _)| rank < *best)
+                && state.one_time.key_for(&entry_result.path()).is_none()
+            {
+                readme_path = Some((rank, entry_result.path()));
+            }
 
             // Construct the URL relative to the web server root
             // Combine the *original* cleaned relative path with the entry name
-            let entry_relative_path = cleaned_relative_path.join(&file_name);
-            let url = format!(
-                "/{}",
-                entry_relative_path.to_string_lossy().replace("\\", "/")
-            ); // Ensure forward slashes
+            let entry_relative_path = cleaned_relative_path
+                .join(&file_name)
+                .to_string_lossy()
+                .replace("\\", "/"); // Ensure forward slashes
+            let url = format!("{}/{}", scope.link_prefix, entry_relative_path);
 
             entries.push(DirEntry {
                 name: file_name,
                 url,
+                path: entry_relative_path,
                 is_dir,
+                is_image,
             });
         }
 
@@ -172,6 +500,21 @@ pub async fn display_dir(
             }
         });
 
+        let readme_modified = match &readme_path {
+            Some((_, path)) => fs::metadata(path)
+                .await
+                .and_then(|metadata| metadata.modified())
+                .ok(),
+            None => None,
+        };
+        let readme = match readme_path {
+            Some((_, path)) => web::block(move || preview::load_readme(&path))
+                .await
+                .ok()
+                .flatten(),
+            None => None,
+        };
+
         // --- 5. Prepare Tera Context ---
         let current_display_path = cleaned_relative_path.to_string_lossy().replace("\\", "/");
 
@@ -181,50 +524,335 @@ pub async fn display_dir(
         {
             cleaned_relative_path
                 .parent()
-                .map(|p| format!("/{}", p.to_string_lossy().replace("\\", "/")))
-                // Handle case where parent is the root ("/")
-                .map(|p| if p == "/" { "/".to_string() } else { p })
+                .map(|p| {
+                    format!(
+                        "{}/{}",
+                        scope.link_prefix,
+                        p.to_string_lossy().replace("\\", "/")
+                    )
+                })
                 // If parent resolves to empty (e.g. was just "./") go to root
                 .filter(|p| !p.is_empty() || cleaned_relative_path.components().count() > 1)
-                .or(Some("/".to_string()))
+                .or(Some(format!("{}/", scope.link_prefix)))
         } else {
             None
         };
 
         let context = DirContext {
             current_path: current_display_path,
+            base_url: state.base_url.clone(),
             parent_path,
             entries,
+            mode: scope.mode,
+            sharing: scope.sharing,
+            thumbnails: state.thumbnails.is_some() && !scope.single_use && !scope.limited,
+            previews: !scope.single_use && !scope.limited,
+            readme,
+            usage: state
+                .quotas
+                .as_ref()
+                .filter(|_| scope.show_usage)
+                .map(|quotas| quotas.summary(&quota::user_key(req), &cleaned_relative_path)),
         };
 
+        // Mirroring clients can skip listings that did not change. Editing the README leaves
+        // the directory mtime alone, and quota usage has no time at all, so a listing
+        // showing usage only gets the ETag.
+        let modified = metadata
+            .modified()
+            .ok()
+            .map(|dir| readme_modified.map_or(dir, |readme| dir.max(readme)))
+            .filter(|_| context.usage.is_none());
+        let validators = Validators::new(
+            modified,
+            serde_json::to_string(&context).unwrap_or_default(),
+        );
+        if validators.is_not_modified(req) {
+            return Ok(not_modified_listing(&validators));
+        }
+
         // --- 6. Render Template ---
         let tera = &state.tera;
-        let rendered_body = match tera.render(
+        let rendered_body = tera.render(
             "home.html",
             &tera::Context::from_serialize(&context).unwrap(),
-        ) {
-            Ok(html) => html,
-            Err(e) => {
-                log::error!(
-                    "Tera rendering error for {}: {}",
-                    canonical_requested_path.display(),
-                    e
-                );
-                return Ok(
-                    HttpResponse::InternalServerError().body("Failed to render directory listing")
-                );
-            }
-        };
+        )?;
 
-        Ok(HttpResponse::Ok()
-            .content_type("text/html; charset=utf-8")
-            .body(rendered_body))
+        let mut response = HttpResponse::Ok();
+        response.content_type("text/html; charset=utf-8");
+        listing_cache_headers(&mut response, &validators);
+        Ok(response.body(rendered_body))
     } else {
         // Path exists but is not a file or directory (e.g., symlink, socket - handle as needed)
         log::debug!(
             "Path is neither file nor directory: {}",
             canonical_requested_path.display()
         );
-        Ok(HttpResponse::NotFound().finish()) // Or maybe Forbidden?
+        Err(AppError::NotAFileOrDirectory)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::download::{thumbnails::Thumbnails, urls};
+    use crate::app::upload::quota::{QuotaLimit, Quotas};
+    use actix_web::{
+        App,
+        dev::{Service, ServiceResponse},
+        test::{self, TestRequest, call_service, init_service},
+    };
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    async fn app(
+        state: State,
+    ) -> impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>
+    {
+        init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .configure(urls::register_urls),
+        )
+        .await
+    }
+
+    async fn get(
+        app: &impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>,
+        uri: &str,
+    ) -> ServiceResponse {
+        call_service(app, TestRequest::get().uri(uri).to_request()).await
+    }
+
+    #[actix_web::test]
+    async fn serves_thumbnails_of_images() {
+        let dir = TempDir::new().unwrap();
+        image::RgbImage::new(600, 300)
+            .save(dir.path().join("photo.png"))
+            .unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "notes").unwrap();
+        std::fs::copy(dir.path().join("photo.png"), dir.path().join("once.png")).unwrap();
+
+        let mut state = State::for_tests(dir.path());
+        state.thumbnails = Some(Arc::new(
+            Thumbnails::start(state.data_dir.join("thumbnails")).unwrap(),
+        ));
+        state.one_time.set(&state.base_path.join("once.png"), true);
+        let app = app(state).await;
+
+        let res = get(&app, "/photo.png?thumb").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE).unwrap(),
+            "image/jpeg"
+        );
+
+        // Only images get one, and one-time files are never given away
+        for uri in ["/notes.txt?thumb", "/once.png?thumb"] {
+            assert_eq!(
+                get(&app, uri).await.status(),
+                StatusCode::NOT_FOUND,
+                "{}",
+                uri
+            );
+        }
+        assert_eq!(get(&app, "/notes.txt").await.status(), StatusCode::OK);
+
+        let app = self::app(State::for_tests(dir.path())).await;
+        assert_eq!(
+            get(&app, "/photo.png?thumb").await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[actix_web::test]
+    async fn serves_previews_and_inline_files() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir(dir.path().join("docs")).unwrap();
+        std::fs::write(dir.path().join("docs/notes.md"), "# Notes").unwrap();
+        std::fs::write(dir.path().join("docs/once.md"), "# Secret").unwrap();
+
+        let state = State::for_tests(dir.path());
+        state
+            .one_time
+            .set(&state.base_path.join("docs/once.md"), true);
+        let app = app(state).await;
+
+        let res = get(&app, "/docs/notes.md?preview").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let page = String::from_utf8(test::read_body(res).await.to_vec()).unwrap();
+        assert!(page.contains("<h1>Notes</h1>"));
+        assert!(page.contains("notes.md"));
+
+        let res = get(&app, "/docs/notes.md?inline").await;
+        let disposition = res.headers().get(header::CONTENT_DISPOSITION).unwrap();
+        assert!(disposition.to_str().unwrap().starts_with("inline"));
+
+        // The page would give the file away without burning it
+        assert_eq!(
+            get(&app, "/docs/once.md?preview").await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[actix_web::test]
+    async fn shows_the_readme_below_listings() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("readme.txt"), "plain readme").unwrap();
+        std::fs::write(dir.path().join("README.md"), "# Markdown readme").unwrap();
+        std::fs::create_dir(dir.path().join("once")).unwrap();
+        std::fs::write(dir.path().join("once/README.md"), "# Secret").unwrap();
+
+        let state = State::for_tests(dir.path());
+        state
+            .one_time
+            .set(&state.base_path.join("once/README.md"), true);
+        let app = app(state).await;
+
+        let res = get(&app, "/").await;
+        let page = String::from_utf8(test::read_body(res).await.to_vec()).unwrap();
+        assert!(page.contains("<h1>Markdown readme</h1>"));
+        assert!(!page.contains("plain readme"));
+
+        let res = get(&app, "/once/").await;
+        let page = String::from_utf8(test::read_body(res).await.to_vec()).unwrap();
+        assert!(!page.contains("Secret"));
+    }
+
+    #[actix_web::test]
+    async fn serves_hex_dumps() {
+        let dir = TempDir::new().unwrap();
+        let mut content = b"\x89PNG\r\n\x1a\n".to_vec();
+        content.resize(5000, 0);
+        std::fs::write(dir.path().join("upload.bin"), &content).unwrap();
+        let app = app(State::for_tests(dir.path())).await;
+
+        let res = get(&app, "/upload.bin?hex&page=2").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let page = String::from_utf8(test::read_body(res).await.to_vec()).unwrap();
+        assert!(page.contains("00001000"));
+        assert!(!page.contains("00000ff0"));
+        assert!(page.contains("PNG image"));
+        assert!(page.contains("upload.png"));
+    }
+
+    #[actix_web::test]
+    async fn hides_the_reserved_folder() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir(dir.path().join(".holonet")).unwrap();
+        std::fs::write(dir.path().join(".holonet/secret"), "secret").unwrap();
+        std::fs::write(dir.path().join("visible.txt"), "visible").unwrap();
+
+        let mut state = State::for_tests(dir.path());
+        state.data_dir = state.base_path.join("data");
+        let app = app(state).await;
+
+        for uri in ["/.holonet", "/.holonet/secret"] {
+            assert_eq!(
+                get(&app, uri).await.status(),
+                StatusCode::FORBIDDEN,
+                "{}",
+                uri
+            );
+        }
+
+        let res = get(&app, "/").await;
+        let page = String::from_utf8(test::read_body(res).await.to_vec()).unwrap();
+        assert!(page.contains("visible.txt"));
+        assert!(!page.contains(".holonet"));
+    }
+
+    #[actix_web::test]
+    async fn listings_change_with_their_readme() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("README.md"), "# First").unwrap();
+        let app = app(State::for_tests(dir.path())).await;
+
+        let res = get(&app, "/").await;
+        let etag = res.headers().get(header::ETAG).unwrap().clone();
+        let last_modified = res.headers().get(header::LAST_MODIFIED).unwrap().clone();
+
+        let req = TestRequest::get()
+            .uri("/")
+            .insert_header((header::IF_NONE_MATCH, etag.clone()))
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::NOT_MODIFIED
+        );
+
+        // Rewriting the README in place leaves the directory mtime alone
+        let readme = std::fs::File::options()
+            .write(true)
+            .open(dir.path().join("README.md"))
+            .unwrap();
+        readme
+            .set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(60))
+            .unwrap();
+        let req = TestRequest::get()
+            .uri("/")
+            .insert_header((header::IF_NONE_MATCH, etag.clone()))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_ne!(res.headers().get(header::ETAG).unwrap(), etag);
+        assert_ne!(
+            res.headers().get(header::LAST_MODIFIED).unwrap(),
+            last_modified
+        );
+    }
+
+    #[actix_web::test]
+    async fn listings_with_usage_only_get_an_etag() {
+        let dir = TempDir::new().unwrap();
+        let mut state = State::for_tests(dir.path());
+        let limit = QuotaLimit {
+            max_bytes: Some(1024),
+            max_files: None,
+        };
+        state.quotas = Some(Arc::new(
+            Quotas::load(
+                state.base_path.clone(),
+                state.data_dir.join("usage.json"),
+                limit,
+                limit,
+            )
+            .unwrap(),
+        ));
+        let app = app(state).await;
+
+        let res = get(&app, "/").await;
+        assert!(res.headers().contains_key(header::ETAG));
+        assert!(!res.headers().contains_key(header::LAST_MODIFIED));
+    }
+
+    #[actix_web::test]
+    async fn burned_one_time_files_leave_the_quota() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("once.txt"), "secret").unwrap();
+
+        let mut state = State::for_tests(dir.path());
+        let limit = QuotaLimit {
+            max_bytes: Some(1024),
+            max_files: None,
+        };
+        let quotas = Arc::new(
+            Quotas::load(
+                state.base_path.clone(),
+                state.data_dir.join("usage.json"),
+                limit,
+                limit,
+            )
+            .unwrap(),
+        );
+        quotas.record_upload("alice", "once.txt", 6).unwrap();
+        state.quotas = Some(quotas.clone());
+        state.one_time.set(&state.base_path.join("once.txt"), true);
+        let app = app(state).await;
+
+        let res = get(&app, "/once.txt").await;
+        assert_eq!(test::read_body(res).await, "secret");
+        assert!(!dir.path().join("once.txt").exists());
+        assert_eq!(quotas.summary("alice", Path::new("")).user.bytes, 0);
     }
 }
@@ -0,0 +1,347 @@
+use actix_web::{
+    HttpResponse, ResponseError,
+    http::{StatusCode, header},
+};
+use std::fmt;
+
+use super::share::token::MAX_LIFETIME_SECS;
+use super::upload::quota::QuotaError;
+
+// Code and message of an error response, picked up by the error page middleware
+#[derive(Clone, Debug)]
+pub struct ErrorInfo {
+    pub code: &'static str,
+    pub message: String,
+}
+
+// Errors returned by the HTTP handlers and middleware
+#[derive(Debug)]
+pub enum AppError {
+    // Path resolution
+    PathNotFound,
+    PathForbidden,
+    InvalidTargetDir(&'static str),
+    NotAFileOrDirectory,
+    ThumbnailUnavailable,
+    PreviewUnavailable,
+    // Request content
+    InvalidHeader(String),
+    InvalidFileName(String),
+    EmptyUpload,
+    PasteNotText,
+    UnsupportedEncoding(String),
+    PayloadError(String),
+    // Archive uploads to be extracted
+    ArchiveUnsupported,
+    ArchiveInvalid(String),
+    ArchiveEntryRejected(String),
+    ExtractTargetExists,
+    // Server side failures
+    Io(std::io::Error),
+    Template(tera::Error),
+    // Access control and share links
+    AddressDenied,
+    ShareInvalid,
+    ShareExpired,
+    ShareDownloadLimitReached,
+    SharePasswordRequired,
+    SharePasswordInvalid,
+    ShareUsed,
+    ShareLifetimeInvalid,
+    ShareDownloadLimitInvalid,
+    InboxTokenInvalid,
+    InboxForbidden,
+    // One-time file or link that is being downloaded right now
+    OneTimeInProgress,
+    // Limits
+    PayloadTooLarge,
+    DecompressionLimitExceeded,
+    ExtractLimitExceeded,
+    InsufficientStorage,
+    QuotaExceeded(QuotaError),
+    // Remaining user or directory quota is smaller than the upload
+    QuotaAllowanceExceeded,
+    RateLimited { retry_after: u64 },
+}
+
+impl AppError {
+    // Stable identifier for clients, never change existing values
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::PathNotFound => "path_not_found",
+            AppError::PathForbidden => "path_forbidden",
+            AppError::InvalidTargetDir(_) => "invalid_target_dir",
+            AppError::NotAFileOrDirectory => "not_a_file_or_directory",
+            AppError::ThumbnailUnavailable => "thumbnail_unavailable",
+            AppError::PreviewUnavailable => "preview_unavailable",
+            AppError::InvalidHeader(_) => "invalid_header",
+            AppError::InvalidFileName(_) => "invalid_file_name",
+            AppError::EmptyUpload => "empty_upload",
+            AppError::PasteNotText => "paste_not_text",
+            AppError::UnsupportedEncoding(_) => "unsupported_encoding",
+            AppError::PayloadError(_) => "payload_error",
+            AppError::ArchiveUnsupported => "archive_unsupported",
+            AppError::ArchiveInvalid(_) => "archive_invalid",
+            AppError::ArchiveEntryRejected(_) => "archive_entry_rejected",
+            AppError::ExtractTargetExists => "extract_target_exists",
+            AppError::Io(_) => "io_error",
+            AppError::Template(_) => "template_error",
+            AppError::AddressDenied => "address_denied",
+            AppError::ShareInvalid => "share_invalid",
+            AppError::ShareExpired => "share_expired",
+            AppError::ShareDownloadLimitReached => "share_download_limit_reached",
+            AppError::SharePasswordRequired => "share_password_required",
+            AppError::SharePasswordInvalid => "share_password_invalid",
+            AppError::ShareUsed => "share_used",
+            AppError::ShareLifetimeInvalid => "share_lifetime_invalid",
+            AppError::ShareDownloadLimitInvalid => "share_download_limit_invalid",
+            AppError::InboxTokenInvalid => "inbox_token_invalid",
+            AppError::InboxForbidden => "inbox_forbidden",
+            AppError::OneTimeInProgress => "one_time_in_progress",
+            AppError::PayloadTooLarge => "payload_too_large",
+            AppError::DecompressionLimitExceeded => "decompression_limit_exceeded",
+            AppError::ExtractLimitExceeded => "extract_limit_exceeded",
+            AppError::InsufficientStorage => "insufficient_storage",
+            AppError::QuotaExceeded(QuotaError::UserBytes) => "user_quota_exceeded",
+            AppError::QuotaExceeded(QuotaError::UserFiles) => "user_file_quota_exceeded",
+            AppError::QuotaExceeded(QuotaError::DirBytes) => "dir_quota_exceeded",
+            AppError::QuotaExceeded(QuotaError::DirFiles) => "dir_file_quota_exceeded",
+            AppError::QuotaAllowanceExceeded => "quota_exceeded",
+            AppError::RateLimited { .. } => "rate_limited",
+        }
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(err: std::io::Error) -> Self {
+        match err.kind() {
+            std::io::ErrorKind::NotFound => AppError::PathNotFound,
+            std::io::ErrorKind::PermissionDenied => AppError::PathForbidden,
+            std::io::ErrorKind::StorageFull => AppError::InsufficientStorage,
+            _ => AppError::Io(err),
+        }
+    }
+}
+
+impl From<tera::Error> for AppError {
+    fn from(err: tera::Error) -> Self {
+        AppError::Template(err)
+    }
+}
+
+impl From<QuotaError> for AppError {
+    fn from(err: QuotaError) -> Self {
+        AppError::QuotaExceeded(err)
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AppError::PathNotFound => write!(f, "Path not found"),
+            AppError::PathForbidden => write!(f, "Forbidden access"),
+            AppError::InvalidTargetDir(msg) => write!(f, "{}", msg),
+            AppError::NotAFileOrDirectory => write!(f, "Not a file or directory"),
+            AppError::ThumbnailUnavailable => write!(f, "No thumbnail available for this file"),
+            AppError::PreviewUnavailable => write!(f, "No preview available for this file"),
+            AppError::InvalidHeader(msg) => write!(f, "Invalid header: {}", msg),
+            AppError::InvalidFileName(msg) => write!(f, "Invalid file name: {}", msg),
+            AppError::EmptyUpload => write!(f, "Empty file upload rejected"),
+            AppError::PasteNotText => write!(f, "Paste must be UTF-8 text"),
+            AppError::UnsupportedEncoding(encoding) => {
+                write!(f, "Unsupported Content-Encoding: {}", encoding)
+            }
+            AppError::PayloadError(msg) => write!(f, "Failed to read request body: {}", msg),
+            AppError::ArchiveUnsupported => {
+                write!(f, "Upload is not a zip, tar, tar.gz or tar.zst archive")
+            }
+            AppError::ArchiveInvalid(msg) => write!(f, "Archive could not be read: {}", msg),
+            AppError::ArchiveEntryRejected(name) => write!(
+                f,
+                "Archive entry {} is a link or has a path outside the target folder",
+                name
+            ),
+            AppError::ExtractTargetExists => {
+                write!(f, "Folder for the extracted archive already exists")
+            }
+            // Server side details stay in the log
+            AppError::Io(_) => write!(f, "Failed to access the file system"),
+            AppError::Template(_) => write!(f, "Failed to render page"),
+            AppError::AddressDenied => write!(f, "Forbidden"),
+            AppError::ShareInvalid => write!(f, "Share link is invalid"),
+            AppError::ShareExpired => write!(f, "Share link has expired"),
+            AppError::ShareDownloadLimitReached => {
+                write!(f, "Share link has reached its download limit")
+            }
+            AppError::SharePasswordRequired => write!(f, "Share link requires a password"),
+            AppError::SharePasswordInvalid => write!(f, "Wrong share link password"),
+            AppError::ShareUsed => write!(f, "Share link has already been used"),
+            AppError::ShareLifetimeInvalid => write!(
+                f,
+                "Share link lifetime must be between 1 second and {} days",
+                MAX_LIFETIME_SECS / (24 * 60 * 60)
+            ),
+            AppError::ShareDownloadLimitInvalid => {
+                write!(f, "Share link download limit must be at least 1")
+            }
+            AppError::InboxTokenInvalid => write!(f, "Missing or wrong inbox token"),
+            AppError::InboxForbidden => write!(f, "Inbox contents are only visible to admins"),
+            AppError::OneTimeInProgress => write!(f, "One-time download already in progress"),
+            AppError::PayloadTooLarge => write!(f, "Upload exceeds maximum allowed size"),
+            AppError::DecompressionLimitExceeded => {
+                write!(f, "Decompressed upload exceeds the allowed size or ratio")
+            }
+            AppError::ExtractLimitExceeded => {
+                write!(
+                    f,
+                    "Archive exceeds the allowed extracted size or entry count"
+                )
+            }
+            AppError::InsufficientStorage => write!(f, "Not enough free space on server"),
+            AppError::QuotaExceeded(err) => write!(f, "{}", err),
+            AppError::QuotaAllowanceExceeded => write!(f, "Upload would exceed storage quota"),
+            AppError::RateLimited { .. } => write!(f, "Too many requests"),
+        }
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::PathNotFound
+            | AppError::NotAFileOrDirectory
+            | AppError::ThumbnailUnavailable
+            | AppError::PreviewUnavailable
+            | AppError::ShareInvalid => StatusCode::NOT_FOUND,
+            AppError::ShareExpired | AppError::ShareDownloadLimitReached | AppError::ShareUsed => {
+                StatusCode::GONE
+            }
+            AppError::OneTimeInProgress | AppError::ExtractTargetExists => StatusCode::CONFLICT,
+            AppError::SharePasswordRequired | AppError::SharePasswordInvalid => {
+                StatusCode::UNAUTHORIZED
+            }
+            AppError::PathForbidden
+            | AppError::AddressDenied
+            | AppError::InboxTokenInvalid
+            | AppError::InboxForbidden => StatusCode::FORBIDDEN,
+            AppError::InvalidTargetDir(_)
+            | AppError::InvalidHeader(_)
+            | AppError::InvalidFileName(_)
+            | AppError::EmptyUpload
+            | AppError::PasteNotText
+            | AppError::ShareLifetimeInvalid
+            | AppError::ShareDownloadLimitInvalid
+            | AppError::PayloadError(_)
+            | AppError::ArchiveInvalid(_)
+            | AppError::ArchiveEntryRejected(_) => StatusCode::BAD_REQUEST,
+            AppError::Io(_) | AppError::Template(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::PayloadTooLarge
+            | AppError::DecompressionLimitExceeded
+            | AppError::ExtractLimitExceeded => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::UnsupportedEncoding(_) | AppError::ArchiveUnsupported => {
+                StatusCode::UNSUPPORTED_MEDIA_TYPE
+            }
+            AppError::InsufficientStorage
+            | AppError::QuotaExceeded(_)
+            | AppError::QuotaAllowanceExceeded => StatusCode::INSUFFICIENT_STORAGE,
+            AppError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            AppError::Io(err) => log::error!("IO error: {}", err),
+            AppError::Template(err) => log::error!("Tera rendering error: {}", err),
+            _ => {}
+        }
+
+        let mut response = HttpResponse::build(self.status_code());
+        if let AppError::RateLimited { retry_after } = self {
+            response.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+        }
+
+        let mut response = response.json(serde_json::json!({
+            "status": self.status_code().as_u16(),
+            "code": self.code(),
+            "message": self.to_string(),
+        }));
+        response.extensions_mut().insert(ErrorInfo {
+            code: self.code(),
+            message: self.to_string(),
+        });
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_errors_to_status_and_code() {
+        let cases = [
+            (AppError::PathNotFound, 404, "path_not_found"),
+            (AppError::PathForbidden, 403, "path_forbidden"),
+            (AppError::AddressDenied, 403, "address_denied"),
+            (AppError::InvalidHeader("X".into()), 400, "invalid_header"),
+            (AppError::EmptyUpload, 400, "empty_upload"),
+            (AppError::PayloadTooLarge, 413, "payload_too_large"),
+            (AppError::InsufficientStorage, 507, "insufficient_storage"),
+            (
+                AppError::QuotaExceeded(QuotaError::DirFiles),
+                507,
+                "dir_file_quota_exceeded",
+            ),
+            (AppError::QuotaAllowanceExceeded, 507, "quota_exceeded"),
+            (
+                AppError::RateLimited { retry_after: 3 },
+                429,
+                "rate_limited",
+            ),
+            (AppError::Io(std::io::Error::other("disk")), 500, "io_error"),
+        ];
+        for (err, status, code) in cases {
+            assert_eq!(err.status_code().as_u16(), status, "{:?}", err);
+            assert_eq!(err.code(), code, "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn maps_io_errors_by_kind() {
+        let cases = [
+            (std::io::ErrorKind::NotFound, "path_not_found"),
+            (std::io::ErrorKind::PermissionDenied, "path_forbidden"),
+            (std::io::ErrorKind::StorageFull, "insufficient_storage"),
+            (std::io::ErrorKind::InvalidData, "io_error"),
+        ];
+        for (kind, code) in cases {
+            assert_eq!(AppError::from(std::io::Error::from(kind)).code(), code);
+        }
+    }
+
+    #[actix_web::test]
+    async fn responds_with_json() {
+        let response = AppError::RateLimited { retry_after: 7 }.error_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "7");
+        assert_eq!(
+            response.extensions().get::<ErrorInfo>().unwrap().code,
+            "rate_limited"
+        );
+
+        // Server side details are not given away
+        let response = AppError::Io(std::io::Error::other("/secret/path")).error_response();
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "status": 500,
+                "code": "io_error",
+                "message": "Failed to access the file system",
+            })
+        );
+    }
+}
@@ -1,7 +1,126 @@
-pub mod upload;
+use serde::Serialize;
+use std::path::Path;
+
 pub mod download;
+pub mod errors;
+pub mod paste;
+pub mod share;
+pub mod upload;
+
+// Which parts of the server are exposed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerMode {
+    Normal,
+    ReadOnly,   // Listing and downloads only, no /upload route
+    UploadOnly, // Upload page only, no listing or downloads
+}
+
+// First path segment of the server's own pages, e.g. share links. The same name in the
+// root directory is never served, so these routes cannot hide a file or folder.
+pub const RESERVED_SEGMENT: &str = ".holonet";
+
+// Whether `path` is the reserved folder in the root directory or below it
+pub fn is_reserved(base_path: &Path, path: &Path) -> bool {
+    path.starts_with(base_path.join(RESERVED_SEGMENT))
+}
+
+pub fn register_urls(cfg: &mut actix_web::web::ServiceConfig, mode: ServerMode, shares: bool) {
+    // Share links come first, the download routes match every path
+    if shares {
+        share::urls::register_urls(cfg);
+    }
+
+    match mode {
+        ServerMode::Normal => {
+            upload::urls::register_urls(cfg);
+            paste::urls::register_urls(cfg);
+            paste::urls::register_view_urls(cfg);
+            share::urls::register_create_urls(cfg);
+            download::urls::register_urls(cfg);
+        }
+        ServerMode::ReadOnly => {
+            paste::urls::register_view_urls(cfg);
+            share::urls::register_create_urls(cfg);
+            download::urls::register_urls(cfg);
+        }
+        ServerMode::UploadOnly => {
+            upload::urls::register_urls(cfg);
+            upload::urls::register_page_urls(cfg);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{App, http::StatusCode, test};
+
+    // Whether the request reaches a handler. Without app data the handlers fail,
+    // but with a server error rather than the 404 of a missing route.
+    async fn is_routed(mode: ServerMode, shares: bool, req: test::TestRequest) -> bool {
+        let app =
+            test::init_service(App::new().configure(|cfg| register_urls(cfg, mode, shares))).await;
+        test::call_service(&app, req.to_request()).await.status() != StatusCode::NOT_FOUND
+    }
+
+    #[actix_web::test]
+    async fn registers_routes_for_mode() {
+        let cases = [
+            (ServerMode::Normal, "/upload", true, true),
+            (ServerMode::Normal, "/docs/report.pdf", false, true),
+            (ServerMode::Normal, "/paste", true, true),
+            (ServerMode::Normal, "/.holonet/p/abc", false, true),
+            // Without the upload route this is just a path in the listing
+            (ServerMode::ReadOnly, "/upload", false, true),
+            (ServerMode::ReadOnly, "/docs/report.pdf", false, true),
+            (ServerMode::ReadOnly, "/paste", false, true),
+            (ServerMode::ReadOnly, "/.holonet/p/abc", false, true),
+            (ServerMode::UploadOnly, "/upload", true, false),
+            (ServerMode::UploadOnly, "/", false, true),
+            (ServerMode::UploadOnly, "/docs/report.pdf", false, false),
+            (ServerMode::UploadOnly, "/paste", false, false),
+            (ServerMode::UploadOnly, "/.holonet/p/abc", false, false),
+        ];
+
+        for (mode, path, post, get) in cases {
+            assert_eq!(
+                is_routed(mode, false, test::TestRequest::post().uri(path)).await,
+                post,
+                "{:?} POST {}",
+                mode,
+                path
+            );
+            assert_eq!(
+                is_routed(mode, false, test::TestRequest::get().uri(path)).await,
+                get,
+                "{:?} GET {}",
+                mode,
+                path
+            );
+        }
+    }
 
-pub fn register_urls(cfg: &mut actix_web::web::ServiceConfig) {
-    upload::urls::register_urls(cfg);
-    download::urls::register_urls(cfg);
+    #[actix_web::test]
+    async fn registers_share_links_only_when_enabled() {
+        for mode in [
+            ServerMode::Normal,
+            ServerMode::ReadOnly,
+            ServerMode::UploadOnly,
+        ] {
+            for path in ["/.holonet/s/abc", "/.holonet/s/abc/docs/a.txt"] {
+                let req = || test::TestRequest::get().uri(path);
+                assert!(is_routed(mode, true, req()).await, "{:?} {}", mode, path);
+                // Without shares the path is left to the listing, which never serves it
+                let routed = is_routed(mode, false, req()).await;
+                assert_eq!(
+                    routed,
+                    mode != ServerMode::UploadOnly,
+                    "{:?} {}",
+                    mode,
+                    path
+                );
+            }
+        }
+    }
 }
@@ -0,0 +1,2 @@
+pub mod urls;
+pub mod views;
@@ -0,0 +1,22 @@
+use actix_web::web;
+
+use super::views;
+use crate::app::RESERVED_SEGMENT;
+
+// Creating pastes is an upload, it is not offered in read-only mode. Nor in
+// upload-only mode, the paste could never be viewed there.
+pub fn register_urls(cfg: &mut web::ServiceConfig) {
+    cfg.route("/paste", web::post().to(views::create_paste));
+}
+
+// Viewing pastes is a download, it is not offered in upload-only mode.
+// Registered ahead of the download routes, which match every path,
+// below the reserved segment so they never shadow a folder.
+pub fn register_view_urls(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope(&format!("/{}/p", RESERVED_SEGMENT))
+            .route("/{id}", web::get().to(views::view_paste))
+            .route("/{id}/raw", web::get().to(views::raw_paste))
+            .route("/{id}/download", web::get().to(views::download_paste)),
+    );
+}
@@ -0,0 +1,335 @@
+use actix_files::NamedFile;
+use actix_web::{
+    HttpRequest, HttpResponse,
+    http::header::{self, ContentDisposition, DispositionParam, DispositionType},
+    mime, web,
+};
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use futures::StreamExt;
+use serde::Serialize;
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+
+use crate::app::RESERVED_SEGMENT;
+use crate::app::download::views::throttled_download;
+use crate::app::errors::AppError;
+use crate::app::upload::quota;
+use crate::utils::proxy::{client_host, client_ip_display, client_scheme};
+use crate::{PASTE_TEMPLATE_NAME, State};
+
+// Random bytes in a paste ID, 8 characters once encoded
+const ID_BYTES: usize = 6;
+// New IDs drawn before giving up on collisions
+const ID_ATTEMPTS: usize = 5;
+
+// Struct for Tera context of the paste page
+#[derive(Serialize)]
+struct PasteContext {
+    base_url: String,
+    link: String, // URL path of the paste, for the raw and download links
+    id: String,
+    lines: Vec<String>,
+    size: u64,
+}
+
+// URL path of a paste below the base URL
+fn paste_link(base_url: &str, id: &str) -> String {
+    format!("{}/{}/p/{}", base_url, RESERVED_SEGMENT, id)
+}
+
+// File of a paste, IDs are limited to the characters they are generated from
+fn paste_path(state: &State, id: &str) -> Result<PathBuf, AppError> {
+    if id.is_empty()
+        || id.len() > 32
+        || !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(AppError::PathNotFound);
+    }
+    Ok(state.paste_dir.join(format!("{}.txt", id)))
+}
+
+// Store the request body as a new paste and answer with its URL
+pub async fn create_paste(
+    mut payload: web::Payload,
+    state: web::Data<State>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    let max_size = state
+        .upload_limits
+        .max_size_for(&state.paste_dir)
+        .map_or(state.max_paste_size, |max| max.min(state.max_paste_size));
+
+    let mut text = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|e| AppError::PayloadError(e.to_string()))?;
+        if (text.len() + chunk.len()) as u64 > max_size {
+            log::debug!("Paste rejected: Exceeds limit of {} bytes", max_size);
+            return Err(AppError::PayloadTooLarge);
+        }
+        text.extend_from_slice(&chunk);
+    }
+
+    if text.is_empty() {
+        return Err(AppError::EmptyUpload);
+    }
+    if std::str::from_utf8(&text).is_err() {
+        return Err(AppError::PasteNotText);
+    }
+
+    tokio::fs::create_dir_all(&state.paste_dir).await?;
+    if !state
+        .upload_limits
+        .has_free_space(&state.paste_dir, text.len() as u64)
+    {
+        log::warn!(
+            "Paste rejected: Not enough free space in {}",
+            state.paste_dir.display()
+        );
+        return Err(AppError::InsufficientStorage);
+    }
+
+    // Pastes count against the quotas like any other upload
+    let user = quota::user_key(&req);
+    let quota_key = |path: &PathBuf| {
+        state
+            .quotas
+            .as_ref()
+            .and_then(|quotas| quotas.relative_key(path))
+    };
+
+    let mut created = None;
+    for _ in 0..ID_ATTEMPTS {
+        let mut id = [0u8; ID_BYTES];
+        getrandom::fill(&mut id).map_err(std::io::Error::other)?;
+        let id = URL_SAFE_NO_PAD.encode(id);
+        let path = paste_path(&state, &id)?;
+
+        if let (Some(quotas), Some(key)) = (&state.quotas, quota_key(&path))
+            && let Some(allowance) = quotas.allowance(&user, &key)?
+            && text.len() as u64 > allowance
+        {
+            log::debug!("Paste rejected for {}: Exceeds remaining quota", user);
+            return Err(AppError::QuotaAllowanceExceeded);
+        }
+
+        // A new ID never replaces an existing paste
+        match tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await
+        {
+            Ok(file) => {
+                created = Some((id, path, file));
+                break;
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let Some((id, path, mut file)) = created else {
+        log::error!("Could not find a free paste ID");
+        return Err(AppError::Io(std::io::Error::other("no free paste ID")));
+    };
+
+    if let Err(e) = file.write_all(&text).await {
+        let _ = tokio::fs::remove_file(&path).await;
+        return Err(e.into());
+    }
+    file.flush().await?;
+
+    if let (Some(quotas), Some(key)) = (&state.quotas, quota_key(&path))
+        && let Err(e) = quotas.record_upload(&user, &key, text.len() as u64)
+    {
+        log::debug!("Paste rejected for {}: {}", user, e);
+        let _ = tokio::fs::remove_file(&path).await;
+        return Err(e.into());
+    }
+
+    log::info!(
+        "Paste {} ({} bytes) created by {}",
+        id,
+        text.len(),
+        client_ip_display(&req)
+    );
+
+    let url = format!(
+        "{}://{}{}",
+        client_scheme(&req),
+        client_host(&req),
+        paste_link(&state.base_url, &id)
+    );
+    Ok(HttpResponse::Created()
+        .insert_header((header::LOCATION, url.clone()))
+        .content_type("text/plain; charset=utf-8")
+        .body(format!("{}\n", url)))
+}
+
+// Paste rendered with line numbers
+pub async fn view_paste(
+    state: web::Data<State>,
+    id: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let id = id.into_inner();
+    let content = tokio::fs::read(paste_path(&state, &id)?).await?;
+
+    let context = PasteContext {
+        base_url: state.base_url.clone(),
+        link: paste_link(&state.base_url, &id),
+        size: content.len() as u64,
+        lines: String::from_utf8_lossy(&content)
+            .lines()
+            .map(str::to_string)
+            .collect(),
+        id,
+    };
+    let rendered_body = state.tera.render(
+        PASTE_TEMPLATE_NAME,
+        &tera::Context::from_serialize(&context).unwrap(),
+    )?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(rendered_body))
+}
+
+// Plain text variant shown in the browser
+pub async fn raw_paste(
+    state: web::Data<State>,
+    req: HttpRequest,
+    id: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    serve_paste(&state, &req, &id, DispositionType::Inline).await
+}
+
+// Plain text variant saved as a file
+pub async fn download_paste(
+    state: web::Data<State>,
+    req: HttpRequest,
+    id: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    serve_paste(&state, &req, &id, DispositionType::Attachment).await
+}
+
+async fn serve_paste(
+    state: &State,
+    req: &HttpRequest,
+    id: &str,
+    disposition: DispositionType,
+) -> Result<HttpResponse, AppError> {
+    let file = NamedFile::open_async(paste_path(state, id)?)
+        .await?
+        .set_content_type(mime::TEXT_PLAIN_UTF_8)
+        .set_content_disposition(ContentDisposition {
+            disposition,
+            parameters: vec![DispositionParam::Filename(format!("{}.txt", id))],
+        });
+
+    let response = file.into_response(req);
+    if !response.status().is_success() {
+        return Ok(response);
+    }
+    Ok(throttled_download(state, response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::paste::urls;
+    use actix_web::{
+        App,
+        http::StatusCode,
+        test::{TestRequest, call_service, init_service, read_body},
+    };
+    use tempfile::TempDir;
+
+    #[actix_web::test]
+    async fn creates_and_shows_pastes() {
+        let dir = TempDir::new().unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(State::for_tests(dir.path())))
+                .configure(urls::register_urls)
+                .configure(urls::register_view_urls),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/paste")
+            .insert_header((header::HOST, "example.com"))
+            .set_payload("first <line>\nsecond line\n")
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let url = res
+            .headers()
+            .get(header::LOCATION)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        let path = url.strip_prefix("http://example.com").unwrap().to_string();
+        assert!(path.starts_with("/.holonet/p/"), "{}", path);
+        assert_eq!(read_body(res).await, format!("{}\n", url));
+
+        let res = call_service(&app, TestRequest::get().uri(&path).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let page = String::from_utf8(read_body(res).await.to_vec()).unwrap();
+        assert!(page.contains("first &lt;line&gt;"));
+        assert!(!page.contains("first <line>"));
+
+        let raw = format!("{}/raw", path);
+        let res = call_service(&app, TestRequest::get().uri(&raw).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(read_body(res).await, "first <line>\nsecond line\n");
+
+        let download = format!("{}/download", path);
+        let res = call_service(&app, TestRequest::get().uri(&download).to_request()).await;
+        let disposition = res.headers().get(header::CONTENT_DISPOSITION).unwrap();
+        assert!(disposition.to_str().unwrap().starts_with("attachment"));
+    }
+
+    #[actix_web::test]
+    async fn rejects_invalid_pastes() {
+        let dir = TempDir::new().unwrap();
+        let mut state = State::for_tests(dir.path());
+        state.max_paste_size = 8;
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .configure(urls::register_urls)
+                .configure(urls::register_view_urls),
+        )
+        .await;
+
+        let cases: [(&'static [u8], StatusCode); 3] = [
+            (b"", StatusCode::BAD_REQUEST),
+            (b"\xff\xfe", StatusCode::BAD_REQUEST),
+            (b"123456789", StatusCode::PAYLOAD_TOO_LARGE),
+        ];
+        for (body, status) in cases {
+            let req = TestRequest::post()
+                .uri("/paste")
+                .set_payload(body)
+                .to_request();
+            assert_eq!(call_service(&app, req).await.status(), status, "{:?}", body);
+        }
+
+        // IDs never reach outside the paste directory
+        std::fs::write(dir.path().join("secret.txt"), "secret").unwrap();
+        for path in [
+            "/.holonet/p/missing",
+            "/.holonet/p/..%2Fsecret",
+            "/.holonet/p/a.b/raw",
+        ] {
+            let res = call_service(&app, TestRequest::get().uri(path).to_request()).await;
+            assert_eq!(res.status(), StatusCode::NOT_FOUND, "{}", path);
+        }
+    }
+}
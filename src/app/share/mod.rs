@@ -0,0 +1,4 @@
+pub mod store;
+pub mod token;
+pub mod urls;
+pub mod views;
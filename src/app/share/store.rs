@@ -0,0 +1,243 @@
+use path_clean::PathClean;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use super::token::{ShareClaims, ShareKey};
+use crate::app::errors::AppError;
+use crate::app::is_reserved;
+
+// Canonical path of a file or folder that may be shared, given relative to the root directory
+pub fn shareable_path(base_path: &Path, data_dir: &Path, path: &str) -> Result<PathBuf, AppError> {
+    let cleaned = PathBuf::from(path.trim_start_matches('/')).clean();
+    let canonical = base_path.join(cleaned).canonicalize()?;
+    if !canonical.starts_with(base_path)
+        || canonical.starts_with(data_dir)
+        || is_reserved(base_path, &canonical)
+    {
+        return Err(AppError::PathForbidden);
+    }
+    Ok(canonical)
+}
+
+// Path relative to the root directory, as stored in the link
+pub fn relative_path(base_path: &Path, path: &Path) -> String {
+    path.strip_prefix(base_path)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace("\\", "/")
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+struct DownloadCount {
+    expires: i64,
+    count: u64,
+}
+
+// On-disk format of the download counters
+#[derive(Default, Serialize, Deserialize)]
+struct CounterFile {
+    downloads: HashMap<String, DownloadCount>, // Keyed by link id
+}
+
+// Download of a limited link, counted before the file is sent. Dropping it
+// without `commit` gives the download back, e.g. when the response was refused.
+pub struct DownloadReservation {
+    shares: Arc<Shares>,
+    claims: ShareClaims,
+    committed: bool,
+}
+
+impl DownloadReservation {
+    pub fn commit(mut self) {
+        self.committed = true;
+    }
+}
+
+impl Drop for DownloadReservation {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.shares.release_download(&self.claims);
+        }
+    }
+}
+
+// Signing key plus the download counters of links limited in downloads
+pub struct Shares {
+    pub key: ShareKey,
+    counters_path: PathBuf,
+    downloads: Mutex<HashMap<String, DownloadCount>>,
+    in_progress: Mutex<HashSet<String>>, // One-time links being downloaded right now
+}
+
+impl Shares {
+    pub fn load(data_dir: &Path) -> std::io::Result<Self> {
+        let key = ShareKey::load_or_create(&data_dir.join("share.key"))?;
+
+        let counters_path = data_dir.join("shares.json");
+        let downloads = match std::fs::read(&counters_path) {
+            Ok(content) => {
+                serde_json::from_slice::<CounterFile>(&content)
+                    .map_err(|e| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("Share counters {}: {}", counters_path.display(), e),
+                        )
+                    })?
+                    .downloads
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(Shares {
+            key,
+            counters_path,
+            downloads: Mutex::new(downloads),
+            in_progress: Mutex::new(HashSet::new()),
+        })
+    }
+
+    // Claims of a link that is signed, not expired and has downloads left
+    pub fn resolve(&self, token: &str) -> Result<ShareClaims, AppError> {
+        let claims = self.key.open(token).ok_or(AppError::ShareInvalid)?;
+
+        if claims.expires <= chrono::Utc::now().timestamp() {
+            return Err(AppError::ShareExpired);
+        }
+
+        if let Some(max) = claims.download_limit() {
+            let downloads = self.downloads.lock().unwrap();
+            if downloads
+                .get(&claims.id)
+                .is_some_and(|downloads| downloads.count >= max)
+            {
+                return Err(if claims.one_time {
+                    AppError::ShareUsed
+                } else {
+                    AppError::ShareDownloadLimitReached
+                });
+            }
+        }
+
+        Ok(claims)
+    }
+
+    // Count a download of a limited link, refused once the limit is reached. The check and
+    // the count happen under one lock, so parallel requests cannot exceed the limit.
+    pub fn reserve_download(
+        self: &Arc<Self>,
+        claims: &ShareClaims,
+    ) -> Result<Option<DownloadReservation>, AppError> {
+        let Some(max) = claims.download_limit() else {
+            return Ok(None);
+        };
+
+        let mut downloads = self.downloads.lock().unwrap();
+        let count = downloads
+            .get(&claims.id)
+            .map_or(0, |downloads| downloads.count);
+        if count >= max {
+            return Err(if claims.one_time {
+                AppError::ShareUsed
+            } else {
+                AppError::ShareDownloadLimitReached
+            });
+        }
+        downloads.insert(
+            claims.id.clone(),
+            DownloadCount {
+                expires: claims.expires,
+                count: count + 1,
+            },
+        );
+        self.persist(&downloads);
+
+        Ok(Some(DownloadReservation {
+            shares: self.clone(),
+            claims: claims.clone(),
+            committed: false,
+        }))
+    }
+
+    fn release_download(&self, claims: &ShareClaims) {
+        let mut downloads = self.downloads.lock().unwrap();
+        let Some(download) = downloads.get_mut(&claims.id) else {
+            return;
+        };
+        download.count = download.count.saturating_sub(1);
+        if download.count == 0 {
+            downloads.remove(&claims.id);
+        }
+        self.persist(&downloads);
+    }
+
+    pub fn record_download(&self, claims: &ShareClaims) {
+        // Unlimited links need no bookkeeping
+        if claims.download_limit().is_none() {
+            return;
+        }
+
+        let mut downloads = self.downloads.lock().unwrap();
+        downloads
+            .entry(claims.id.clone())
+            .or_insert(DownloadCount {
+                expires: claims.expires,
+                count: 0,
+            })
+            .count += 1;
+
+        // Counters of expired links are of no use anymore
+        let now = chrono::Utc::now().timestamp();
+        downloads.retain(|_, downloads| downloads.expires > now);
+
+        self.persist(&downloads);
+    }
+
+    // Reserve the single download of a one-time link, refused while another one is running
+    pub fn begin_one_time(&self, claims: &ShareClaims) -> Result<(), AppError> {
+        let mut in_progress = self.in_progress.lock().unwrap();
+        if in_progress.contains(&claims.id) {
+            return Err(AppError::OneTimeInProgress);
+        }
+        // Another download may have burned the link since it was resolved
+        if self.downloads.lock().unwrap().contains_key(&claims.id) {
+            return Err(AppError::ShareUsed);
+        }
+        in_progress.insert(claims.id.clone());
+        Ok(())
+    }
+
+    // Burn a one-time link once its download completed, release it otherwise
+    pub fn finish_one_time(&self, claims: &ShareClaims, completed: bool) {
+        if completed {
+            self.record_download(claims);
+        }
+        self.in_progress.lock().unwrap().remove(&claims.id);
+    }
+
+    fn persist(&self, downloads: &HashMap<String, DownloadCount>) {
+        let content = CounterFile {
+            downloads: downloads.clone(),
+        };
+
+        let result = (|| -> std::io::Result<()> {
+            if let Some(parent) = self.counters_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            // Write to a temporary file first so a crash never leaves truncated counters
+            let tmp_path = self.counters_path.with_extension("tmp");
+            std::fs::write(&tmp_path, serde_json::to_vec(&content)?)?;
+            std::fs::rename(&tmp_path, &self.counters_path)
+        })();
+
+        if let Err(e) = result {
+            log::error!(
+                "Failed to write share counters {}: {}",
+                self.counters_path.display(),
+                e
+            );
+        }
+    }
+}
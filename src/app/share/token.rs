@@ -0,0 +1,367 @@
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::io::Write;
+use std::path::Path;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const KEY_LEN: usize = 32;
+
+// Longest lifetime a link can be minted with, one year
+pub const MAX_LIFETIME_SECS: u64 = 365 * 24 * 60 * 60;
+
+// What a share link grants, carried inside the link itself
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ShareClaims {
+    #[serde(rename = "p")]
+    pub path: String, // File or folder relative to the root directory
+    #[serde(rename = "e")]
+    pub expires: i64, // Unix timestamp
+    #[serde(rename = "m", default, skip_serializing_if = "Option::is_none")]
+    pub max_downloads: Option<u64>,
+    #[serde(rename = "o", default, skip_serializing_if = "std::ops::Not::not")]
+    pub one_time: bool, // Burned after the first complete download
+    #[serde(rename = "k", default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>, // Keyed hash, worthless without the server key
+    #[serde(rename = "n")]
+    pub id: String, // Random, tells links to the same path apart
+}
+
+impl ShareClaims {
+    // Downloads the link allows, one-time links allow exactly one
+    pub fn download_limit(&self) -> Option<u64> {
+        if self.one_time {
+            return Some(1);
+        }
+        self.max_downloads
+    }
+}
+
+// Server secret the links are signed with
+pub struct ShareKey {
+    key: Vec<u8>,
+}
+
+impl ShareKey {
+    // Read the key file, creating it on first use
+    pub fn load_or_create(path: &Path) -> std::io::Result<Self> {
+        match std::fs::read(path) {
+            Ok(key) if key.len() >= KEY_LEN => return Ok(ShareKey { key }),
+            Ok(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Share key {} is too short", path.display()),
+                ));
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let mut key = vec![0u8; KEY_LEN];
+        getrandom::fill(&mut key).map_err(std::io::Error::other)?;
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(path)?.write_all(&key)?;
+
+        log::info!("Created share link key {}", path.display());
+        Ok(ShareKey { key })
+    }
+
+    fn mac(&self, context: &str, parts: &[&str]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(context.as_bytes());
+        for part in parts {
+            mac.update(b"\0");
+            mac.update(part.as_bytes());
+        }
+        mac
+    }
+
+    fn tag(&self, context: &str, parts: &[&str]) -> String {
+        URL_SAFE_NO_PAD.encode(self.mac(context, parts).finalize().into_bytes())
+    }
+
+    // Constant time comparison against a tag produced by `tag`
+    fn verify(&self, context: &str, parts: &[&str], tag: &str) -> bool {
+        URL_SAFE_NO_PAD
+            .decode(tag)
+            .is_ok_and(|tag| self.mac(context, parts).verify_slice(&tag).is_ok())
+    }
+
+    // Token for the URL, the encoded claims followed by their signature
+    pub fn mint(
+        &self,
+        path: String,
+        lifetime_secs: u64,
+        password: Option<&str>,
+        max_downloads: Option<u64>,
+        one_time: bool,
+    ) -> std::io::Result<(String, ShareClaims)> {
+        let mut id = [0u8; 12];
+        getrandom::fill(&mut id).map_err(std::io::Error::other)?;
+        let id = URL_SAFE_NO_PAD.encode(id);
+
+        let expires = i64::try_from(lifetime_secs)
+            .ok()
+            .filter(|_| lifetime_secs <= MAX_LIFETIME_SECS)
+            .and_then(|lifetime| chrono::Utc::now().timestamp().checked_add(lifetime))
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "Share link lifetime must not exceed {} days",
+                        MAX_LIFETIME_SECS / (24 * 60 * 60)
+                    ),
+                )
+            })?;
+        // A link that allows no download at all is a mistake
+        if max_downloads == Some(0) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Share link download limit must be at least 1",
+            ));
+        }
+
+        let claims = ShareClaims {
+            path,
+            expires,
+            max_downloads,
+            one_time,
+            password: password
+                .filter(|password| !password.is_empty())
+                .map(|password| self.tag("password", &[&id, password])),
+            id,
+        };
+
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims)?);
+        let signature = self.tag("link", &[&payload]);
+        Ok((format!("{}.{}", payload, signature), claims))
+    }
+
+    // Claims of a token with a valid signature, expiry is left to the caller
+    pub fn open(&self, token: &str) -> Option<ShareClaims> {
+        let (payload, signature) = token.split_once('.')?;
+        if !self.verify("link", &[payload], signature) {
+            return None;
+        }
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()
+    }
+
+    pub fn check_password(&self, claims: &ShareClaims, password: &str) -> bool {
+        match &claims.password {
+            Some(hash) => self.verify("password", &[&claims.id, password], hash),
+            None => true,
+        }
+    }
+
+    // Cookie value proving the password of a link was entered
+    pub fn unlock_token(&self, claims: &ShareClaims) -> String {
+        self.tag("unlock", &[&claims.id])
+    }
+
+    pub fn check_unlock_token(&self, claims: &ShareClaims, token: &str) -> bool {
+        self.verify("unlock", &[&claims.id], token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::errors::AppError;
+    use crate::app::share::store::Shares;
+    use tempfile::TempDir;
+
+    fn key(byte: u8) -> ShareKey {
+        ShareKey {
+            key: vec![byte; KEY_LEN],
+        }
+    }
+
+    fn mint(key: &ShareKey, password: Option<&str>) -> (String, ShareClaims) {
+        key.mint("docs/report.pdf".to_string(), 60, password, None, false)
+            .unwrap()
+    }
+
+    // Token for arbitrary claims, signed the way `mint` does
+    fn sign(key: &ShareKey, claims: &ShareClaims) -> String {
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).unwrap());
+        format!("{}.{}", payload, key.tag("link", &[&payload]))
+    }
+
+    #[test]
+    fn opens_minted_links() {
+        let key = key(1);
+        let (token, claims) = mint(&key, None);
+        let opened = key.open(&token).unwrap();
+        assert_eq!(opened.path, claims.path);
+        assert_eq!(opened.expires, claims.expires);
+        assert_eq!(opened.id, claims.id);
+    }
+
+    #[test]
+    fn rejects_modified_tokens() {
+        let key = key(1);
+        let (token, claims) = mint(&key, None);
+        let (payload, signature) = token.split_once('.').unwrap();
+
+        // Same signature over claims for another path and a later expiry
+        let modified = ShareClaims {
+            path: "secret.txt".to_string(),
+            expires: claims.expires + 3600,
+            ..claims.clone()
+        };
+        let modified_payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&modified).unwrap());
+
+        let mut flipped = signature.to_string().into_bytes();
+        flipped[0] = if flipped[0] == b'A' { b'B' } else { b'A' };
+        let flipped = String::from_utf8(flipped).unwrap();
+
+        let cases = [
+            format!("{}.{}", modified_payload, signature),
+            format!("{}.{}", payload, flipped),
+            format!("{}x.{}", payload, signature),
+            payload.to_string(),
+            format!("{}.", payload),
+            String::new(),
+        ];
+        for token in cases {
+            assert!(key.open(&token).is_none(), "{}", token);
+        }
+
+        // Signed with another server's key
+        let (foreign, _) = mint(&self::key(2), None);
+        assert!(key.open(&foreign).is_none());
+    }
+
+    #[test]
+    fn rejects_expired_links() {
+        let dir = TempDir::new().unwrap();
+        let shares = Shares::load(dir.path()).unwrap();
+
+        let (token, claims) = mint(&shares.key, None);
+        assert!(shares.resolve(&token).is_ok());
+
+        let now = chrono::Utc::now().timestamp();
+        for expires in [now - 3600, now] {
+            let expired = sign(
+                &shares.key,
+                &ShareClaims {
+                    expires,
+                    ..claims.clone()
+                },
+            );
+            // The signature is fine, only the expiry is not
+            assert!(shares.key.open(&expired).is_some());
+            assert!(matches!(
+                shares.resolve(&expired),
+                Err(AppError::ShareExpired)
+            ));
+        }
+    }
+
+    #[test]
+    fn checks_passwords() {
+        let key = key(1);
+        let (_, claims) = mint(&key, Some("hunter2"));
+        assert!(claims.password.is_some());
+        assert!(key.check_password(&claims, "hunter2"));
+        for wrong in ["", "hunter", "hunter22", "Hunter2"] {
+            assert!(!key.check_password(&claims, wrong), "{}", wrong);
+        }
+
+        // The hash is bound to the link, it does not carry over to another one
+        let (_, other) = mint(&key, Some("letmein"));
+        let swapped = ShareClaims {
+            password: claims.password.clone(),
+            ..other
+        };
+        assert!(!key.check_password(&swapped, "hunter2"));
+
+        // Links without a password, an empty one counts as none
+        let (_, open) = mint(&key, Some(""));
+        assert!(open.password.is_none());
+        assert!(key.check_password(&open, "anything"));
+    }
+
+    #[test]
+    fn unlock_tokens_are_bound_to_their_link() {
+        let key = key(1);
+        let (_, first) = mint(&key, Some("hunter2"));
+        let (_, second) = mint(&key, Some("hunter2"));
+
+        let cookie = key.unlock_token(&first);
+        assert!(key.check_unlock_token(&first, &cookie));
+        assert!(!key.check_unlock_token(&second, &cookie));
+        assert!(!key.check_unlock_token(&first, ""));
+        assert!(!self::key(2).check_unlock_token(&first, &cookie));
+    }
+
+    #[test]
+    fn burns_one_time_links() {
+        let dir = TempDir::new().unwrap();
+        let shares = Shares::load(dir.path()).unwrap();
+        let (token, claims) = shares
+            .key
+            .mint("a.txt".to_string(), 60, None, None, true)
+            .unwrap();
+        assert_eq!(claims.download_limit(), Some(1));
+
+        // Only one download at a time, an aborted one releases the link
+        shares.begin_one_time(&claims).unwrap();
+        assert!(matches!(
+            shares.begin_one_time(&claims),
+            Err(AppError::OneTimeInProgress)
+        ));
+        shares.finish_one_time(&claims, false);
+        assert!(shares.resolve(&token).is_ok());
+
+        shares.begin_one_time(&claims).unwrap();
+        shares.finish_one_time(&claims, true);
+        assert!(matches!(shares.resolve(&token), Err(AppError::ShareUsed)));
+        assert!(matches!(
+            shares.begin_one_time(&claims),
+            Err(AppError::ShareUsed)
+        ));
+
+        // The burn survives a restart
+        let shares = Shares::load(dir.path()).unwrap();
+        assert!(matches!(shares.resolve(&token), Err(AppError::ShareUsed)));
+    }
+
+    #[test]
+    fn rejects_invalid_lifetimes() {
+        let key = key(1);
+        for lifetime in [MAX_LIFETIME_SECS + 1, i64::MAX as u64, u64::MAX] {
+            let result = key.mint("a.txt".to_string(), lifetime, None, None, false);
+            assert_eq!(
+                result.unwrap_err().kind(),
+                std::io::ErrorKind::InvalidInput,
+                "{}",
+                lifetime
+            );
+        }
+        assert!(
+            key.mint("a.txt".to_string(), MAX_LIFETIME_SECS, None, None, false)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn rejects_download_limits_below_one() {
+        let key = key(1);
+        let result = key.mint("a.txt".to_string(), 60, None, Some(0), false);
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        assert!(
+            key.mint("a.txt".to_string(), 60, None, Some(1), false)
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,22 @@
+use actix_web::web;
+
+use super::views;
+use crate::app::RESERVED_SEGMENT;
+
+// Links live below the reserved segment, so they never shadow a shared folder
+pub fn register_urls(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope(&format!("/{}/s", RESERVED_SEGMENT))
+            .service(
+                web::resource("/{token}")
+                    .route(web::get().to(views::open_share))
+                    .route(web::post().to(views::unlock_share)),
+            )
+            .route("/{token}/{tail:.*}", web::get().to(views::open_share)),
+    );
+}
+
+// Creating links needs the listing, it is not offered in upload-only mode
+pub fn register_create_urls(cfg: &mut web::ServiceConfig) {
+    cfg.route("/share", web::post().to(views::create_share));
+}
@@ -0,0 +1,533 @@
+use actix_web::{
+    HttpRequest, HttpResponse,
+    body::{BodySize, BoxBody, MessageBody},
+    cookie::{Cookie, SameSite, time::OffsetDateTime},
+    http::header,
+    web,
+};
+use serde::{Deserialize, Serialize};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use super::store::{DownloadReservation, Shares, relative_path, shareable_path};
+use super::token::{MAX_LIFETIME_SECS, ShareClaims};
+use crate::app::download::one_time::CompletionBody;
+use crate::app::download::views::{PathQuery, Scope, serve_path};
+use crate::app::errors::AppError;
+use crate::app::{RESERVED_SEGMENT, ServerMode};
+use crate::middleware::rate_limit::Download;
+use crate::utils::proxy::{client_host, client_ip_display, client_scheme};
+use crate::{SHARE_PASSWORD_TEMPLATE_NAME, State};
+
+// Lifetime of links created without one
+const DEFAULT_LIFETIME_SECS: u64 = 24 * 60 * 60;
+
+// Body of a request to create a link
+#[derive(Deserialize)]
+pub struct ShareRequest {
+    path: String, // Relative to the root directory
+    expires_in: Option<u64>,
+    password: Option<String>,
+    max_downloads: Option<u64>,
+    #[serde(default)]
+    one_time: bool,
+}
+
+#[derive(Serialize)]
+struct ShareResponse {
+    url: String,
+    expires: i64,
+}
+
+#[derive(Deserialize)]
+pub struct ShareTarget {
+    token: String,
+    #[serde(default)]
+    tail: String, // Path inside a shared folder
+}
+
+#[derive(Deserialize)]
+pub struct UnlockForm {
+    password: String,
+}
+
+// Struct for Tera context of the password prompt
+#[derive(Serialize)]
+struct PasswordContext {
+    base_url: String,
+    action: String,
+    failed: bool,
+}
+
+// URL path of a link below the base URL
+pub fn share_link(base_url: &str, token: &str) -> String {
+    format!("{}/{}/s/{}", base_url, RESERVED_SEGMENT, token)
+}
+
+fn unlock_cookie_name(claims: &ShareClaims) -> String {
+    format!("holonet_share_{}", claims.id)
+}
+
+fn wants_html(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(header::ACCEPT)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"))
+}
+
+fn password_page(state: &State, token: &str, failed: bool) -> Result<HttpResponse, AppError> {
+    let context = PasswordContext {
+        base_url: state.base_url.clone(),
+        action: share_link(&state.base_url, token),
+        failed,
+    };
+    let rendered_body = state.tera.render(
+        SHARE_PASSWORD_TEMPLATE_NAME,
+        &tera::Context::from_serialize(&context).unwrap(),
+    )?;
+
+    Ok(HttpResponse::Unauthorized()
+        .content_type("text/html; charset=utf-8")
+        .body(rendered_body))
+}
+
+pub async fn create_share(
+    state: web::Data<State>,
+    req: HttpRequest,
+    body: web::Json<ShareRequest>,
+) -> Result<HttpResponse, AppError> {
+    let Some(shares) = &state.shares else {
+        return Err(AppError::PathNotFound);
+    };
+    let body = body.into_inner();
+
+    let lifetime = body.expires_in.unwrap_or(DEFAULT_LIFETIME_SECS);
+    if lifetime == 0 || lifetime > MAX_LIFETIME_SECS {
+        return Err(AppError::ShareLifetimeInvalid);
+    }
+    if body.max_downloads == Some(0) {
+        return Err(AppError::ShareDownloadLimitInvalid);
+    }
+
+    let path = shareable_path(&state.base_path, &state.data_dir, &body.path)?;
+    let (token, claims) = shares.key.mint(
+        relative_path(&state.base_path, &path),
+        lifetime,
+        body.password.as_deref(),
+        body.max_downloads,
+        body.one_time,
+    )?;
+
+    log::info!(
+        "Share link for '{}' created by {}, valid until {}",
+        claims.path,
+        client_ip_display(&req),
+        claims.expires
+    );
+
+    // Folders end in a slash so the link reads like a directory
+    let url = format!(
+        "{}://{}{}{}",
+        client_scheme(&req),
+        client_host(&req),
+        share_link(&state.base_url, &token),
+        if path.is_dir() { "/" } else { "" }
+    );
+
+    Ok(HttpResponse::Ok().json(ShareResponse {
+        url,
+        expires: claims.expires,
+    }))
+}
+
+// Serve the shared file, or the shared folder and anything below it
+pub async fn open_share(
+    state: web::Data<State>,
+    req: HttpRequest,
+    target: web::Path<ShareTarget>,
+    query: web::Query<PathQuery>,
+) -> Result<HttpResponse, AppError> {
+    let Some(shares) = &state.shares else {
+        return Err(AppError::ShareInvalid);
+    };
+    let target = target.into_inner();
+    let claims = shares.resolve(&target.token)?;
+
+    // Scripts send the password along, browsers unlock the link once through the form
+    if claims.password.is_some() {
+        let password = req
+            .headers()
+            .get("X-Share-Password")
+            .and_then(|h| h.to_str().ok());
+        let unlocked = req
+            .cookie(&unlock_cookie_name(&claims))
+            .is_some_and(|cookie| shares.key.check_unlock_token(&claims, cookie.value()));
+
+        match password {
+            Some(password) if !shares.key.check_password(&claims, password) => {
+                return Err(AppError::SharePasswordInvalid);
+            }
+            Some(_) => {}
+            None if unlocked => {}
+            None if wants_html(&req) => return password_page(&state, &target.token, false),
+            None => return Err(AppError::SharePasswordRequired),
+        }
+    }
+
+    // Checked again, the path may have been replaced since the link was made
+    let root = shareable_path(&state.base_path, &state.data_dir, &claims.path)?;
+    let scope = Scope {
+        root,
+        link_prefix: share_link(&state.base_url, &target.token),
+        mode: ServerMode::ReadOnly,
+        redirect_not_found: false,
+        show_usage: false,
+        sharing: false,
+        single_use: claims.one_time,
+        limited: claims.max_downloads.is_some(),
+        variant: query.variant(),
+    };
+
+    if claims.one_time {
+        return serve_one_time(shares.clone(), &state, &req, &scope, &target.tail, claims).await;
+    }
+
+    let response = serve_path(&state, &req, &scope, &target.tail).await?;
+    if !response.extensions().contains::<Download>() {
+        return Ok(response);
+    }
+
+    // Limited links always send files whole, so every download counts. It is counted
+    // before the file is sent and only kept once sending starts.
+    let Some(reservation) = shares.reserve_download(&claims)? else {
+        return Ok(response);
+    };
+    Ok(response.map_body(|_, body| {
+        BoxBody::new(ReservedBody {
+            body,
+            reservation: Some(reservation),
+        })
+    }))
+}
+
+// Body holding the download reservation of a limited link. The download is kept once the
+// first chunk is requested, a response that is never sent (e.g. refused by the rate
+// limiter, or a HEAD request) gives it back.
+struct ReservedBody {
+    body: BoxBody,
+    reservation: Option<DownloadReservation>,
+}
+
+impl MessageBody for ReservedBody {
+    type Error = Box<dyn std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<web::Bytes, Self::Error>>> {
+        if let Some(reservation) = self.reservation.take() {
+            reservation.commit();
+        }
+        Pin::new(&mut self.body).poll_next(cx)
+    }
+}
+
+// Serve through a one-time link, burning it once a file was sent completely.
+// Listings of a shared folder leave the link intact.
+async fn serve_one_time(
+    shares: Arc<Shares>,
+    state: &State,
+    req: &HttpRequest,
+    scope: &Scope,
+    tail: &str,
+    claims: ShareClaims,
+) -> Result<HttpResponse, AppError> {
+    shares.begin_one_time(&claims)?;
+
+    let response = match serve_path(state, req, scope, tail).await {
+        Ok(response) if response.extensions().contains::<Download>() => response,
+        result => {
+            shares.finish_one_time(&claims, false);
+            return result;
+        }
+    };
+
+    Ok(response.map_body(|_, body| {
+        BoxBody::new(CompletionBody::new(body, move |completed| {
+            if completed {
+                log::info!("One-time share link for '{}' was used", claims.path);
+            }
+            shares.finish_one_time(&claims, completed)
+        }))
+    }))
+}
+
+// Password form submission, remembered in a cookie scoped to the link
+pub async fn unlock_share(
+    state: web::Data<State>,
+    req: HttpRequest,
+    token: web::Path<String>,
+    form: web::Form<UnlockForm>,
+) -> Result<HttpResponse, AppError> {
+    let Some(shares) = &state.shares else {
+        return Err(AppError::ShareInvalid);
+    };
+    let token = token.into_inner();
+    let claims = shares.resolve(&token)?;
+
+    if !shares.key.check_password(&claims, &form.password) {
+        log::warn!(
+            "Wrong password for share link '{}' from {}",
+            claims.path,
+            client_ip_display(&req)
+        );
+        return password_page(&state, &token, true);
+    }
+
+    let link = share_link(&state.base_url, &token);
+    let mut cookie = Cookie::build(
+        unlock_cookie_name(&claims),
+        shares.key.unlock_token(&claims),
+    )
+    .path(link.clone())
+    .http_only(true)
+    .same_site(SameSite::Lax)
+    .secure(client_scheme(&req) == "https")
+    .finish();
+    if let Ok(expires) = OffsetDateTime::from_unix_timestamp(claims.expires) {
+        cookie.set_expires(expires);
+    }
+
+    Ok(HttpResponse::SeeOther()
+        .cookie(cookie)
+        .insert_header((header::LOCATION, link))
+        .finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::download::thumbnails::Thumbnails;
+    use crate::app::share::urls;
+    use actix_web::{
+        App,
+        http::StatusCode,
+        test::{TestRequest, call_service, init_service, read_body},
+    };
+    use tempfile::TempDir;
+
+    fn state(dir: &TempDir) -> State {
+        let mut state = State::for_tests(dir.path());
+        state.shares = Some(Arc::new(Shares::load(&state.data_dir).unwrap()));
+        state
+    }
+
+    fn mint(state: &State, path: &str, max_downloads: Option<u64>) -> String {
+        let shares = state.shares.as_ref().unwrap();
+        let (token, _) = shares
+            .key
+            .mint(path.to_string(), 60, None, max_downloads, false)
+            .unwrap();
+        share_link("", &token)
+    }
+
+    #[actix_web::test]
+    async fn counts_whole_downloads_of_limited_links() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "0123456789").unwrap();
+        let state = state(&dir);
+        let link = mint(&state, "a.txt", Some(2));
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .configure(urls::register_urls),
+        )
+        .await;
+
+        // Ranges are ignored, a counted download cannot be fetched again in pieces
+        for _ in 0..2 {
+            let req = TestRequest::get()
+                .uri(&link)
+                .insert_header((header::RANGE, "bytes=0-1"))
+                .to_request();
+            let res = call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(read_body(res).await, "0123456789");
+        }
+
+        let res = call_service(&app, TestRequest::get().uri(&link).to_request()).await;
+        assert_eq!(res.status(), StatusCode::GONE);
+    }
+
+    #[actix_web::test]
+    async fn refuses_previews_on_limited_links() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir(dir.path().join("docs")).unwrap();
+        std::fs::write(dir.path().join("docs/notes.md"), "# Notes").unwrap();
+        std::fs::write(dir.path().join("docs/README.md"), "# Read me").unwrap();
+        let state = state(&dir);
+        let limited = mint(&state, "docs", Some(1));
+        let unlimited = mint(&state, "docs", None);
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .configure(urls::register_urls),
+        )
+        .await;
+
+        for (link, status) in [
+            (&limited, StatusCode::NOT_FOUND),
+            (&unlimited, StatusCode::OK),
+        ] {
+            // Hex pages read any part of the file, they are no different
+            for query in ["preview", "hex&page=1"] {
+                let uri = format!("{}/notes.md?{}", link, query);
+                let res = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+                assert_eq!(res.status(), status, "{}", uri);
+            }
+        }
+
+        // Nor are they offered in the listing
+        for (link, offered) in [(&limited, false), (&unlimited, true)] {
+            let uri = format!("{}/", link);
+            let res = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+            let page = String::from_utf8(read_body(res).await.to_vec()).unwrap();
+            assert!(page.contains("notes.md"));
+            assert_eq!(page.contains("?preview=1"), offered, "{}", uri);
+            // Nor is the README rendered below it
+            assert_eq!(page.contains("<h1>Read me</h1>"), offered, "{}", uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn creates_links_with_valid_lifetimes() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "a").unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(state(&dir)))
+                .configure(urls::register_create_urls),
+        )
+        .await;
+
+        let cases = [
+            (serde_json::json!({"path": "a.txt"}), StatusCode::OK),
+            (
+                serde_json::json!({"path": "a.txt", "expires_in": MAX_LIFETIME_SECS}),
+                StatusCode::OK,
+            ),
+            (
+                serde_json::json!({"path": "a.txt", "expires_in": 0}),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                serde_json::json!({"path": "a.txt", "expires_in": MAX_LIFETIME_SECS + 1}),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                serde_json::json!({"path": "a.txt", "expires_in": u64::MAX}),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                serde_json::json!({"path": "a.txt", "max_downloads": 1}),
+                StatusCode::OK,
+            ),
+            (
+                serde_json::json!({"path": "a.txt", "max_downloads": 0}),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                serde_json::json!({"path": "missing.txt"}),
+                StatusCode::NOT_FOUND,
+            ),
+        ];
+        for (body, status) in cases {
+            let req = TestRequest::post()
+                .uri("/share")
+                .set_json(&body)
+                .to_request();
+            assert_eq!(call_service(&app, req).await.status(), status, "{}", body);
+        }
+    }
+
+    #[actix_web::test]
+    async fn counts_only_downloads_that_are_sent() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "0123456789").unwrap();
+        let state = state(&dir);
+        let link = mint(&state, "a.txt", Some(1));
+        let shares = state.shares.clone().unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .configure(urls::register_urls),
+        )
+        .await;
+
+        // Reserved while the response is pending, given back once it is dropped unsent
+        let res = call_service(&app, TestRequest::get().uri(&link).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let pending = call_service(&app, TestRequest::get().uri(&link).to_request()).await;
+        assert_eq!(pending.status(), StatusCode::GONE);
+        drop(res);
+
+        let res = call_service(&app, TestRequest::get().uri(&link).to_request()).await;
+        assert_eq!(read_body(res).await, "0123456789");
+        let res = call_service(&app, TestRequest::get().uri(&link).to_request()).await;
+        assert_eq!(res.status(), StatusCode::GONE);
+
+        // Parallel reservations never exceed the limit
+        let (token, _) = shares
+            .key
+            .mint("a.txt".to_string(), 60, None, Some(3), false)
+            .unwrap();
+        let claims = shares.resolve(&token).unwrap();
+        let reservations: Vec<_> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| scope.spawn(|| shares.reserve_download(&claims).ok().flatten()))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert_eq!(reservations.iter().filter(|r| r.is_some()).count(), 3);
+    }
+
+    #[actix_web::test]
+    async fn refuses_thumbnails_on_limited_links() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir(dir.path().join("photos")).unwrap();
+        image::RgbImage::new(8, 8)
+            .save(dir.path().join("photos/a.png"))
+            .unwrap();
+        let mut state = state(&dir);
+        state.thumbnails = Some(Arc::new(
+            Thumbnails::start(state.data_dir.join("thumbnails")).unwrap(),
+        ));
+        let limited = mint(&state, "photos", Some(1));
+        let unlimited = mint(&state, "photos", None);
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .configure(urls::register_urls),
+        )
+        .await;
+
+        for (link, status, gallery) in [
+            (&limited, StatusCode::NOT_FOUND, false),
+            (&unlimited, StatusCode::OK, true),
+        ] {
+            let uri = format!("{}/a.png?thumb", link);
+            let res = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+            assert_eq!(res.status(), status, "{}", uri);
+
+            let uri = format!("{}/", link);
+            let res = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+            let page = String::from_utf8(read_body(res).await.to_vec()).unwrap();
+            assert_eq!(page.contains("?thumb=1"), gallery, "{}", uri);
+        }
+    }
+}
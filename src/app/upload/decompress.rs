@@ -0,0 +1,189 @@
+use actix_web::{
+    HttpRequest,
+    dev::Decompress,
+    error::PayloadError,
+    http::header::{self, ContentEncoding},
+    web::Bytes,
+};
+use futures::{Stream, StreamExt, stream::LocalBoxStream};
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::app::errors::AppError;
+
+// Output below this size is never treated as a decompression bomb, whatever the ratio
+const MIN_RATIO_CHECK_BYTES: u64 = 1024 * 1024;
+
+// How compressed upload bodies are handled
+#[derive(Clone, Debug, Default)]
+pub struct Decompression {
+    pub max_ratio: Option<u64>, // Decompressed bytes allowed per received byte
+    pub max_size: Option<u64>,  // Decompressed bytes allowed in total
+    pub store_compressed: bool, // Keep the body as sent, with a matching file suffix
+}
+
+impl Decompression {
+    // Abort once the output grows suspiciously large compared to the input
+    pub fn check(&self, received: u64, decompressed: u64) -> Result<(), AppError> {
+        if let Some(max) = self.max_size
+            && decompressed > max
+        {
+            return Err(AppError::DecompressionLimitExceeded);
+        }
+        if let Some(ratio) = self.max_ratio
+            && decompressed > MIN_RATIO_CHECK_BYTES
+            && decompressed > received.saturating_mul(ratio)
+        {
+            return Err(AppError::DecompressionLimitExceeded);
+        }
+        Ok(())
+    }
+}
+
+// Encoding of the request body, None for an uncompressed body
+pub fn body_encoding(req: &HttpRequest) -> Result<Option<ContentEncoding>, AppError> {
+    let Some(value) = req.headers().get(header::CONTENT_ENCODING) else {
+        return Ok(None);
+    };
+    let value = value
+        .to_str()
+        .map_err(|e| AppError::InvalidHeader(e.to_string()))?
+        .trim();
+
+    match value.parse::<ContentEncoding>() {
+        Ok(ContentEncoding::Identity) => Ok(None),
+        Ok(encoding @ (ContentEncoding::Gzip | ContentEncoding::Zstd)) => Ok(Some(encoding)),
+        _ => Err(AppError::UnsupportedEncoding(value.to_string())),
+    }
+}
+
+// File suffix for a body stored in its compressed form
+pub fn file_suffix(encoding: ContentEncoding) -> &'static str {
+    match encoding {
+        ContentEncoding::Zstd => "zst",
+        _ => "gz",
+    }
+}
+
+// The body as it streams in, decompressed if an encoding is given. The counter tracks the
+// bytes taken off the wire, for the ratio check and the bandwidth limit.
+pub fn body_stream<S>(
+    payload: S,
+    encoding: Option<ContentEncoding>,
+) -> (
+    LocalBoxStream<'static, Result<Bytes, PayloadError>>,
+    Rc<Cell<u64>>,
+)
+where
+    S: Stream<Item = Result<Bytes, PayloadError>> + Unpin + 'static,
+{
+    let received = Rc::new(Cell::new(0));
+    let counter = received.clone();
+    let payload = payload.inspect(move |chunk| {
+        if let Ok(data) = chunk {
+            counter.set(counter.get() + data.len() as u64);
+        }
+    });
+
+    let body = match encoding {
+        Some(encoding) => Decompress::new(payload, encoding).boxed_local(),
+        None => payload.boxed_local(),
+    };
+    (body, received)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_http::{ResponseHead, StatusCode, encoding::Encoder};
+    use actix_web::{body, test::TestRequest};
+
+    const MIB: u64 = 1024 * 1024;
+
+    async fn compress(encoding: ContentEncoding, data: &'static [u8]) -> Bytes {
+        let mut head = ResponseHead::new(StatusCode::OK);
+        body::to_bytes(Encoder::response(encoding, &mut head, data))
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn checks_ratio_and_size() {
+        let decompression = Decompression {
+            max_ratio: Some(10),
+            max_size: Some(100 * MIB),
+            store_compressed: false,
+        };
+        let cases = [
+            (1, MIB, true),
+            (1, MIB + 1, false),
+            (MIB / 10, 2 * MIB, false),
+            (MIB, 10 * MIB, true),
+            (MIB, 10 * MIB + 1, false),
+            (20 * MIB, 100 * MIB, true),
+            (20 * MIB, 100 * MIB + 1, false),
+        ];
+        for (received, decompressed, allowed) in cases {
+            assert_eq!(
+                decompression.check(received, decompressed).is_ok(),
+                allowed,
+                "{} -> {}",
+                received,
+                decompressed
+            );
+        }
+        assert!(Decompression::default().check(1, u64::MAX).is_ok());
+    }
+
+    #[test]
+    fn reads_body_encodings() {
+        let cases = [
+            (None, Ok(None)),
+            (Some("identity"), Ok(None)),
+            (Some("gzip"), Ok(Some(ContentEncoding::Gzip))),
+            (Some(" zstd "), Ok(Some(ContentEncoding::Zstd))),
+            (Some("br"), Err("unsupported_encoding")),
+            (Some("deflate"), Err("unsupported_encoding")),
+            (Some("gzip, zstd"), Err("unsupported_encoding")),
+        ];
+        for (value, expected) in cases {
+            let mut req = TestRequest::default();
+            if let Some(value) = value {
+                req = req.insert_header((header::CONTENT_ENCODING, value));
+            }
+            let encoding = body_encoding(&req.to_http_request()).map_err(|e| e.code());
+            assert_eq!(encoding, expected, "{:?}", value);
+        }
+        assert_eq!(file_suffix(ContentEncoding::Gzip), "gz");
+        assert_eq!(file_suffix(ContentEncoding::Zstd), "zst");
+    }
+
+    #[actix_web::test]
+    async fn decodes_bodies_and_counts_wire_bytes() {
+        let text: &'static [u8] = &[b'a'; 64 * 1024];
+        for encoding in [ContentEncoding::Gzip, ContentEncoding::Zstd] {
+            let compressed = compress(encoding, text).await;
+            let sent = compressed.len() as u64;
+            let payload = futures::stream::iter(
+                compressed
+                    .chunks(100)
+                    .map(|chunk| Ok::<_, PayloadError>(Bytes::copy_from_slice(chunk)))
+                    .collect::<Vec<_>>(),
+            );
+
+            let (body, received) = body_stream(payload, Some(encoding));
+            let decoded: Vec<u8> = body
+                .map(|chunk| chunk.unwrap())
+                .collect::<Vec<_>>()
+                .await
+                .concat();
+            assert_eq!(decoded, text, "{:?}", encoding);
+            assert_eq!(received.get(), sent, "{:?}", encoding);
+        }
+
+        let payload = futures::stream::iter([Ok::<_, PayloadError>(Bytes::from_static(b"plain"))]);
+        let (mut body, received) = body_stream(payload, None);
+        assert_eq!(body.next().await.unwrap().unwrap(), "plain");
+        assert_eq!(received.get(), 5);
+    }
+}
@@ -0,0 +1,597 @@
+use flate2::read::MultiGzDecoder;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use crate::app::download::magic;
+use crate::app::errors::AppError;
+
+// Archive formats uploads can be extracted from, recognized by their magic bytes
+#[derive(Clone, Copy, Debug)]
+enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+// Name endings removed from an archive to name the folder it is extracted into
+const ARCHIVE_SUFFIXES: &[&str] = &[".tar.gz", ".tgz", ".tar.zst", ".tzst", ".tar", ".zip"];
+
+// Limits for the contents of a single archive
+#[derive(Clone, Copy, Debug)]
+pub struct ExtractLimits {
+    pub max_size: u64,    // Bytes all extracted files may take together
+    pub max_entries: u64, // Files and folders in the archive
+}
+
+// A file written by the extraction, with its path below the target folder
+pub struct ExtractedFile {
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+// Folder an archive is extracted into, next to it and named after it
+pub fn target_folder(archive_name: &str) -> String {
+    let lower = archive_name.to_ascii_lowercase();
+    let stem = ARCHIVE_SUFFIXES
+        .iter()
+        .find(|suffix| lower.ends_with(*suffix))
+        .map(|suffix| &archive_name[..archive_name.len() - suffix.len()])
+        .or_else(|| Path::new(archive_name).file_stem()?.to_str())
+        .unwrap_or_default();
+
+    // The folder must not collide with the archive itself
+    if stem.is_empty() || stem == archive_name {
+        format!("{}_extracted", archive_name)
+    } else {
+        stem.to_string()
+    }
+}
+
+fn archive_format(path: &Path) -> std::io::Result<Option<ArchiveFormat>> {
+    let mut header = Vec::new();
+    File::open(path)?
+        .take(magic::HEADER_LEN as u64)
+        .read_to_end(&mut header)?;
+
+    let extension = magic::identify(&header).and_then(|file_type| file_type.extension);
+    Ok(match extension {
+        Some("zip") => Some(ArchiveFormat::Zip),
+        Some("tar") => Some(ArchiveFormat::Tar),
+        Some("gz") => Some(ArchiveFormat::TarGz),
+        Some("zst") => Some(ArchiveFormat::TarZst),
+        _ => None,
+    })
+}
+
+fn invalid(err: impl std::fmt::Display) -> AppError {
+    AppError::ArchiveInvalid(err.to_string())
+}
+
+fn rejected(name: &Path) -> AppError {
+    AppError::ArchiveEntryRejected(name.display().to_string())
+}
+
+// Writes the entries of an archive below `root`, keeping count of the limits
+struct Extractor<'a, F> {
+    root: &'a Path,
+    limits: ExtractLimits,
+    has_free_space: F,
+    entries: u64,
+    size: u64,
+    files: Vec<ExtractedFile>,
+}
+
+impl<F: Fn(u64) -> bool> Extractor<'_, F> {
+    // Path of an entry below the root. Absolute paths and parent directory
+    // components are refused rather than mangled, they never occur in honest archives.
+    fn entry_path(&mut self, name: &Path) -> Result<Option<PathBuf>, AppError> {
+        self.entries += 1;
+        if self.entries > self.limits.max_entries {
+            return Err(AppError::ExtractLimitExceeded);
+        }
+
+        let mut relative = PathBuf::new();
+        for component in name.components() {
+            match component {
+                Component::Normal(part) => {
+                    relative.push(sanitize_filename::sanitize(part.to_string_lossy()))
+                }
+                Component::CurDir => {}
+                Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                    return Err(rejected(name));
+                }
+            }
+        }
+        // An entry for the root itself, e.g. "./" in tarballs
+        if relative.as_os_str().is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(relative))
+    }
+
+    fn add_dir(&mut self, name: &Path) -> Result<(), AppError> {
+        if let Some(relative) = self.entry_path(name)? {
+            std::fs::create_dir_all(self.root.join(relative))?;
+        }
+        Ok(())
+    }
+
+    fn add_file(
+        &mut self,
+        name: &Path,
+        announced: u64,
+        content: impl Read,
+    ) -> Result<(), AppError> {
+        let relative = self.entry_path(name)?.ok_or_else(|| rejected(name))?;
+        let remaining = self.limits.max_size - self.size;
+        if announced > remaining {
+            return Err(AppError::ExtractLimitExceeded);
+        }
+        if !(self.has_free_space)(announced) {
+            return Err(AppError::InsufficientStorage);
+        }
+
+        let path = self.root.join(&relative);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Entries appearing twice would overwrite each other and spoil the accounting
+        let mut file = File::options()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::AlreadyExists => {
+                    invalid(format!("Entry {} appears more than once", name.display()))
+                }
+                _ => e.into(),
+            })?;
+
+        // The announced size may lie, the limit applies to what is actually written
+        let written = std::io::copy(&mut content.take(remaining + 1), &mut file).map_err(|e| {
+            match e.kind() {
+                std::io::ErrorKind::StorageFull => AppError::InsufficientStorage,
+                _ => invalid(e),
+            }
+        })?;
+        if written > remaining {
+            return Err(AppError::ExtractLimitExceeded);
+        }
+        file.flush()?;
+
+        self.size += written;
+        self.files.push(ExtractedFile {
+            path: relative,
+            size: written,
+        });
+        Ok(())
+    }
+
+    fn extract_zip(&mut self, archive: File) -> Result<(), AppError> {
+        let mut archive = zip::ZipArchive::new(archive).map_err(invalid)?;
+        // The central directory tells the entry count up front
+        if archive.len() as u64 > self.limits.max_entries {
+            return Err(AppError::ExtractLimitExceeded);
+        }
+
+        for index in 0..archive.len() {
+            let entry = archive.by_index(index).map_err(invalid)?;
+            let name = PathBuf::from(entry.name().map_err(invalid)?.as_ref());
+            if entry.is_symlink() {
+                return Err(rejected(&name));
+            }
+            if entry.is_dir() {
+                self.add_dir(&name)?;
+            } else {
+                let size = entry.size();
+                self.add_file(&name, size, entry)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn extract_tar(&mut self, archive: impl Read) -> Result<(), AppError> {
+        let mut archive = tar::Archive::new(archive);
+        for entry in archive.entries().map_err(invalid)? {
+            let entry = entry.map_err(invalid)?;
+            let name = entry.path().map_err(invalid)?.into_owned();
+            match entry.header().entry_type() {
+                tar::EntryType::Regular | tar::EntryType::Continuous => {
+                    let size = entry.size();
+                    self.add_file(&name, size, entry)?;
+                }
+                tar::EntryType::Directory => self.add_dir(&name)?,
+                // Metadata for the following entries, nothing to write
+                tar::EntryType::XGlobalHeader => {}
+                // Symbolic and hard links, devices and pipes
+                _ => return Err(rejected(&name)),
+            }
+        }
+        Ok(())
+    }
+}
+
+// Extract `archive` into the new folder `target`, this runs on the blocking pool.
+// Entries are written to a hidden folder first, so a rejected archive leaves nothing behind.
+pub fn extract(
+    archive: &Path,
+    target: &Path,
+    limits: ExtractLimits,
+    has_free_space: impl Fn(u64) -> bool,
+) -> Result<Vec<ExtractedFile>, AppError> {
+    let format = archive_format(archive)?.ok_or(AppError::ArchiveUnsupported)?;
+    if target.exists() {
+        return Err(AppError::ExtractTargetExists);
+    }
+
+    let folder_name = target
+        .file_name()
+        .ok_or(AppError::ExtractTargetExists)?
+        .to_string_lossy();
+    let staging = target.with_file_name(format!(".{}.extracting", folder_name));
+    std::fs::create_dir(&staging).map_err(|e| match e.kind() {
+        // Another upload of the same name is being extracted right now
+        std::io::ErrorKind::AlreadyExists => AppError::ExtractTargetExists,
+        _ => e.into(),
+    })?;
+
+    let mut extractor = Extractor {
+        root: &staging,
+        limits,
+        has_free_space,
+        entries: 0,
+        size: 0,
+        files: Vec::new(),
+    };
+    let result = File::open(archive)
+        .map_err(AppError::from)
+        .and_then(|file| match format {
+            ArchiveFormat::Zip => extractor.extract_zip(file),
+            ArchiveFormat::Tar => extractor.extract_tar(file),
+            ArchiveFormat::TarGz => extractor.extract_tar(MultiGzDecoder::new(file)),
+            ArchiveFormat::TarZst => extractor.extract_tar(zstd::Decoder::new(file)?),
+        })
+        .and_then(|()| std::fs::rename(&staging, target).map_err(AppError::from));
+
+    if let Err(e) = result {
+        if let Err(e) = std::fs::remove_dir_all(&staging) {
+            log::error!(
+                "Failed to remove partial extraction {}: {}",
+                staging.display(),
+                e
+            );
+        }
+        return Err(e);
+    }
+    Ok(extractor.files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use tempfile::TempDir;
+
+    const LIMITS: ExtractLimits = ExtractLimits {
+        max_size: 1024,
+        max_entries: 8,
+    };
+
+    fn zip_archive(build: impl FnOnce(&mut zip::ZipWriter<Cursor<Vec<u8>>>)) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        build(&mut writer);
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn zip_options() -> zip::write::SimpleFileOptions {
+        zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored)
+    }
+
+    // Tar entry with the name written as is, the builder refuses names like "../x"
+    fn tar_entry(
+        builder: &mut tar::Builder<Vec<u8>>,
+        name: &str,
+        kind: tar::EntryType,
+        data: &[u8],
+    ) {
+        let mut header = tar::Header::new_ustar();
+        header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_entry_type(kind);
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append(&header, data).unwrap();
+    }
+
+    fn tar_archive(entries: &[(&str, tar::EntryType, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, kind, data) in entries {
+            tar_entry(&mut builder, name, *kind, data);
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn run(
+        dir: &TempDir,
+        archive: &[u8],
+        limits: ExtractLimits,
+    ) -> Result<Vec<ExtractedFile>, AppError> {
+        let archive_path = dir.path().join("upload");
+        std::fs::write(&archive_path, archive).unwrap();
+        extract(&archive_path, &dir.path().join("out"), limits, |_| true)
+    }
+
+    // A failed extraction leaves neither the target nor the staging folder behind
+    fn assert_nothing_left(dir: &TempDir) {
+        assert!(!dir.path().join("out").exists());
+        assert!(!dir.path().join(".out.extracting").exists());
+    }
+
+    #[test]
+    fn target_folder_names() {
+        let cases = [
+            ("photos.zip", "photos"),
+            ("Photos.TAR.GZ", "Photos"),
+            ("src.tgz", "src"),
+            ("backup.tar.zst", "backup"),
+            ("data.tar", "data"),
+            ("archive.bin", "archive"),
+            ("archive", "archive_extracted"),
+            (".zip", ".zip_extracted"),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(target_folder(name), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn entry_paths() {
+        let mut cases = vec![
+            ("a/b.txt", Some(Some("a/b.txt"))),
+            ("./a/./b.txt", Some(Some("a/b.txt"))),
+            ("./", Some(None)),
+            ("../evil.txt", None),
+            ("a/../../evil.txt", None),
+            ("/etc/passwd", None),
+        ];
+        if cfg!(windows) {
+            cases.push((r"C:\evil.txt", None));
+            cases.push((r"\\server\share\evil.txt", None));
+        }
+
+        let root = Path::new("root");
+        for (name, expected) in cases {
+            let mut extractor = Extractor {
+                root,
+                limits: LIMITS,
+                has_free_space: |_| true,
+                entries: 0,
+                size: 0,
+                files: Vec::new(),
+            };
+            let result = extractor.entry_path(Path::new(name));
+            match expected {
+                Some(path) => assert_eq!(result.unwrap(), path.map(PathBuf::from), "{}", name),
+                None => assert!(
+                    matches!(result, Err(AppError::ArchiveEntryRejected(_))),
+                    "{}",
+                    name
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn extracts_zip() {
+        let dir = TempDir::new().unwrap();
+        let archive = zip_archive(|writer| {
+            writer.add_directory("docs/", zip_options()).unwrap();
+            writer.start_file("docs/readme.txt", zip_options()).unwrap();
+            writer.write_all(b"hello").unwrap();
+        });
+
+        let files = run(&dir, &archive, LIMITS).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, Path::new("docs/readme.txt"));
+        assert_eq!(files[0].size, 5);
+        assert_eq!(
+            std::fs::read(dir.path().join("out/docs/readme.txt")).unwrap(),
+            b"hello"
+        );
+        assert!(!dir.path().join(".out.extracting").exists());
+    }
+
+    #[test]
+    fn extracts_tar_gz() {
+        let dir = TempDir::new().unwrap();
+        let tar = tar_archive(&[
+            ("./", tar::EntryType::Directory, b""),
+            ("a/b.txt", tar::EntryType::Regular, b"content"),
+        ]);
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(&tar).unwrap();
+
+        let files = run(&dir, &encoder.finish().unwrap(), LIMITS).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(
+            std::fs::read(dir.path().join("out/a/b.txt")).unwrap(),
+            b"content"
+        );
+    }
+
+    #[test]
+    fn rejects_escaping_zip_entries() {
+        for name in ["../evil.txt", "a/../../evil.txt", "/tmp/evil.txt"] {
+            let dir = TempDir::new().unwrap();
+            let archive = zip_archive(|writer| {
+                writer.start_file(name, zip_options()).unwrap();
+                writer.write_all(b"evil").unwrap();
+            });
+
+            let result = run(&dir, &archive, LIMITS);
+            assert!(
+                matches!(result, Err(AppError::ArchiveEntryRejected(_))),
+                "{}",
+                name
+            );
+            assert_nothing_left(&dir);
+            assert!(!dir.path().join("evil.txt").exists());
+        }
+    }
+
+    #[test]
+    fn rejects_escaping_tar_entries() {
+        for name in ["../evil.txt", "a/../../evil.txt", "/tmp/evil.txt"] {
+            let dir = TempDir::new().unwrap();
+            let archive = tar_archive(&[(name, tar::EntryType::Regular, b"evil")]);
+
+            let result = run(&dir, &archive, LIMITS);
+            assert!(
+                matches!(result, Err(AppError::ArchiveEntryRejected(_))),
+                "{}",
+                name
+            );
+            assert_nothing_left(&dir);
+            assert!(!dir.path().join("evil.txt").exists());
+        }
+    }
+
+    #[test]
+    fn rejects_zip_symlinks() {
+        let dir = TempDir::new().unwrap();
+        let archive = zip_archive(|writer| {
+            writer
+                .add_symlink("link", "/etc/passwd", zip_options())
+                .unwrap();
+        });
+
+        let result = run(&dir, &archive, LIMITS);
+        assert!(matches!(result, Err(AppError::ArchiveEntryRejected(_))));
+        assert_nothing_left(&dir);
+    }
+
+    #[test]
+    fn rejects_tar_links() {
+        for kind in [tar::EntryType::Symlink, tar::EntryType::Link] {
+            let dir = TempDir::new().unwrap();
+            let mut builder = tar::Builder::new(Vec::new());
+            tar_entry(&mut builder, "a.txt", tar::EntryType::Regular, b"a");
+            let mut header = tar::Header::new_ustar();
+            header.set_entry_type(kind);
+            header.set_size(0);
+            builder
+                .append_link(&mut header, "link", "/etc/passwd")
+                .unwrap();
+            let archive = builder.into_inner().unwrap();
+
+            let result = run(&dir, &archive, LIMITS);
+            assert!(
+                matches!(result, Err(AppError::ArchiveEntryRejected(_))),
+                "{:?}",
+                kind
+            );
+            assert_nothing_left(&dir);
+        }
+    }
+
+    #[test]
+    fn rejects_duplicate_entries() {
+        let dir = TempDir::new().unwrap();
+        let archive = tar_archive(&[
+            ("a.txt", tar::EntryType::Regular, b"first"),
+            ("a.txt", tar::EntryType::Regular, b"second"),
+        ]);
+
+        let result = run(&dir, &archive, LIMITS);
+        assert!(matches!(result, Err(AppError::ArchiveInvalid(_))));
+        assert_nothing_left(&dir);
+    }
+
+    #[test]
+    fn enforces_entry_limit() {
+        let limits = ExtractLimits {
+            max_entries: 2,
+            ..LIMITS
+        };
+
+        let dir = TempDir::new().unwrap();
+        let archive = zip_archive(|writer| {
+            for name in ["a", "b", "c"] {
+                writer.start_file(name, zip_options()).unwrap();
+            }
+        });
+        let result = run(&dir, &archive, limits);
+        assert!(matches!(result, Err(AppError::ExtractLimitExceeded)));
+        assert_nothing_left(&dir);
+
+        // Directories count as entries, too
+        let dir = TempDir::new().unwrap();
+        let archive = tar_archive(&[
+            ("a/", tar::EntryType::Directory, b""),
+            ("b/", tar::EntryType::Directory, b""),
+            ("a/c.txt", tar::EntryType::Regular, b"c"),
+        ]);
+        let result = run(&dir, &archive, limits);
+        assert!(matches!(result, Err(AppError::ExtractLimitExceeded)));
+        assert_nothing_left(&dir);
+    }
+
+    #[test]
+    fn enforces_size_limit() {
+        let limits = ExtractLimits {
+            max_size: 10,
+            ..LIMITS
+        };
+
+        // A single file above the limit
+        let dir = TempDir::new().unwrap();
+        let archive = tar_archive(&[("a.txt", tar::EntryType::Regular, &[b'a'; 11])]);
+        let result = run(&dir, &archive, limits);
+        assert!(matches!(result, Err(AppError::ExtractLimitExceeded)));
+        assert_nothing_left(&dir);
+
+        // Files below the limit on their own, but not together
+        let dir = TempDir::new().unwrap();
+        let archive = zip_archive(|writer| {
+            for name in ["a.txt", "b.txt"] {
+                writer.start_file(name, zip_options()).unwrap();
+                writer.write_all(&[b'x'; 6]).unwrap();
+            }
+        });
+        let result = run(&dir, &archive, limits);
+        assert!(matches!(result, Err(AppError::ExtractLimitExceeded)));
+        assert_nothing_left(&dir);
+
+        // Exactly at the limit
+        let dir = TempDir::new().unwrap();
+        let archive = tar_archive(&[("a.txt", tar::EntryType::Regular, &[b'a'; 10])]);
+        assert_eq!(run(&dir, &archive, limits).unwrap()[0].size, 10);
+    }
+
+    #[test]
+    fn checks_free_space() {
+        let dir = TempDir::new().unwrap();
+        let archive = tar_archive(&[("a.txt", tar::EntryType::Regular, b"content")]);
+        let archive_path = dir.path().join("upload");
+        std::fs::write(&archive_path, archive).unwrap();
+
+        let result = extract(&archive_path, &dir.path().join("out"), LIMITS, |_| false);
+        assert!(matches!(result, Err(AppError::InsufficientStorage)));
+        assert_nothing_left(&dir);
+    }
+
+    #[test]
+    fn refuses_existing_target_and_other_formats() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir(dir.path().join("out")).unwrap();
+        let archive = tar_archive(&[("a.txt", tar::EntryType::Regular, b"a")]);
+        let result = run(&dir, &archive, LIMITS);
+        assert!(matches!(result, Err(AppError::ExtractTargetExists)));
+
+        let dir = TempDir::new().unwrap();
+        let result = run(&dir, b"just some text", LIMITS);
+        assert!(matches!(result, Err(AppError::ArchiveUnsupported)));
+    }
+}
@@ -0,0 +1,186 @@
+use actix_web::HttpRequest;
+use ipnet::IpNet;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+use crate::utils::proxy::client_ip;
+
+// Hex characters of the token hash used as folder name
+const NAMESPACE_LEN: usize = 16;
+
+// Write-only directory, every token gets a folder of its own below it
+#[derive(Clone, Debug)]
+pub struct Inbox {
+    pub dir: PathBuf,            // Canonical path
+    token_hashes: Vec<[u8; 32]>, // Tokens are only kept hashed
+}
+
+impl Inbox {
+    // Folder the uploads made with `token` go to, None if the token is not valid here.
+    // Named after the token hash, so the listing never reveals a token.
+    pub fn namespace(&self, token: &str) -> Option<String> {
+        let hash: [u8; 32] = Sha256::digest(token.as_bytes()).into();
+        if !self.token_hashes.contains(&hash) {
+            return None;
+        }
+
+        let hex: String = hash.iter().map(|byte| format!("{:02x}", byte)).collect();
+        Some(hex[..NAMESPACE_LEN].to_string())
+    }
+}
+
+// Inbox directories plus the clients allowed to look inside them
+#[derive(Clone, Debug, Default)]
+pub struct Inboxes {
+    inboxes: Vec<Inbox>,
+    admins: Vec<IpNet>,
+}
+
+impl Inboxes {
+    // `dir_tokens` holds canonical directories, a directory may be given several tokens
+    pub fn new(dir_tokens: Vec<(PathBuf, String)>, admins: Vec<IpNet>) -> Self {
+        let mut inboxes: Vec<Inbox> = Vec::new();
+        for (dir, token) in dir_tokens {
+            let hash = Sha256::digest(token.as_bytes()).into();
+            match inboxes.iter_mut().find(|inbox| inbox.dir == dir) {
+                Some(inbox) => inbox.token_hashes.push(hash),
+                None => inboxes.push(Inbox {
+                    dir,
+                    token_hashes: vec![hash],
+                }),
+            }
+        }
+        Inboxes { inboxes, admins }
+    }
+
+    // Inbox `path` lies in, the most specific one if inboxes are nested
+    pub fn find(&self, path: &Path) -> Option<&Inbox> {
+        self.inboxes
+            .iter()
+            .filter(|inbox| path.starts_with(&inbox.dir))
+            .max_by_key(|inbox| inbox.dir.components().count())
+    }
+
+    pub fn is_admin(&self, req: &HttpRequest) -> bool {
+        client_ip(req).is_some_and(|ip| {
+            let ip = ip.to_canonical();
+            self.admins.iter().any(|net| net.contains(&ip))
+        })
+    }
+
+    // Inbox contents are hidden from everyone but admins
+    pub fn can_view(&self, req: &HttpRequest, path: &Path) -> bool {
+        self.find(path).is_none() || self.is_admin(req)
+    }
+}
+
+// Parse a "DIR=TOKEN" pair used for inbox directories
+pub fn parse_dir_token(value: &str) -> Result<(String, String), String> {
+    let (dir, token) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Expected DIR=TOKEN, got '{}'", value))?;
+    if token.is_empty() {
+        return Err(format!("Inbox '{}' needs a non-empty token", dir));
+    }
+    Ok((dir.to_string(), token.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn inboxes() -> Inboxes {
+        Inboxes::new(
+            vec![
+                (PathBuf::from("/srv/inbox"), "first".to_string()),
+                (PathBuf::from("/srv/inbox"), "second".to_string()),
+                (PathBuf::from("/srv/inbox/team"), "team".to_string()),
+            ],
+            vec![
+                "192.168.1.0/24".parse().unwrap(),
+                "::1/128".parse().unwrap(),
+            ],
+        )
+    }
+
+    #[test]
+    fn finds_the_most_specific_inbox() {
+        let inboxes = inboxes();
+        let cases = [
+            ("/srv/inbox", Some("/srv/inbox")),
+            ("/srv/inbox/a/b", Some("/srv/inbox")),
+            ("/srv/inbox/team", Some("/srv/inbox/team")),
+            ("/srv/inbox/team/x", Some("/srv/inbox/team")),
+            ("/srv/inboxes", None),
+            ("/srv", None),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(
+                inboxes
+                    .find(Path::new(path))
+                    .map(|inbox| inbox.dir.as_path()),
+                expected.map(Path::new),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn gives_every_token_its_own_folder() {
+        let inboxes = inboxes();
+        let inbox = inboxes.find(Path::new("/srv/inbox")).unwrap();
+
+        let first = inbox.namespace("first").unwrap();
+        let second = inbox.namespace("second").unwrap();
+        assert_eq!(first.len(), NAMESPACE_LEN);
+        assert_ne!(first, second);
+        assert_eq!(inbox.namespace("first"), Some(first.clone()));
+        assert!(!first.contains("first"));
+
+        // Tokens only work for their own inbox
+        for token in ["", "team", "First", "first "] {
+            assert_eq!(inbox.namespace(token), None, "{}", token);
+        }
+    }
+
+    #[test]
+    fn shows_inboxes_to_admins_only() {
+        let inboxes = inboxes();
+        let cases = [
+            ("192.168.1.20:5000", "/srv/inbox/a", true),
+            ("[::1]:5000", "/srv/inbox", true),
+            ("[::ffff:192.168.1.20]:5000", "/srv/inbox", true),
+            ("192.168.2.20:5000", "/srv/inbox/a", false),
+            ("192.168.2.20:5000", "/srv/public", true),
+        ];
+        for (peer, path, expected) in cases {
+            let req = TestRequest::default()
+                .peer_addr(peer.parse().unwrap())
+                .to_http_request();
+            assert_eq!(
+                inboxes.can_view(&req, Path::new(path)),
+                expected,
+                "{} {}",
+                peer,
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn parses_dir_tokens() {
+        assert_eq!(
+            parse_dir_token("inbox=s3cret"),
+            Ok(("inbox".to_string(), "s3cret".to_string()))
+        );
+        assert_eq!(
+            parse_dir_token("a/b=x=y"),
+            Ok(("a/b".to_string(), "x=y".to_string()))
+        );
+        for value in ["inbox", "inbox="] {
+            assert!(parse_dir_token(value).is_err(), "{}", value);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::path::{Path, PathBuf};
+
+use crate::utils::utils::parse_size;
+
+// Size and disk space limits applied to uploads
+#[derive(Clone, Debug, Default)]
+pub struct UploadLimits {
+    pub max_size: Option<u64>,              // Global maximum body size
+    pub dir_max_sizes: Vec<(PathBuf, u64)>, // Per directory maximum body size (canonical paths)
+    pub min_free_space: Option<u64>,        // Bytes that must stay free on the target filesystem
+}
+
+impl UploadLimits {
+    // Resolve the effective maximum size for an upload into `target_dir`.
+    // The most specific directory limit wins over the global one.
+    pub fn max_size_for(&self, target_dir: &Path) -> Option<u64> {
+        self.dir_max_sizes
+            .iter()
+            .filter(|(dir, _)| target_dir.starts_with(dir))
+            .max_by_key(|(dir, _)| dir.components().count())
+            .map(|(_, size)| *size)
+            .or(self.max_size)
+    }
+
+    // Check whether writing `incoming` more bytes would eat into the free space reserve
+    pub fn has_free_space(&self, target_dir: &Path, incoming: u64) -> bool {
+        let Some(reserve) = self.min_free_space else {
+            return true;
+        };
+
+        match fs4::available_space(target_dir) {
+            Ok(available) => available.saturating_sub(incoming) >= reserve,
+            Err(e) => {
+                log::error!(
+                    "Could not query free space for {}: {}",
+                    target_dir.display(),
+                    e
+                );
+                false
+            }
+        }
+    }
+}
+
+// Parse a "DIR=SIZE" pair used for per directory upload limits
+pub fn parse_dir_size(value: &str) -> Result<(String, u64), String> {
+    let (dir, size) = value
+        .rsplit_once('=')
+        .ok_or_else(|| format!("Expected DIR=SIZE, got '{}'", value))?;
+    Ok((dir.to_string(), parse_size(size)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_the_most_specific_dir_limit() {
+        let limits = UploadLimits {
+            max_size: Some(100),
+            dir_max_sizes: vec![
+                (PathBuf::from("/srv/media"), 1000),
+                (PathBuf::from("/srv/media/small"), 10),
+                (PathBuf::from("/srv/docs"), 50),
+            ],
+            min_free_space: None,
+        };
+
+        let cases = [
+            ("/srv", Some(100)),
+            ("/srv/other", Some(100)),
+            ("/srv/media", Some(1000)),
+            ("/srv/media/video/2024", Some(1000)),
+            ("/srv/media/small", Some(10)),
+            ("/srv/media/small/thumbs", Some(10)),
+            ("/srv/docs", Some(50)),
+            // Only whole components match
+            ("/srv/documents", Some(100)),
+        ];
+        for (dir, expected) in cases {
+            assert_eq!(limits.max_size_for(Path::new(dir)), expected, "{}", dir);
+        }
+
+        let unlimited = UploadLimits {
+            max_size: None,
+            ..limits
+        };
+        assert_eq!(unlimited.max_size_for(Path::new("/srv/other")), None);
+        assert_eq!(unlimited.max_size_for(Path::new("/srv/docs")), Some(50));
+    }
+
+    #[test]
+    fn parses_dir_sizes() {
+        let cases = [
+            ("uploads=10M", ("uploads", 10 * 1024 * 1024)),
+            ("a=b=1K", ("a=b", 1024)),
+            ("=5", ("", 5)),
+        ];
+        for (value, (dir, size)) in cases {
+            assert_eq!(
+                parse_dir_size(value),
+                Ok((dir.to_string(), size)),
+                "{}",
+                value
+            );
+        }
+
+        for value in ["uploads", "uploads=", "uploads=10X", "uploads=-1"] {
+            assert!(parse_dir_size(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn free_space_without_reserve() {
+        let limits = UploadLimits::default();
+        assert!(limits.has_free_space(Path::new("/nonexistent"), u64::MAX));
+
+        // Unknown free space counts as none
+        let limits = UploadLimits {
+            min_free_space: Some(1),
+            ..UploadLimits::default()
+        };
+        assert!(!limits.has_free_space(Path::new("/nonexistent/holonet"), 0));
+    }
+}
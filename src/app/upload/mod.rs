@@ -1,2 +1,7 @@
+pub mod decompress;
+pub mod extract;
+pub mod inbox;
+pub mod limits;
+pub mod quota;
 pub mod urls;
 pub mod views;
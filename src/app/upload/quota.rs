@@ -0,0 +1,768 @@
+use actix_web::HttpRequest;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::utils::proxy::client_ip;
+
+// Byte and file count budget
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct QuotaLimit {
+    pub max_bytes: Option<u64>,
+    pub max_files: Option<u64>,
+}
+
+impl QuotaLimit {
+    pub fn is_set(&self) -> bool {
+        self.max_bytes.is_some() || self.max_files.is_some()
+    }
+}
+
+// Bytes and files currently accounted to a user or directory
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct Usage {
+    pub bytes: u64,
+    pub files: u64,
+}
+
+impl Usage {
+    fn add(&mut self, size: u64) {
+        self.bytes += size;
+        self.files += 1;
+    }
+
+    fn remove(&mut self, size: u64) {
+        self.bytes = self.bytes.saturating_sub(size);
+        self.files = self.files.saturating_sub(1);
+    }
+}
+
+// Usage shown in the listing header
+#[derive(Serialize)]
+pub struct UsageSummary {
+    pub user: Usage,
+    pub user_limit: QuotaLimit,
+    pub dir_name: String,
+    pub dir: Usage,
+    pub dir_limit: QuotaLimit,
+}
+
+#[derive(Debug)]
+pub enum QuotaError {
+    UserBytes,
+    UserFiles,
+    DirBytes,
+    DirFiles,
+}
+
+impl std::fmt::Display for QuotaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            QuotaError::UserBytes => write!(f, "User storage quota exceeded"),
+            QuotaError::UserFiles => write!(f, "User file count quota exceeded"),
+            QuotaError::DirBytes => write!(f, "Directory storage quota exceeded"),
+            QuotaError::DirFiles => write!(f, "Directory file count quota exceeded"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct FileRecord {
+    owner: String,
+    size: u64,
+}
+
+// On-disk format of the usage index
+#[derive(Default, Serialize, Deserialize)]
+struct IndexFile {
+    files: BTreeMap<String, FileRecord>, // Keyed by path relative to the root directory
+}
+
+struct UsageIndex {
+    files: BTreeMap<String, FileRecord>,
+    users: HashMap<String, Usage>,
+    dirs: HashMap<String, Usage>,
+    version: u64, // Bumped on every change, tells snapshots apart
+}
+
+impl UsageIndex {
+    fn from_records(files: BTreeMap<String, FileRecord>) -> Self {
+        let mut index = UsageIndex {
+            files: BTreeMap::new(),
+            users: HashMap::new(),
+            dirs: HashMap::new(),
+            version: 0,
+        };
+        for (path, record) in files {
+            index.insert(path, record);
+        }
+        index
+    }
+
+    fn insert(&mut self, path: String, record: FileRecord) {
+        self.remove(&path);
+        self.users
+            .entry(record.owner.clone())
+            .or_default()
+            .add(record.size);
+        self.dirs
+            .entry(top_level_dir(&path))
+            .or_default()
+            .add(record.size);
+        self.files.insert(path, record);
+    }
+
+    fn remove(&mut self, path: &str) -> Option<FileRecord> {
+        let record = self.files.remove(path)?;
+        if let Some(usage) = self.users.get_mut(&record.owner) {
+            usage.remove(record.size);
+        }
+        if let Some(usage) = self.dirs.get_mut(&top_level_dir(path)) {
+            usage.remove(record.size);
+        }
+        Some(record)
+    }
+
+    fn user_usage(&self, user: &str) -> Usage {
+        self.users.get(user).copied().unwrap_or_default()
+    }
+
+    fn dir_usage(&self, dir: &str) -> Usage {
+        self.dirs.get(dir).copied().unwrap_or_default()
+    }
+}
+
+// Per user and per top-level directory quotas backed by a persisted usage index.
+// Only uploads made through the server are accounted.
+pub struct Quotas {
+    pub user_limit: QuotaLimit,
+    pub dir_limit: QuotaLimit,
+    base_path: PathBuf,
+    index_path: PathBuf,
+    index: Mutex<UsageIndex>,
+    persisted: Mutex<u64>, // Version of the index last written to disk
+}
+
+impl Quotas {
+    pub fn load(
+        base_path: PathBuf,
+        index_path: PathBuf,
+        user_limit: QuotaLimit,
+        dir_limit: QuotaLimit,
+    ) -> std::io::Result<Self> {
+        let records = match std::fs::read(&index_path) {
+            Ok(content) => {
+                serde_json::from_slice::<IndexFile>(&content)
+                    .map_err(|e| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("Usage index {}: {}", index_path.display(), e),
+                        )
+                    })?
+                    .files
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+
+        let quotas = Quotas {
+            user_limit,
+            dir_limit,
+            base_path,
+            index_path,
+            index: Mutex::new(UsageIndex::from_records(records)),
+            persisted: Mutex::new(0),
+        };
+
+        // Forget files that were removed while the server was not running
+        quotas.prune();
+
+        Ok(quotas)
+    }
+
+    // Path of a file relative to the root directory, as used for the index keys
+    pub fn relative_key(&self, file_path: &Path) -> Option<String> {
+        file_path
+            .strip_prefix(&self.base_path)
+            .ok()
+            .map(|p| p.to_string_lossy().replace("\\", "/"))
+    }
+
+    // Check whether `user` may store `key` and return how many bytes it may grow to
+    pub fn allowance(&self, user: &str, key: &str) -> Result<Option<u64>, QuotaError> {
+        match self.try_allowance(user, key) {
+            Ok(allowance) => Ok(allowance),
+            Err(_) => {
+                // Files may have been removed out of band, re-check against the disk once
+                self.prune();
+                self.try_allowance(user, key)
+            }
+        }
+    }
+
+    fn try_allowance(&self, user: &str, key: &str) -> Result<Option<u64>, QuotaError> {
+        let index = self.index.lock().unwrap();
+
+        // An overwritten file gives its previous size back
+        let previous = index.files.get(key);
+        let replaced_size = |owner_matches: bool| match previous {
+            Some(record) if owner_matches => record.size,
+            _ => 0,
+        };
+
+        let user_usage = index.user_usage(user);
+        let user_is_owner = previous.is_some_and(|r| r.owner == user);
+        let user_remaining = check_limit(
+            &self.user_limit,
+            user_usage,
+            replaced_size(user_is_owner),
+            user_is_owner,
+        )
+        .map_err(|bytes| {
+            if bytes {
+                QuotaError::UserBytes
+            } else {
+                QuotaError::UserFiles
+            }
+        })?;
+
+        let dir_usage = index.dir_usage(&top_level_dir(key));
+        let dir_remaining = check_limit(
+            &self.dir_limit,
+            dir_usage,
+            replaced_size(true),
+            previous.is_some(),
+        )
+        .map_err(|bytes| {
+            if bytes {
+                QuotaError::DirBytes
+            } else {
+                QuotaError::DirFiles
+            }
+        })?;
+
+        Ok(match (user_remaining, dir_remaining) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        })
+    }
+
+    // Account an uploaded file. Checked against the quotas again, as uploads running
+    // at the same time were each allowed the same remaining bytes.
+    pub fn record_upload(&self, user: &str, key: &str, size: u64) -> Result<(), QuotaError> {
+        self.record_files(user, &[(key.to_string(), size)])
+    }
+
+    // Account files created together, e.g. extracted from an archive, all or none.
+    // They are checked against the quotas once written and left out if they do not fit.
+    pub fn record_files(&self, user: &str, files: &[(String, u64)]) -> Result<(), QuotaError> {
+        let mut index = self.index.lock().unwrap();
+        for (key, size) in files {
+            index.insert(
+                key.clone(),
+                FileRecord {
+                    owner: user.to_string(),
+                    size: *size,
+                },
+            );
+        }
+
+        let dirs: HashSet<String> = files.iter().map(|(key, _)| top_level_dir(key)).collect();
+        let result = over_limit(
+            &self.user_limit,
+            index.user_usage(user),
+            QuotaError::UserBytes,
+            QuotaError::UserFiles,
+        )
+        .and_then(|()| {
+            dirs.iter().try_for_each(|dir| {
+                over_limit(
+                    &self.dir_limit,
+                    index.dir_usage(dir),
+                    QuotaError::DirBytes,
+                    QuotaError::DirFiles,
+                )
+            })
+        });
+
+        if result.is_err() {
+            for (key, _) in files {
+                index.remove(key);
+            }
+            return result;
+        }
+        self.persist(index);
+        Ok(())
+    }
+
+    pub fn record_delete(&self, key: &str) {
+        let mut index = self.index.lock().unwrap();
+        if index.remove(key).is_some() {
+            self.persist(index);
+        }
+    }
+
+    // Usage of `user` and of the top-level directory containing the listed `dir`
+    pub fn summary(&self, user: &str, dir: &Path) -> UsageSummary {
+        let index = self.index.lock().unwrap();
+        let dir_name = dir
+            .components()
+            .find_map(|c| match c {
+                Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+                _ => None,
+            })
+            .unwrap_or_default();
+        UsageSummary {
+            user: index.user_usage(user),
+            user_limit: self.user_limit,
+            dir: index.dir_usage(&dir_name),
+            dir_name,
+            dir_limit: self.dir_limit,
+        }
+    }
+
+    // Drop records of files which no longer exist
+    fn prune(&self) {
+        let mut index = self.index.lock().unwrap();
+        let missing: Vec<String> = index
+            .files
+            .keys()
+            .filter(|key| !self.base_path.join(key).is_file())
+            .cloned()
+            .collect();
+
+        if missing.is_empty() {
+            return;
+        }
+
+        for key in &missing {
+            log::debug!("Removing deleted file '{}' from usage index", key);
+            index.remove(key);
+        }
+        self.persist(index);
+    }
+
+    // Write a snapshot of the index. The file is written after the index lock is
+    // released, so usage checks never wait for the disk.
+    fn persist(&self, mut index: MutexGuard<UsageIndex>) {
+        index.version += 1;
+        let version = index.version;
+        let content = IndexFile {
+            files: index.files.clone(),
+        };
+        drop(index);
+
+        // Snapshots taken at the same time may arrive out of order, keep the newest
+        let mut persisted = self.persisted.lock().unwrap();
+        if *persisted >= version {
+            return;
+        }
+
+        let result = (|| -> std::io::Result<()> {
+            if let Some(parent) = self.index_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            // Write to a temporary file first so a crash never leaves a truncated index
+            let tmp_path = self.index_path.with_extension("tmp");
+            std::fs::write(&tmp_path, serde_json::to_vec(&content)?)?;
+            std::fs::rename(&tmp_path, &self.index_path)
+        })();
+
+        match result {
+            Ok(()) => *persisted = version,
+            Err(e) => log::error!(
+                "Failed to write usage index {}: {}",
+                self.index_path.display(),
+                e
+            ),
+        }
+    }
+}
+
+// Returns the remaining byte allowance, or Err(true) / Err(false) if the byte / file budget is used up
+fn check_limit(
+    limit: &QuotaLimit,
+    usage: Usage,
+    replaced_size: u64,
+    replaces_file: bool,
+) -> Result<Option<u64>, bool> {
+    if let Some(max_files) = limit.max_files
+        && !replaces_file
+        && usage.files >= max_files
+    {
+        return Err(false);
+    }
+
+    match limit.max_bytes {
+        Some(max_bytes) => {
+            let used = usage.bytes.saturating_sub(replaced_size);
+            if used >= max_bytes {
+                Err(true)
+            } else {
+                Ok(Some(max_bytes - used))
+            }
+        }
+        None => Ok(None),
+    }
+}
+
+// Usage that has already grown beyond a limit
+fn over_limit(
+    limit: &QuotaLimit,
+    usage: Usage,
+    bytes_error: QuotaError,
+    files_error: QuotaError,
+) -> Result<(), QuotaError> {
+    if limit
+        .max_bytes
+        .is_some_and(|max_bytes| usage.bytes > max_bytes)
+    {
+        return Err(bytes_error);
+    }
+    if limit
+        .max_files
+        .is_some_and(|max_files| usage.files > max_files)
+    {
+        return Err(files_error);
+    }
+    Ok(())
+}
+
+// Name of the top-level directory a relative path belongs to, empty for files in the root
+fn top_level_dir(key: &str) -> String {
+    let path = Path::new(key);
+    let mut components = path
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)));
+    match (components.next(), components.next()) {
+        (Some(first), Some(_)) => first.as_os_str().to_string_lossy().into_owned(),
+        _ => String::new(),
+    }
+}
+
+// There is no authentication, so users are told apart by their remote address
+pub fn user_key(req: &HttpRequest) -> String {
+    client_ip(req)
+        .map(|ip| ip.to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const fn limit(max_bytes: Option<u64>, max_files: Option<u64>) -> QuotaLimit {
+        QuotaLimit {
+            max_bytes,
+            max_files,
+        }
+    }
+
+    const fn usage(bytes: u64, files: u64) -> Usage {
+        Usage { bytes, files }
+    }
+
+    // Quotas over a fresh root directory
+    struct TestQuotas {
+        root: TempDir,
+        quotas: Quotas,
+    }
+
+    impl TestQuotas {
+        fn new(user_limit: QuotaLimit, dir_limit: QuotaLimit) -> Self {
+            let root = TempDir::new().unwrap();
+            let quotas = Self::load(&root, user_limit, dir_limit);
+            TestQuotas { root, quotas }
+        }
+
+        fn load(root: &TempDir, user_limit: QuotaLimit, dir_limit: QuotaLimit) -> Quotas {
+            Quotas::load(
+                root.path().to_path_buf(),
+                root.path().join(".data/usage.json"),
+                user_limit,
+                dir_limit,
+            )
+            .unwrap()
+        }
+
+        // Upload a file the way the upload handler does, so pruning keeps it
+        fn upload(&self, user: &str, key: &str, size: u64) {
+            let path = self.root.path().join(key);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, vec![0u8; size as usize]).unwrap();
+            self.quotas.record_upload(user, key, size).unwrap();
+        }
+
+        fn user_usage(&self, user: &str) -> (u64, u64) {
+            let usage = self.quotas.index.lock().unwrap().user_usage(user);
+            (usage.bytes, usage.files)
+        }
+
+        fn dir_usage(&self, dir: &str) -> (u64, u64) {
+            let usage = self.quotas.index.lock().unwrap().dir_usage(dir);
+            (usage.bytes, usage.files)
+        }
+    }
+
+    #[test]
+    fn checks_limits() {
+        // Limit, usage, replaced size, whether a file is replaced, then the result
+        let cases = [
+            (limit(None, None), usage(500, 50), 0, false, Ok(None)),
+            (limit(Some(100), None), usage(0, 0), 0, false, Ok(Some(100))),
+            (limit(Some(100), None), usage(60, 1), 0, false, Ok(Some(40))),
+            (limit(Some(100), None), usage(100, 1), 0, false, Err(true)),
+            (limit(Some(100), None), usage(150, 2), 0, false, Err(true)),
+            // Replacing a file gives its size back
+            (
+                limit(Some(100), None),
+                usage(100, 1),
+                60,
+                true,
+                Ok(Some(60)),
+            ),
+            (
+                limit(Some(100), None),
+                usage(10, 1),
+                60,
+                true,
+                Ok(Some(100)),
+            ),
+            (limit(None, Some(2)), usage(0, 1), 0, false, Ok(None)),
+            (limit(None, Some(2)), usage(0, 2), 0, false, Err(false)),
+            // Replacing a file does not add one
+            (limit(None, Some(2)), usage(0, 2), 0, true, Ok(None)),
+            // The file count is checked first
+            (
+                limit(Some(100), Some(1)),
+                usage(100, 1),
+                0,
+                false,
+                Err(false),
+            ),
+            (limit(Some(100), Some(1)), usage(100, 1), 0, true, Err(true)),
+        ];
+
+        for (limit, usage, replaced_size, replaces_file, expected) in cases {
+            assert_eq!(
+                check_limit(&limit, usage, replaced_size, replaces_file),
+                expected,
+                "{:?} {:?} {} {}",
+                limit,
+                usage,
+                replaced_size,
+                replaces_file
+            );
+        }
+    }
+
+    #[test]
+    fn finds_top_level_dirs() {
+        let cases = [
+            ("report.pdf", ""),
+            ("./report.pdf", ""),
+            ("docs/report.pdf", "docs"),
+            ("docs/2024/q3/report.pdf", "docs"),
+            ("./docs/report.pdf", "docs"),
+            ("", ""),
+        ];
+        for (key, expected) in cases {
+            assert_eq!(top_level_dir(key), expected, "{}", key);
+        }
+    }
+
+    #[test]
+    fn overwrites_count_against_the_right_user() {
+        let test = TestQuotas::new(limit(Some(100), Some(1)), QuotaLimit::default());
+        test.upload("alice", "docs/a.txt", 60);
+        test.upload("bob", "docs/b.txt", 30);
+
+        // Overwriting your own file gives its size and file slot back
+        assert_eq!(
+            test.quotas.allowance("alice", "docs/a.txt").unwrap(),
+            Some(100)
+        );
+        assert_eq!(
+            test.quotas.allowance("bob", "docs/b.txt").unwrap(),
+            Some(100)
+        );
+
+        // Overwriting someone else's file costs a file of your own
+        assert!(matches!(
+            test.quotas.allowance("bob", "docs/a.txt"),
+            Err(QuotaError::UserFiles)
+        ));
+        assert_eq!(
+            test.quotas.allowance("carol", "docs/a.txt").unwrap(),
+            Some(100)
+        );
+
+        // The file then moves over to its new owner
+        test.upload("carol", "docs/a.txt", 20);
+        assert_eq!(test.user_usage("alice"), (0, 0));
+        assert_eq!(test.user_usage("carol"), (20, 1));
+        assert_eq!(test.dir_usage("docs"), (50, 2));
+    }
+
+    #[test]
+    fn directory_limits_ignore_the_owner() {
+        let test = TestQuotas::new(QuotaLimit::default(), limit(Some(100), Some(2)));
+        test.upload("alice", "docs/a.txt", 70);
+        test.upload("bob", "docs/b.txt", 20);
+
+        // Whoever overwrites a file, the directory gets its size back
+        assert_eq!(
+            test.quotas.allowance("bob", "docs/a.txt").unwrap(),
+            Some(80)
+        );
+        assert!(matches!(
+            test.quotas.allowance("bob", "docs/c.txt"),
+            Err(QuotaError::DirFiles)
+        ));
+        assert_eq!(
+            test.quotas.allowance("bob", "other/c.txt").unwrap(),
+            Some(100)
+        );
+    }
+
+    #[test]
+    fn root_files_share_one_directory() {
+        let test = TestQuotas::new(QuotaLimit::default(), limit(None, Some(1)));
+        test.upload("alice", "a.txt", 10);
+        assert_eq!(test.dir_usage(""), (10, 1));
+
+        assert!(matches!(
+            test.quotas.allowance("bob", "b.txt"),
+            Err(QuotaError::DirFiles)
+        ));
+        assert!(test.quotas.allowance("bob", "docs/b.txt").is_ok());
+        assert_eq!(test.quotas.summary("bob", Path::new("")).dir.files, 1);
+        assert_eq!(test.quotas.summary("bob", Path::new("docs")).dir.files, 0);
+    }
+
+    #[test]
+    fn forgets_files_removed_from_disk() {
+        let test = TestQuotas::new(limit(None, Some(1)), QuotaLimit::default());
+        test.upload("alice", "docs/a.txt", 10);
+        std::fs::remove_file(test.root.path().join("docs/a.txt")).unwrap();
+
+        assert!(test.quotas.allowance("alice", "docs/b.txt").is_ok());
+        assert_eq!(test.user_usage("alice"), (0, 0));
+    }
+
+    #[test]
+    fn keeps_usage_across_restarts() {
+        let test = TestQuotas::new(QuotaLimit::default(), QuotaLimit::default());
+        test.upload("alice", "docs/a.txt", 10);
+        test.upload("alice", "docs/b.txt", 20);
+        test.upload("bob", "c.txt", 5);
+        test.quotas.record_delete("docs/b.txt");
+
+        let reloaded = TestQuotas::load(&test.root, QuotaLimit::default(), QuotaLimit::default());
+        let index = reloaded.index.lock().unwrap();
+        assert_eq!(index.files.len(), 2);
+        assert_eq!(index.user_usage("alice").bytes, 10);
+        assert_eq!(index.user_usage("bob").bytes, 5);
+        assert_eq!(index.dir_usage("docs").files, 1);
+    }
+
+    #[test]
+    fn finds_exceeded_limits() {
+        // Limit, usage, then whether bytes, files or nothing is over the limit
+        let cases = [
+            (limit(None, None), usage(500, 50), None),
+            (limit(Some(100), Some(2)), usage(100, 2), None),
+            (limit(Some(100), Some(2)), usage(101, 2), Some(true)),
+            (limit(Some(100), Some(2)), usage(100, 3), Some(false)),
+            (limit(Some(100), Some(2)), usage(101, 3), Some(true)),
+            (limit(None, Some(2)), usage(1000, 2), None),
+        ];
+
+        for (limit, usage, expected) in cases {
+            let result = over_limit(&limit, usage, QuotaError::DirBytes, QuotaError::DirFiles);
+            let actual = match result {
+                Ok(()) => None,
+                Err(QuotaError::DirBytes) => Some(true),
+                Err(QuotaError::DirFiles) => Some(false),
+                Err(e) => panic!("unexpected {}", e),
+            };
+            assert_eq!(actual, expected, "{:?} {:?}", limit, usage);
+        }
+    }
+
+    #[test]
+    fn rolls_back_files_over_the_limit() {
+        let test = TestQuotas::new(limit(Some(100), None), limit(None, Some(3)));
+        test.upload("alice", "docs/a.txt", 50);
+
+        // Over the user's bytes
+        let files = [
+            ("archive/one.txt".to_string(), 30),
+            ("archive/two.txt".to_string(), 30),
+        ];
+        assert!(matches!(
+            test.quotas.record_files("alice", &files),
+            Err(QuotaError::UserBytes)
+        ));
+        assert_eq!(test.user_usage("alice"), (50, 1));
+        assert_eq!(test.dir_usage("archive"), (0, 0));
+        assert!(
+            !test
+                .quotas
+                .index
+                .lock()
+                .unwrap()
+                .files
+                .contains_key("archive/one.txt")
+        );
+
+        // Over the file count of a directory
+        let files = [
+            ("docs/x/1.txt".to_string(), 1),
+            ("docs/x/2.txt".to_string(), 1),
+            ("docs/x/3.txt".to_string(), 1),
+        ];
+        assert!(matches!(
+            test.quotas.record_files("alice", &files),
+            Err(QuotaError::DirFiles)
+        ));
+        assert_eq!(test.user_usage("alice"), (50, 1));
+        assert_eq!(test.dir_usage("docs"), (50, 1));
+
+        // Within every limit
+        assert!(test.quotas.record_files("alice", &files[..2]).is_ok());
+        assert_eq!(test.user_usage("alice"), (52, 3));
+        assert_eq!(test.dir_usage("docs"), (52, 3));
+    }
+
+    #[test]
+    fn rejects_uploads_that_raced_past_the_limit() {
+        let test = TestQuotas::new(limit(Some(100), None), QuotaLimit::default());
+        test.upload("alice", "docs/a.txt", 40);
+
+        // Both uploads started while 60 bytes were left
+        assert_eq!(
+            test.quotas.allowance("alice", "docs/b.txt").unwrap(),
+            Some(60)
+        );
+        assert_eq!(
+            test.quotas.allowance("alice", "docs/c.txt").unwrap(),
+            Some(60)
+        );
+        assert!(test.quotas.record_upload("alice", "docs/b.txt", 50).is_ok());
+        assert!(matches!(
+            test.quotas.record_upload("alice", "docs/c.txt", 50),
+            Err(QuotaError::UserBytes)
+        ));
+        assert_eq!(test.user_usage("alice"), (90, 2));
+
+        // The index on disk follows every change
+        let reloaded = TestQuotas::load(&test.root, test.quotas.user_limit, test.quotas.dir_limit);
+        assert_eq!(reloaded.index.lock().unwrap().files.len(), 1);
+        test.quotas.record_delete("docs/b.txt");
+        let reloaded = TestQuotas::load(&test.root, test.quotas.user_limit, test.quotas.dir_limit);
+        assert_eq!(reloaded.index.lock().unwrap().user_usage("alice").bytes, 40);
+    }
+}
@@ -5,3 +5,8 @@ use super::views;
 pub fn register_urls(cfg: &mut web::ServiceConfig) {
     cfg.route("/upload", web::post().to(views::upload));
 }
+
+// Standalone upload page, used when the listing is disabled
+pub fn register_page_urls(cfg: &mut web::ServiceConfig) {
+    cfg.route("/", web::get().to(views::upload_page));
+}
@@ -1,18 +1,49 @@
-use actix_web::{HttpRequest, HttpResponse, Result, web};
+use actix_web::{HttpRequest, HttpResponse, http::header, web};
 use base64::{Engine as _, engine::general_purpose};
 use futures::StreamExt;
 use path_clean::PathClean;
 use sanitize_filename;
-use std::path::PathBuf;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
 use tokio::io::AsyncWriteExt;
 
+use super::{decompress, extract, quota};
 use crate::State;
+use crate::app::errors::AppError;
+use crate::app::{ServerMode, is_reserved};
+use crate::utils::proxy::client_ip_display;
+
+// Struct for Tera context when only the upload page is shown
+#[derive(Serialize)]
+struct UploadContext {
+    current_path: String,
+    base_url: String,
+    mode: ServerMode,
+}
+
+// Render the upload section without any directory listing
+pub async fn upload_page(state: web::Data<State>) -> Result<HttpResponse, AppError> {
+    let context = UploadContext {
+        current_path: String::new(),
+        base_url: state.base_url.clone(),
+        mode: state.mode,
+    };
+
+    let rendered_body = state.tera.render(
+        "home.html",
+        &tera::Context::from_serialize(&context).unwrap(),
+    )?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(rendered_body))
+}
 
 pub async fn upload(
-    mut payload: web::Payload,
+    payload: web::Payload,
     state: web::Data<State>,
     req: HttpRequest,
-) -> Result<HttpResponse> {
+) -> Result<HttpResponse, AppError> {
     // Determine filename
 
     // Check if base64 file name was send
@@ -21,17 +52,19 @@ pub async fn upload(
             Ok(base64_string) => match general_purpose::STANDARD.decode(base64_string) {
                 Ok(vec_u8_file_name) => match String::from_utf8(vec_u8_file_name.clone()) {
                     Ok(file_name) => file_name,
-                    Err(err) => return Ok(HttpResponse::BadRequest().body(format!("{}", err))),
+                    Err(err) => return Err(AppError::InvalidFileName(err.to_string())),
                 },
-                Err(err) => return Ok(HttpResponse::BadRequest().body(format!("{}", err))),
+                Err(err) => return Err(AppError::InvalidFileName(err.to_string())),
             },
-            Err(err) => return Ok(HttpResponse::BadRequest().body(format!("{}", err))),
+            Err(err) => return Err(AppError::InvalidHeader(err.to_string())),
         }
     } else if let Some(ascii_file_name) = req.headers().get("X-Target-File") {
         match ascii_file_name.to_str() {
             Ok(name) if !name.is_empty() => name.to_string(),
             _ => {
-                return Ok(HttpResponse::BadRequest().finish());
+                return Err(AppError::InvalidHeader(
+                    "X-Target-File must be a non-empty ASCII string".to_string(),
+                ));
             }
         }
     } else {
@@ -41,6 +74,30 @@ pub async fn upload(
     // Sanitize the filename
     let filename = sanitize_filename::sanitize(&filename);
 
+    // One-time files are deleted after their first complete download
+    let one_time = bool_header(&req, "X-One-Time")?;
+    // Archives are unpacked into a folder next to them and then removed
+    let extract = bool_header(&req, "X-Extract")?;
+    if one_time && extract {
+        return Err(AppError::InvalidHeader(
+            "X-One-Time cannot be combined with X-Extract".to_string(),
+        ));
+    }
+
+    // Compressed bodies are decoded on the fly, unless they are to be stored as sent
+    let body_encoding = decompress::body_encoding(&req)?;
+    let (filename, decode_encoding) = match body_encoding {
+        Some(encoding) if state.decompression.store_compressed => {
+            let suffix = decompress::file_suffix(encoding);
+            if filename.ends_with(&format!(".{}", suffix)) {
+                (filename, None)
+            } else {
+                (format!("{}.{}", filename, suffix), None)
+            }
+        }
+        encoding => (filename, encoding),
+    };
+
     // Determine Target Directory from Header (Optional - Only if uploaded from frontend)
     let target_subdir_str = req
         .headers()
@@ -59,8 +116,9 @@ pub async fn upload(
                 full_target_dir.display(),
                 err
             );
-            return Ok(HttpResponse::BadRequest()
-                .body("Target directory does not exist or is inaccessible"));
+            return Err(AppError::InvalidTargetDir(
+                "Target directory does not exist or is inaccessible",
+            ));
         }
     };
 
@@ -70,7 +128,19 @@ pub async fn upload(
             canonical_full_target_dir.display(),
             cleaned_target_subdir.display()
         );
-        return Ok(HttpResponse::Forbidden().body("Forbidden target directory"));
+        return Err(AppError::PathForbidden);
+    }
+
+    // The metadata directory and the reserved route segment are never writable through uploads
+    if canonical_full_target_dir.starts_with(&state.data_dir)
+        || is_reserved(&state.base_path, &full_target_dir)
+        || is_reserved(&state.base_path, &canonical_full_target_dir)
+    {
+        log::debug!(
+            "Upload rejected: Target '{}' is inside the data directory",
+            canonical_full_target_dir.display()
+        );
+        return Err(AppError::PathForbidden);
     }
 
     // Ensure the target is actually a directory after canonicalization
@@ -79,16 +149,131 @@ pub async fn upload(
             "Upload rejected: Target path '{}' is not a directory.",
             canonical_full_target_dir.display()
         );
-        return Ok(HttpResponse::BadRequest().body("Target path is not a directory"));
+        return Err(AppError::InvalidTargetDir("Target path is not a directory"));
     }
 
-    // Build full path
-    let full_file_path = canonical_full_target_dir.join(&filename);
+    // Inbox uploads need a token and go to the folder of that token, with the upload
+    // time in front of the name so nobody overwrites an earlier file
+    let (canonical_full_target_dir, filename) = match state.inboxes.find(&canonical_full_target_dir)
+    {
+        Some(inbox) => {
+            let Some(namespace) = req
+                .headers()
+                .get("X-Inbox-Token")
+                .and_then(|h| h.to_str().ok())
+                .and_then(|token| inbox.namespace(token))
+            else {
+                log::warn!(
+                    "Upload rejected: Missing or wrong inbox token for {} from {}",
+                    inbox.dir.display(),
+                    client_ip_display(&req)
+                );
+                return Err(AppError::InboxTokenInvalid);
+            };
+
+            let namespace_dir = inbox.dir.join(namespace);
+            tokio::fs::create_dir_all(&namespace_dir).await?;
+            let timestamp = chrono::Utc::now().format("%Y%m%dT%H%M%S%.3fZ");
+            (namespace_dir, format!("{}_{}", timestamp, filename))
+        }
+        None => (canonical_full_target_dir, filename),
+    };
+
+    // Build full path. Archives are written under a hidden temporary name, so a file
+    // with the name of the archive is left alone.
+    let full_file_path = if extract {
+        let suffix = getrandom::u64().map_err(std::io::Error::other)?;
+        canonical_full_target_dir.join(format!(".{}.{:016x}.upload", filename, suffix))
+    } else {
+        canonical_full_target_dir.join(&filename)
+    };
+
+    // Nor can the upload itself take their place, e.g. a file named `.holonet` in the root
+    let final_path = if extract {
+        canonical_full_target_dir.join(extract::target_folder(&filename))
+    } else {
+        full_file_path.clone()
+    };
+    if final_path.starts_with(&state.data_dir) || is_reserved(&state.base_path, &final_path) {
+        log::debug!(
+            "Upload rejected: '{}' would take the place of the data directory",
+            final_path.display()
+        );
+        return Err(AppError::PathForbidden);
+    }
 
     log::debug!("Attempting to upload to: {}", full_file_path.display());
 
+    // Check the announced size against the limits before touching the disk
+    let max_size = state.upload_limits.max_size_for(&canonical_full_target_dir);
+    // The announced length says nothing about the size of a decoded body
+    let content_length: Option<u64> = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|s| s.parse().ok())
+        .filter(|_| decode_encoding.is_none());
+
+    if let (Some(max), Some(length)) = (max_size, content_length)
+        && length > max
+    {
+        log::debug!(
+            "Upload rejected: Content-Length {} exceeds limit of {} bytes",
+            length,
+            max
+        );
+        return Err(AppError::PayloadTooLarge);
+    }
+
+    if !state
+        .upload_limits
+        .has_free_space(&canonical_full_target_dir, content_length.unwrap_or(0))
+    {
+        log::warn!(
+            "Upload rejected: Not enough free space in {}",
+            canonical_full_target_dir.display()
+        );
+        return Err(AppError::InsufficientStorage);
+    }
+
+    // Check the user and directory quotas
+    let user = quota::user_key(&req);
+    let quota_key = state
+        .quotas
+        .as_ref()
+        .and_then(|quotas| quotas.relative_key(&full_file_path));
+    let quota_allowance = match (&state.quotas, &quota_key) {
+        (Some(quotas), Some(key)) => match quotas.allowance(&user, key) {
+            Ok(allowance) => allowance,
+            Err(e) => {
+                log::debug!("Upload rejected for {}: {}", user, e);
+                return Err(e.into());
+            }
+        },
+        _ => None,
+    };
+
+    if let (Some(allowance), Some(length)) = (quota_allowance, content_length)
+        && length > allowance
+    {
+        log::debug!(
+            "Upload rejected for {}: Content-Length {} exceeds remaining quota of {} bytes",
+            user,
+            length,
+            allowance
+        );
+        return Err(AppError::QuotaAllowanceExceeded);
+    }
+
     // --- 6. Open File for Writing ---
-    let file = match tokio::fs::File::create(&full_file_path).await {
+    let file = match tokio::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .create_new(extract)
+        .open(&full_file_path)
+        .await
+    {
         Ok(f) => f,
         Err(e) => {
             log::error!(
@@ -96,11 +281,16 @@ pub async fn upload(
                 full_file_path.display(),
                 e
             );
-            // Use InternalServerError as this is likely a server-side FS issue
-            return Ok(HttpResponse::InternalServerError().body("Failed to create file on server"));
+            // Most likely a server-side FS issue
+            return Err(AppError::Io(e));
         }
     };
 
+    // Any previous file at this path has just been truncated
+    if let (Some(quotas), Some(key)) = (&state.quotas, &quota_key) {
+        quotas.record_delete(key);
+    }
+
     let mut total_bytes_written: u64 = 0;
 
     // Set chunk size to 4 Megabytes
@@ -111,41 +301,100 @@ pub async fn upload(
 
     let mut writer = file;
 
+    let mut throttle = state.upload_bandwidth.connection();
+    let mut throttled_bytes: u64 = 0;
+
+    let (mut body, wire_bytes) = decompress::body_stream(payload, decode_encoding);
+
     // Stream payload
-    while let Some(chunk) = payload.next().await {
+    while let Some(chunk) = body.next().await {
         match chunk {
             Ok(data) => {
+                // Pausing the read loop slows the client down through TCP backpressure.
+                // Compressed bodies are limited by what they take on the wire.
+                let on_wire = wire_bytes.get();
+                throttle.consume((on_wire - throttled_bytes) as usize).await;
+                throttled_bytes = on_wire;
                 read_buffer.extend_from_slice(&data);
+
+                // Enforce the size limit on the actual stream, Content-Length may be absent or lie
+                let received = total_bytes_written + read_buffer.len() as u64;
+                if decode_encoding.is_some()
+                    && let Err(e) = state.decompression.check(on_wire, received)
+                {
+                    log::debug!(
+                        "Upload rejected: Decompressed stream for '{}' grew to {} bytes from {} bytes",
+                        full_file_path.display(),
+                        received,
+                        on_wire
+                    );
+                    discard_partial_upload(&full_file_path).await;
+                    return Err(e);
+                }
+
+                if let Some(max) = max_size
+                    && received > max
+                {
+                    log::debug!(
+                        "Upload rejected: Stream for '{}' exceeded limit of {} bytes",
+                        full_file_path.display(),
+                        max
+                    );
+                    discard_partial_upload(&full_file_path).await;
+                    return Err(AppError::PayloadTooLarge);
+                }
+
+                if let Some(allowance) = quota_allowance
+                    && received > allowance
+                {
+                    log::debug!(
+                        "Upload rejected for {}: Stream exceeded remaining quota of {} bytes",
+                        user,
+                        allowance
+                    );
+                    discard_partial_upload(&full_file_path).await;
+                    return Err(AppError::QuotaAllowanceExceeded);
+                }
+
                 // If we exceed the chunk size it is time to write
                 if read_buffer.len() >= chunk_size {
-                    writer.write_all(&read_buffer).await.map_err(|e| {
-                        log::error!("Write error: {}", e);
-                        actix_web::error::ErrorInternalServerError("Write failure")
-                    })?;
+                    write_chunk(
+                        &mut writer,
+                        &read_buffer,
+                        &state,
+                        &canonical_full_target_dir,
+                        &full_file_path,
+                    )
+                    .await?;
                     total_bytes_written += read_buffer.len() as u64;
                     read_buffer.clear();
                 }
             }
             Err(err) => {
                 log::error!("{}", err);
-                return Ok(HttpResponse::BadRequest().finish());
+                discard_partial_upload(&full_file_path).await;
+                return Err(AppError::PayloadError(err.to_string()));
             }
         }
     }
 
     // Write any remaining data in the buffer
     if !read_buffer.is_empty() {
-        writer.write_all(&read_buffer).await.map_err(|e| {
-            log::error!("Write error: {}", e);
-            actix_web::error::ErrorInternalServerError("Write failure")
-        })?;
+        write_chunk(
+            &mut writer,
+            &read_buffer,
+            &state,
+            &canonical_full_target_dir,
+            &full_file_path,
+        )
+        .await?;
         total_bytes_written += read_buffer.len() as u64;
     }
 
     // Flush buffer
     writer.flush().await.map_err(|e| {
         log::error!("Flush error: {}", e);
-        actix_web::error::ErrorInternalServerError("Flush failure")
+        AppError::Io(e)
     })?;
 
     // --- 8. Check if File Size is Zero (Optional but good) ---
@@ -163,14 +412,267 @@ pub async fn upload(
             );
             // Continue to return bad request, but log the cleanup error
         }
-        return Ok(HttpResponse::BadRequest().body("Empty file upload rejected"));
+        return Err(AppError::EmptyUpload);
+    }
+
+    // Uploads running at the same time may have used up the allowance together
+    if let (Some(quotas), Some(key)) = (&state.quotas, &quota_key)
+        && let Err(e) = quotas.record_upload(&user, key, total_bytes_written)
+    {
+        log::debug!("Upload rejected for {}: {}", user, e);
+        discard_partial_upload(&full_file_path).await;
+        return Err(e.into());
     }
 
+    if extract {
+        return extract_upload(
+            &state,
+            &user,
+            &full_file_path,
+            &filename,
+            &canonical_full_target_dir,
+            max_size,
+        )
+        .await;
+    }
+
+    // A plain upload replacing a one-time file clears the mark
+    state.one_time.set(&full_file_path, one_time);
+
     log::info!(
-        "Successfully uploaded {:?} ({} bytes) to {}",
+        "Successfully uploaded {:?} ({} bytes) to {}{}",
         filename,
         total_bytes_written,
-        full_file_path.display()
+        full_file_path.display(),
+        if one_time { " as one-time file" } else { "" }
+    );
+    Ok(HttpResponse::Ok().finish())
+}
+
+// Optional boolean header, absent meaning false
+fn bool_header(req: &HttpRequest, name: &str) -> Result<bool, AppError> {
+    match req.headers().get(name).map(|h| h.to_str()) {
+        None => Ok(false),
+        Some(Ok(value)) if value.eq_ignore_ascii_case("true") => Ok(true),
+        Some(Ok(value)) if value.eq_ignore_ascii_case("false") => Ok(false),
+        Some(_) => Err(AppError::InvalidHeader(format!(
+            "{} must be true or false",
+            name
+        ))),
+    }
+}
+
+// Unpack an uploaded archive into a folder named after it and remove the archive,
+// which is gone afterwards whether the extraction worked or not
+async fn extract_upload(
+    state: &web::Data<State>,
+    user: &str,
+    archive_path: &Path,
+    archive_name: &str,
+    target_dir: &Path,
+    max_size: Option<u64>,
+) -> Result<HttpResponse, AppError> {
+    let folder = target_dir.join(extract::target_folder(archive_name));
+    let archive_key = state
+        .quotas
+        .as_ref()
+        .and_then(|quotas| quotas.relative_key(archive_path));
+
+    // The extracted files replace the archive, so its size is given back to the quota
+    let quota_allowance = match (&state.quotas, &archive_key) {
+        (Some(quotas), Some(key)) => quotas.allowance(user, key).ok().flatten(),
+        _ => None,
+    };
+    let mut limits = state.extract_limits;
+    limits.max_size = [Some(limits.max_size), max_size, quota_allowance]
+        .into_iter()
+        .flatten()
+        .min()
+        .unwrap_or(limits.max_size);
+
+    let result = {
+        let state = state.clone();
+        let archive_path = archive_path.to_path_buf();
+        let target_dir = target_dir.to_path_buf();
+        let folder = folder.clone();
+        web::block(move || {
+            extract::extract(&archive_path, &folder, limits, |incoming| {
+                state.upload_limits.has_free_space(&target_dir, incoming)
+            })
+        })
+        .await
+        .map_err(std::io::Error::other)?
+    };
+
+    discard_partial_upload(archive_path).await;
+    if let (Some(quotas), Some(key)) = (&state.quotas, &archive_key) {
+        quotas.record_delete(key);
+    }
+    let files = match result {
+        Ok(files) => files,
+        Err(e) => {
+            log::debug!("Extracting {} failed: {}", archive_path.display(), e);
+            return Err(e);
+        }
+    };
+
+    let total_size: u64 = files.iter().map(|file| file.size).sum();
+    if let Some(quotas) = &state.quotas {
+        let records: Vec<(String, u64)> = files
+            .iter()
+            .filter_map(|file| {
+                let key = quotas.relative_key(&folder.join(&file.path))?;
+                Some((key, file.size))
+            })
+            .collect();
+        if let Err(e) = quotas.record_files(user, &records) {
+            log::debug!("Extracted archive rejected for {}: {}", user, e);
+            if let Err(e) = tokio::fs::remove_dir_all(&folder).await {
+                log::error!(
+                    "Failed to remove extracted folder {}: {}",
+                    folder.display(),
+                    e
+                );
+            }
+            return Err(e.into());
+        }
+    }
+
+    log::info!(
+        "Successfully extracted {:?} ({} files, {} bytes) to {}",
+        archive_name,
+        files.len(),
+        total_size,
+        folder.display()
     );
     Ok(HttpResponse::Ok().finish())
 }
+
+// Write one buffered chunk, checking the free space reserve first.
+// Returns an error (after removing the partial file) if the write must be aborted.
+async fn write_chunk(
+    writer: &mut tokio::fs::File,
+    buffer: &[u8],
+    state: &State,
+    target_dir: &Path,
+    file_path: &Path,
+) -> Result<(), AppError> {
+    if !state
+        .upload_limits
+        .has_free_space(target_dir, buffer.len() as u64)
+    {
+        log::warn!(
+            "Upload aborted: Free space reserve reached while writing {}",
+            file_path.display()
+        );
+        discard_partial_upload(file_path).await;
+        return Err(AppError::InsufficientStorage);
+    }
+
+    if let Err(e) = writer.write_all(buffer).await {
+        log::error!("Write error: {}", e);
+        discard_partial_upload(file_path).await;
+        if e.kind() == std::io::ErrorKind::StorageFull {
+            return Err(AppError::InsufficientStorage);
+        }
+        return Err(AppError::Io(e));
+    }
+
+    Ok(())
+}
+
+// Remove a partially written upload
+async fn discard_partial_upload(file_path: &Path) {
+    if let Err(e) = tokio::fs::remove_file(file_path).await {
+        log::error!(
+            "Failed to delete partial upload {}: {}",
+            file_path.display(),
+            e
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::upload::urls;
+    use actix_web::{
+        App,
+        http::StatusCode,
+        test::{TestRequest, call_service, init_service},
+    };
+    use std::io::{Cursor, Write};
+    use tempfile::TempDir;
+
+    fn zip_archive(name: &str, data: &[u8]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .start_file(name, zip::write::SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[actix_web::test]
+    async fn extracts_archives_without_touching_their_namesake() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("photos.zip"), "keep me").unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(State::for_tests(dir.path())))
+                .configure(urls::register_urls),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri("/upload")
+            .insert_header(("X-Target-File", "photos.zip"))
+            .insert_header(("X-Extract", "true"))
+            .set_payload(zip_archive("a.txt", b"extracted"))
+            .to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::OK);
+
+        assert_eq!(
+            std::fs::read(dir.path().join("photos/a.txt")).unwrap(),
+            b"extracted"
+        );
+        assert_eq!(
+            std::fs::read(dir.path().join("photos.zip")).unwrap(),
+            b"keep me"
+        );
+        // The temporary archive is gone
+        let mut names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["photos", "photos.zip"]);
+    }
+
+    #[actix_web::test]
+    async fn rejects_uploads_named_like_the_data_directory() {
+        let dir = TempDir::new().unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(State::for_tests(dir.path())))
+                .configure(urls::register_urls),
+        )
+        .await;
+
+        let cases = [
+            (".holonet", false, StatusCode::FORBIDDEN),
+            (".holonet.zip", true, StatusCode::FORBIDDEN),
+            (".holonet.txt", false, StatusCode::OK),
+        ];
+        for (name, extract, status) in cases {
+            let req = TestRequest::post()
+                .uri("/upload")
+                .insert_header(("X-Target-File", name))
+                .insert_header(("X-Extract", extract.to_string()))
+                .set_payload(zip_archive("a.txt", b"data"))
+                .to_request();
+            assert_eq!(call_service(&app, req).await.status(), status, "{}", name);
+        }
+        assert!(!dir.path().join(".holonet").exists());
+    }
+}
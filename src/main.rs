@@ -1,14 +1,44 @@
-use actix_web::{App, HttpServer, middleware::Logger, web};
-use clap::Parser;
+use actix_web::{
+    App, HttpServer,
+    http::header::HeaderValue,
+    middleware::{Logger, from_fn},
+    web,
+};
+use clap::{Parser, Subcommand};
 use env_logger::Env;
 use ifcfg::IfCfg;
-use std::net::SocketAddr;
+use path_clean::PathClean;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tera::Tera;
 
 mod app;
+mod middleware;
 mod utils;
 
+use app::ServerMode;
+use app::download::one_time::OneTimeFiles;
+use app::download::thumbnails::Thumbnails;
+use app::share::store::{Shares, relative_path, shareable_path};
+use app::share::token::ShareKey;
+use app::share::views::share_link;
+use app::upload::decompress::Decompression;
+use app::upload::extract::ExtractLimits;
+use app::upload::inbox::{self, Inboxes};
+use app::upload::limits::{self, UploadLimits};
+use app::upload::quota::{QuotaLimit, Quotas};
+use middleware::access::{self, AccessList};
+use middleware::compression::{self, Compression, DEFAULT_COMPRESS_TYPES};
+use middleware::error_pages;
+use middleware::https_redirect::{self, HttpsRedirect};
+use middleware::rate_limit::{self, RateLimitConfig, RateLimiter};
+use utils::errors::UtilsError;
+use utils::net::{ListenSpec, Listener, bind_tcp, resolve_host, url_authority};
+use utils::proxy::{self, TrustedProxies};
+use utils::throttle::Bandwidth;
+#[cfg(unix)]
+use utils::unix::ActivatedSocket;
+
 #[derive(Parser, Debug)]
 #[command(
     version,
@@ -19,14 +49,21 @@ Optional / Custom file and directory name with:\n\
 curl -X POST -T file_path -H \"X-Target-File: desired_filename.ext\" -H \"X-Target-dir: dirname\" http://ip:port/upload"
 )]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Root directory
     #[arg(short, long, default_value = ".")]
     directory: String,
 
-    /// Host to bind the server to
+    /// Host or interface name (e.g. tun0) to bind the server to
     #[arg(short = 'l', long, default_value = "0.0.0.0")]
     host: String,
 
+    /// Bind to every IPv4 and IPv6 address of the interface given with --host
+    #[arg(long)]
+    all_addresses: bool,
+
     /// Port to host the server on
     #[arg(short, long, default_value_t = 7070)]
     port: u16,
@@ -34,103 +71,633 @@ struct Args {
     /// Use TLS encryption
     #[arg(long)]
     tls: bool,
+
+    /// Additionally serve HTTPS on this port, next to plain HTTP on --port
+    #[arg(long, conflicts_with_all = ["tls", "listen"])]
+    tls_port: Option<u16>,
+
+    /// Redirect browsers on plain HTTP listeners to HTTPS, scripted uploads keep working
+    #[arg(long)]
+    redirect_https: bool,
+
+    /// URL path prefix the server is mounted under, e.g. /files
+    #[arg(long, default_value = "", value_parser = utils::utils::parse_base_url)]
+    base_url: String,
+
+    /// Redirect requests for missing paths to the index instead of answering 404
+    #[arg(long)]
+    redirect_not_found: bool,
+
+    /// Reverse proxy whose X-Forwarded-For/Proto/Host headers are trusted, as address,
+    /// CIDR range, interface name or "unix". Can be repeated
+    #[arg(long, value_name = "CIDR|INTERFACE|unix")]
+    trusted_proxy: Vec<String>,
+
+    /// Listen on a Unix domain socket at this path instead of the default TCP address
+    #[arg(long, value_name = "PATH")]
+    unix_socket: Option<PathBuf>,
+
+    /// Permissions of the Unix socket file in octal, e.g. 660
+    #[arg(long, value_parser = utils::utils::parse_mode, requires = "unix_socket")]
+    unix_socket_mode: Option<u32>,
+
+    /// Owner of the Unix socket file as USER[:GROUP]
+    #[arg(long, value_name = "USER[:GROUP]", requires = "unix_socket")]
+    unix_socket_owner: Option<String>,
+
+    /// Address to listen on as [http://|https://]HOST[:PORT], e.g. https://[::]:7443.
+    /// Can be repeated, replaces --host, --port and --tls
+    #[arg(long, value_name = "URL", value_parser = utils::net::parse_listen)]
+    listen: Vec<ListenSpec>,
+
+    /// Only serve the listing and downloads, disable uploads
+    #[arg(long, conflicts_with = "upload_only")]
+    read_only: bool,
+
+    /// Only serve the upload page, disable listing and downloads
+    #[arg(long)]
+    upload_only: bool,
+
+    /// Maximum upload size, e.g. 500M or 2G
+    #[arg(long, value_parser = utils::utils::parse_size)]
+    max_upload_size: Option<u64>,
+
+    /// Maximum upload size for a directory (relative to root), e.g. uploads=10G. Can be repeated
+    #[arg(long, value_name = "DIR=SIZE", value_parser = limits::parse_dir_size)]
+    dir_max_upload_size: Vec<(String, u64)>,
+
+    /// Free disk space to keep in reserve, uploads are refused below it, e.g. 1G
+    #[arg(long, value_parser = utils::utils::parse_size)]
+    min_free_space: Option<u64>,
+
+    /// Decompressed bytes allowed per received byte for gzip or zstd encoded uploads, 0 disables the check
+    #[arg(long, default_value_t = 100)]
+    max_decompression_ratio: u64,
+
+    /// Maximum decompressed size of gzip or zstd encoded uploads, e.g. 1G
+    #[arg(long, value_parser = utils::utils::parse_size)]
+    max_decompressed_size: Option<u64>,
+
+    /// Store gzip or zstd encoded uploads as sent, adding a .gz or .zst suffix
+    #[arg(long)]
+    store_compressed: bool,
+
+    /// Maximum total size of the files extracted from an archive upload sent with X-Extract, e.g. 1G
+    #[arg(long, default_value = "1G", value_parser = utils::utils::parse_size)]
+    max_extract_size: u64,
+
+    /// Maximum number of files and folders in an archive upload sent with X-Extract
+    #[arg(long, default_value_t = 10000)]
+    max_extract_entries: u64,
+
+    /// Directory text pastes are stored in, relative to root
+    #[arg(long, default_value = "pastes")]
+    paste_dir: String,
+
+    /// Maximum size of a text paste, e.g. 1M
+    #[arg(long, default_value = "1M", value_parser = utils::utils::parse_size)]
+    max_paste_size: u64,
+
+    /// Directory for server metadata, hidden from the listing [default: <DIRECTORY>/.holonet]
+    #[arg(long)]
+    data_dir: Option<String>,
+
+    /// Total upload size allowed per user (client address), e.g. 1G
+    #[arg(long, value_parser = utils::utils::parse_size)]
+    user_quota: Option<u64>,
+
+    /// Number of uploaded files allowed per user (client address)
+    #[arg(long)]
+    user_file_quota: Option<u64>,
+
+    /// Total upload size allowed per top-level directory, e.g. 10G
+    #[arg(long, value_parser = utils::utils::parse_size)]
+    dir_quota: Option<u64>,
+
+    /// Number of uploaded files allowed per top-level directory
+    #[arg(long)]
+    dir_file_quota: Option<u64>,
+
+    /// Requests per second allowed per client address
+    #[arg(long, value_parser = utils::utils::parse_rate)]
+    rate_limit: Option<f64>,
+
+    /// Number of requests a client may burst above the rate limit [default: rate limit]
+    #[arg(long, requires = "rate_limit", value_parser = utils::utils::parse_rate)]
+    rate_burst: Option<f64>,
+
+    /// Concurrent uploads allowed per client address
+    #[arg(long)]
+    max_uploads_per_ip: Option<usize>,
+
+    /// Concurrent downloads allowed per client address
+    #[arg(long)]
+    max_downloads_per_ip: Option<usize>,
+
+    /// Concurrent uploads allowed in total
+    #[arg(long)]
+    max_uploads: Option<usize>,
+
+    /// Concurrent downloads allowed in total
+    #[arg(long)]
+    max_downloads: Option<usize>,
+
+    /// Download bandwidth shared by all clients in bytes per second, e.g. 10M
+    #[arg(long, value_parser = utils::utils::parse_size)]
+    max_download_rate: Option<u64>,
+
+    /// Download bandwidth per connection in bytes per second, e.g. 1M
+    #[arg(long, value_parser = utils::utils::parse_size)]
+    max_download_rate_per_conn: Option<u64>,
+
+    /// Upload bandwidth shared by all clients in bytes per second, e.g. 10M
+    #[arg(long, value_parser = utils::utils::parse_size)]
+    max_upload_rate: Option<u64>,
+
+    /// Upload bandwidth per connection in bytes per second, e.g. 1M
+    #[arg(long, value_parser = utils::utils::parse_size)]
+    max_upload_rate_per_conn: Option<u64>,
+
+    /// Enable share links, and the Share action in the listing to create them
+    #[arg(long)]
+    shares: bool,
+
+    /// Generate image thumbnails in the background and offer a gallery layout in the listing
+    #[arg(long)]
+    thumbnails: bool,
+
+    /// Cache-Control header sent with downloaded files, e.g. "public, max-age=3600"
+    #[arg(long, value_name = "VALUE", value_parser = utils::utils::parse_header_value)]
+    cache_control: Option<HeaderValue>,
+
+    /// Compress listings and text responses with brotli, zstd or gzip, as accepted by the client
+    #[arg(long)]
+    compress: bool,
+
+    /// Content type eligible for --compress, e.g. text/* or application/json. Can be repeated
+    /// [default: text/*, JSON, JavaScript, XML, SVG and WebAssembly]
+    #[arg(long, value_name = "MIME")]
+    compress_type: Vec<String>,
+
+    /// Serve FILE.br or FILE.gz in place of FILE when it exists and the client accepts it
+    #[arg(long)]
+    precompressed: bool,
+
+    /// Write-only upload directory (relative to root) and a token for uploading to it, e.g. inbox=s3cret. Can be repeated
+    #[arg(long, value_name = "DIR=TOKEN", value_parser = inbox::parse_dir_token)]
+    inbox: Vec<(String, String)>,
+
+    /// Client address, CIDR range or interface network allowed to see inbox contents. Can be repeated
+    #[arg(long, value_name = "CIDR|INTERFACE")]
+    admin: Vec<String>,
+
+    /// Only allow clients from this address, CIDR range or interface network. Can be repeated
+    #[arg(long, value_name = "CIDR|INTERFACE")]
+    allow: Vec<String>,
+
+    /// Deny clients from this address, CIDR range or interface network. Can be repeated
+    #[arg(long, value_name = "CIDR|INTERFACE")]
+    deny: Vec<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print a signed share link for a file or folder below the root directory and exit.
+    /// The server must run with --shares for the link to work
+    Share {
+        /// File or folder, relative to the root directory
+        path: String,
+
+        /// Lifetime of the link, e.g. 30m, 24h or 7d
+        #[arg(long, default_value = "24h", value_parser = utils::utils::parse_duration)]
+        expires: u64,
+
+        /// Password asked for before access is granted
+        #[arg(long)]
+        password: Option<String>,
+
+        /// Number of downloads after which the link stops working
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+        max_downloads: Option<u64>,
+
+        /// Stop working after the first complete download
+        #[arg(long)]
+        one_time: bool,
+
+        /// Public address of the server the link starts with, e.g. https://files.example.com
+        #[arg(long)]
+        url: Option<String>,
+    },
+}
+
+impl Args {
+    fn upload_limits(&self, base_path: &Path) -> std::io::Result<UploadLimits> {
+        let mut dir_max_sizes = Vec::new();
+        for (dir, size) in &self.dir_max_upload_size {
+            let dir_path = base_path
+                .join(PathBuf::from(dir).clean())
+                .canonicalize()
+                .map_err(|e| {
+                    std::io::Error::new(
+                        e.kind(),
+                        format!("Upload limit directory '{}': {}", dir, e),
+                    )
+                })?;
+            dir_max_sizes.push((dir_path, *size));
+        }
+
+        Ok(UploadLimits {
+            max_size: self.max_upload_size,
+            dir_max_sizes,
+            min_free_space: self.min_free_space,
+        })
+    }
+
+    fn inboxes(&self, base_path: &Path, data_dir: &Path) -> std::io::Result<Inboxes> {
+        let mut dir_tokens = Vec::new();
+        for (dir, token) in &self.inbox {
+            let dir_path = base_path
+                .join(PathBuf::from(dir).clean())
+                .canonicalize()
+                .map_err(|e| std::io::Error::new(e.kind(), format!("Inbox '{}': {}", dir, e)))?;
+            if !dir_path.starts_with(base_path) || dir_path.starts_with(data_dir) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Inbox '{}' must be a directory below the root", dir),
+                ));
+            }
+            dir_tokens.push((dir_path, token.clone()));
+        }
+
+        let mut admins = Vec::new();
+        for rule in &self.admin {
+            admins.extend(
+                access::resolve_rule(rule)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+            );
+        }
+
+        Ok(Inboxes::new(dir_tokens, admins))
+    }
+
+    // Need not exist yet, it is created with the first paste
+    fn paste_dir(&self, base_path: &Path, data_dir: &Path) -> std::io::Result<PathBuf> {
+        let paste_dir = base_path.join(PathBuf::from(&self.paste_dir).clean());
+        let paste_dir = paste_dir.canonicalize().unwrap_or(paste_dir);
+        if !paste_dir.starts_with(base_path)
+            || paste_dir == base_path
+            || paste_dir.starts_with(data_dir)
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Paste directory '{}' must be a directory below the root",
+                    self.paste_dir
+                ),
+            ));
+        }
+        Ok(paste_dir)
+    }
+
+    fn decompression(&self) -> Decompression {
+        Decompression {
+            max_ratio: Some(self.max_decompression_ratio).filter(|ratio| *ratio > 0),
+            max_size: self.max_decompressed_size,
+            store_compressed: self.store_compressed,
+        }
+    }
+
+    fn extract_limits(&self) -> ExtractLimits {
+        ExtractLimits {
+            max_size: self.max_extract_size,
+            max_entries: self.max_extract_entries,
+        }
+    }
+
+    fn data_dir(&self, base_path: &Path) -> std::io::Result<PathBuf> {
+        let data_dir = match &self.data_dir {
+            Some(dir) => std::path::absolute(dir)?.clean(),
+            None => base_path.join(".holonet"),
+        };
+        // Resolve symlinks if it already exists so it can be compared with requested paths
+        Ok(data_dir.canonicalize().unwrap_or(data_dir))
+    }
+
+    fn quotas(&self, base_path: &Path, data_dir: &Path) -> std::io::Result<Option<Quotas>> {
+        let user_limit = QuotaLimit {
+            max_bytes: self.user_quota,
+            max_files: self.user_file_quota,
+        };
+        let dir_limit = QuotaLimit {
+            max_bytes: self.dir_quota,
+            max_files: self.dir_file_quota,
+        };
+
+        if !user_limit.is_set() && !dir_limit.is_set() {
+            return Ok(None);
+        }
+
+        Quotas::load(
+            base_path.to_path_buf(),
+            data_dir.join("usage.json"),
+            user_limit,
+            dir_limit,
+        )
+        .map(Some)
+    }
+
+    fn rate_limit_config(&self) -> RateLimitConfig {
+        RateLimitConfig {
+            requests_per_sec: self.rate_limit,
+            burst: self.rate_burst,
+            max_uploads_per_ip: self.max_uploads_per_ip,
+            max_downloads_per_ip: self.max_downloads_per_ip,
+            max_uploads: self.max_uploads,
+            max_downloads: self.max_downloads,
+            upload_path: format!("{}/upload", self.base_url),
+        }
+    }
+
+    fn compression(&self) -> Compression {
+        if !self.compress {
+            return Compression::default();
+        }
+        if self.compress_type.is_empty() {
+            let defaults: Vec<String> = DEFAULT_COMPRESS_TYPES
+                .iter()
+                .map(|content_type| content_type.to_string())
+                .collect();
+            return Compression::new(&defaults);
+        }
+        Compression::new(&self.compress_type)
+    }
+
+    // Resolve --listen values, or --host/--port/--tls if none were given
+    // and no Unix or systemd socket takes the place of the default listener
+    fn listeners(&self, has_socket_listeners: bool) -> Result<Vec<Listener>, UtilsError> {
+        let specs = if self.listen.is_empty() && has_socket_listeners {
+            Vec::new()
+        } else if self.listen.is_empty() {
+            let mut specs = vec![ListenSpec {
+                tls: Some(self.tls),
+                host: self.host.clone(),
+                port: Some(self.port),
+            }];
+            if let Some(tls_port) = self.tls_port {
+                specs.push(ListenSpec {
+                    tls: Some(true),
+                    host: self.host.clone(),
+                    port: Some(tls_port),
+                });
+            }
+            specs
+        } else {
+            self.listen.clone()
+        };
+
+        let mut listeners = Vec::new();
+        for spec in specs {
+            let tls = spec.tls.unwrap_or(self.tls);
+            let port = spec.port.unwrap_or(self.port);
+            for address in resolve_host(&spec.host, port, self.all_addresses)? {
+                listeners.push(Listener {
+                    tls,
+                    address,
+                    v6_only: false,
+                });
+            }
+        }
+
+        // Keep an IPv6 wildcard from claiming the IPv4 side of a port another listener uses
+        let v4_ports: Vec<u16> = listeners
+            .iter()
+            .filter(|listener| listener.address.is_ipv4())
+            .map(|listener| listener.address.port())
+            .collect();
+        for listener in &mut listeners {
+            listener.v6_only =
+                listener.address.is_ipv6() && v4_ports.contains(&listener.address.port());
+        }
+
+        Ok(listeners)
+    }
+
+    fn https_redirect(&self, listeners: &[Listener]) -> std::io::Result<HttpsRedirect> {
+        if !self.redirect_https {
+            return Ok(HttpsRedirect::default());
+        }
+
+        let port = listeners
+            .iter()
+            .find(|listener| listener.tls)
+            .map(|listener| listener.address.port())
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "--redirect-https needs an HTTPS listener (--tls-port or --listen https://...)",
+                )
+            })?;
+
+        Ok(HttpsRedirect { port: Some(port) })
+    }
+
+    fn mode(&self) -> ServerMode {
+        if self.read_only {
+            ServerMode::ReadOnly
+        } else if self.upload_only {
+            ServerMode::UploadOnly
+        } else {
+            ServerMode::Normal
+        }
+    }
 }
 
 pub struct State {
     pub base_path: PathBuf,
     pub tera: tera::Tera,
+    pub mode: ServerMode,
+    pub upload_limits: UploadLimits,
+    pub decompression: Decompression,
+    pub extract_limits: ExtractLimits,
+    pub data_dir: PathBuf,
+    pub quotas: Option<Arc<Quotas>>,
+    pub download_bandwidth: Bandwidth,
+    pub upload_bandwidth: Bandwidth,
+    pub base_url: String,
+    pub redirect_not_found: bool,
+    pub cache_control: Option<HeaderValue>,
+    pub precompressed: bool,
+    pub shares: Option<Arc<Shares>>,
+    pub one_time: Arc<OneTimeFiles>,
+    pub inboxes: Inboxes,
+    pub paste_dir: PathBuf,
+    pub max_paste_size: u64,
+    pub thumbnails: Option<Arc<Thumbnails>>,
 }
 
 const DIR_LISTING_TEMPLATE_CONTENT: &str = include_str!("../static/templates/home.html");
 const DIR_LISTING_TEMPLATE_NAME: &str = "home.html";
+const ERROR_TEMPLATE_CONTENT: &str = include_str!("../static/templates/error.html");
+pub const ERROR_TEMPLATE_NAME: &str = "error.html";
+const SHARE_PASSWORD_TEMPLATE_CONTENT: &str =
+    include_str!("../static/templates/share_password.html");
+pub const SHARE_PASSWORD_TEMPLATE_NAME: &str = "share_password.html";
+const PASTE_TEMPLATE_CONTENT: &str = include_str!("../static/templates/paste.html");
+pub const PASTE_TEMPLATE_NAME: &str = "paste.html";
+const PREVIEW_TEMPLATE_CONTENT: &str = include_str!("../static/templates/preview.html");
+pub const PREVIEW_TEMPLATE_NAME: &str = "preview.html";
 
-fn print_startup_messages(args: &Args) {
-    let scheme = if args.tls { "https" } else { "http" };
-
-    // Check if binding to all interfaces
-    if args.host == "0.0.0.0" || args.host == "::" {
-        println!(" * Running on all addresses ({})", args.host);
-        match IfCfg::get() {
-            Ok(ifaces) => {
-                let mut found_specific_ip = false;
-                for iface in ifaces {
-                    // Skip loopback interfaces
-                    if iface.name == "lo" || iface.name == "lo0" {
-                        log::debug!("Skipping loopback interface {}", iface.name);
-                        continue;
-                    }
+// Print every URL a listener can be reached on
+fn print_listener_urls(listener: &Listener, base_url: &str) {
+    let scheme = listener.scheme();
+    let address = listener.address;
 
-                    // Still print loopback for 0.0.0.0 case as it's reachable
-                    for addr_info in &iface.addresses {
-                        if let Some(SocketAddr::V4(v4_addr)) = addr_info.address {
-                            if v4_addr.ip().is_loopback() {
-                                println!(
-                                    " * Running on {}://{}:{}",
-                                    scheme,
-                                    v4_addr.ip(),
-                                    args.port
-                                );
-                                found_specific_ip = true;
-                            }
-                        }
-                    }
+    if !address.ip().is_unspecified() {
+        // Specific host or interface provided
+        println!(
+            " * Running on {}://{}{}",
+            scheme,
+            url_authority(&address),
+            base_url
+        );
+        return;
+    }
+
+    // Binding to all interfaces, an IPv6 wildcard also accepts IPv4 clients unless v6 only
+    println!(" * Running on all addresses ({})", url_authority(&address));
+    let include_v4 = address.is_ipv4() || !listener.v6_only;
+    let include_v6 = address.is_ipv6();
+
+    match IfCfg::get() {
+        Ok(ifaces) => {
+            let mut found_specific_ip = false;
+            for iface in ifaces {
+                // Skip loopback interfaces
+                if iface.name == "lo" || iface.name == "lo0" {
+                    log::debug!("Skipping loopback interface {}", iface.name);
+                    continue;
+                }
+
+                // Skip common virtual/docker interface prefixes, but allow 'tun' as requested
+                let name_lower = iface.name.to_lowercase();
+                if name_lower.starts_with("docker")
+                    || name_lower.starts_with("veth")
+                    || name_lower.starts_with("vmnet")
+                    || name_lower.starts_with("virbr")
+                // Add other interface name patterns to exclude if needed
+                {
+                    log::debug!("Skipping interface {} due to filter", iface.name);
+                    continue;
+                }
 
-                    // Skip common virtual/docker interface prefixes, but allow 'tun' as requested
-                    let name_lower = iface.name.to_lowercase();
-                    if name_lower.starts_with("docker")
-                        || name_lower.starts_with("veth")
-                        || name_lower.starts_with("vmnet")
-                        || name_lower.starts_with("virbr")
-                    // Add other interface name patterns to exclude if needed
+                // Print the addresses of the interface
+                for addr_info in iface.addresses {
+                    let Some(mut iface_address) = addr_info.address else {
+                        continue;
+                    };
+                    if iface_address.ip().is_loopback()
+                        || (iface_address.is_ipv4() && !include_v4)
+                        || (iface_address.is_ipv6() && !include_v6)
                     {
-                        log::debug!("Skipping interface {} due to filter", iface.name);
                         continue;
                     }
-
-                    // Print IPv4 addresses for the interface
-                    for addr_info in iface.addresses {
-                        if let Some(SocketAddr::V4(v4_addr)) = addr_info.address {
-                            // Avoid re-printing loopback if already handled or if it's not the primary address for 0.0.0.0
-                            if !v4_addr.ip().is_loopback() {
-                                // Only print non-loopback here
-                                println!(
-                                    " * Running on {}://{}:{}",
-                                    scheme,
-                                    v4_addr.ip(),
-                                    args.port
-                                );
-                                found_specific_ip = true;
-                            }
-                        }
-                        // Optionally, handle and print IPv6 addresses if args.host == "::"
-                        // if args.host == "::" {
-                        //     if let Some(SocketAddr::V6(v6_addr)) = addr_info.address {
-                        //         println!(" * Running on {}://[{}]:{}", scheme, v6_addr.ip(), args.port);
-                        //         found_specific_ip = true;
-                        //     }
-                        // }
-                    }
-                }
-                if !found_specific_ip && (args.host == "0.0.0.0" || args.host == "::") {
-                    // Fallback if no specific IPs were found but listening on all, maybe only loopback was available
-                    // This case should be rare if loopback is explicitly printed above.
-                    log::warn!(
-                        "Could not find specific non-loopback IP addresses, but server is listening on all interfaces."
+                    iface_address.set_port(address.port());
+                    println!(
+                        " * Running on {}://{}{}",
+                        scheme,
+                        url_authority(&iface_address),
+                        base_url
                     );
+                    found_specific_ip = true;
                 }
             }
-            Err(e) => {
-                log::error!("[ERROR] Could not get network interfaces: {}", e);
-                // Fallback to just printing the bind address as specified
-                println!(
-                    " * Running on {}://{}:{} (interface detection failed)",
-                    scheme, args.host, args.port
+            if !found_specific_ip {
+                // Fallback if no specific IPs were found but listening on all, maybe only loopback was available
+                log::warn!(
+                    "Could not find specific non-loopback IP addresses, but server is listening on all interfaces."
                 );
             }
         }
-    } else {
-        // Specific host provided
-        println!(" * Running on {}://{}:{}", scheme, args.host, args.port);
+        Err(e) => {
+            log::error!("[ERROR] Could not get network interfaces: {}", e);
+            // Fallback to just printing the bind address as specified
+            println!(
+                " * Running on {}://{} (interface detection failed)",
+                scheme,
+                url_authority(&address)
+            );
+        }
+    }
+}
+
+// Mint a share link without a running server, it shares the key in the data directory
+fn print_share_link(
+    base_path: &Path,
+    data_dir: &Path,
+    base_url: &str,
+    command: &Command,
+) -> std::io::Result<()> {
+    let Command::Share {
+        path,
+        expires,
+        password,
+        max_downloads,
+        one_time,
+        url,
+    } = command;
+
+    let target = shareable_path(base_path, data_dir, path)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()))?;
+    let key = ShareKey::load_or_create(&data_dir.join("share.key"))?;
+    let (token, claims) = key.mint(
+        relative_path(base_path, &target),
+        *expires,
+        password.as_deref(),
+        *max_downloads,
+        *one_time,
+    )?;
+
+    let expires_at = chrono::DateTime::from_timestamp(claims.expires, 0)
+        .map(|time| time.to_rfc3339())
+        .unwrap_or_default();
+    println!(
+        "{}{}{}",
+        url.as_deref().unwrap_or("").trim_end_matches('/'),
+        share_link(base_url, &token),
+        if target.is_dir() { "/" } else { "" }
+    );
+    eprintln!("Valid until {}", expires_at);
+    Ok(())
+}
+
+fn print_startup_messages(
+    listeners: &[Listener],
+    unix_sockets: &[String],
+    base_url: &str,
+    mode: ServerMode,
+    shares: bool,
+    inboxes: bool,
+) {
+    for listener in listeners {
+        print_listener_urls(listener, base_url);
+    }
+    for path in unix_sockets {
+        println!(" * Running on unix:{}", path);
+    }
+    match mode {
+        ServerMode::ReadOnly => println!(" * Read-only mode: uploads are disabled"),
+        ServerMode::UploadOnly => {
+            println!(" * Upload-only mode: listing and downloads are disabled")
+        }
+        ServerMode::Normal => {}
+    }
+    if shares {
+        println!(" * Share links are enabled");
+    }
+    if inboxes {
+        println!(
+            " * Inbox directories accept uploads with a token, contents are shown to admins only"
+        );
     }
     println!("Press CTRL+C to quit\n");
 }
@@ -141,42 +708,273 @@ async fn main() -> std::io::Result<()> {
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
     let args = Args::parse();
+    let mode = args.mode();
+    let base_path: PathBuf = Path::new(&args.directory).canonicalize()?;
+    let upload_limits = args.upload_limits(&base_path)?;
+    let decompression = args.decompression();
+    let extract_limits = args.extract_limits();
+    let data_dir = args.data_dir(&base_path)?;
+
+    if let Some(command) = &args.command {
+        return print_share_link(&base_path, &data_dir, &args.base_url, command);
+    }
+
+    // Taken over before any thread is started, the environment is cleared of them
+    #[cfg(unix)]
+    let activated_sockets = utils::unix::systemd_sockets()?;
+
+    let quotas = args.quotas(&base_path, &data_dir)?.map(Arc::new);
+    let inboxes = args.inboxes(&base_path, &data_dir)?;
+    let paste_dir = args.paste_dir(&base_path, &data_dir)?;
+    let thumbnails = if args.thumbnails {
+        Some(Arc::new(Thumbnails::start(data_dir.join("thumbnails"))?))
+    } else {
+        None
+    };
+    let shares = if args.shares {
+        Some(Arc::new(Shares::load(&data_dir)?))
+    } else {
+        None
+    };
+    let one_time = Arc::new(OneTimeFiles::load(
+        base_path.clone(),
+        data_dir.join("one_time.json"),
+    )?);
+    let download_bandwidth =
+        Bandwidth::new(args.max_download_rate, args.max_download_rate_per_conn);
+    let upload_bandwidth = Bandwidth::new(args.max_upload_rate, args.max_upload_rate_per_conn);
+    let access_list = AccessList::new(&args.allow, &args.deny)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let access_list = web::Data::new(access_list);
+    let rate_limiter = web::Data::new(RateLimiter::new(args.rate_limit_config()));
+    let compression = web::Data::new(args.compression());
+    let trusted_proxies = TrustedProxies::new(&args.trusted_proxy)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let trusted_proxies = web::Data::new(trusted_proxies);
+    let base_url = args.base_url.clone();
+
+    // Sockets handed over by systemd, and the Unix socket, replace the default TCP listener
+    #[cfg(unix)]
+    let has_socket_listeners = args.unix_socket.is_some() || !activated_sockets.is_empty();
+    #[cfg(not(unix))]
+    let has_socket_listeners = if args.unix_socket.is_some() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "Unix domain sockets are not supported on this platform",
+        ));
+    } else {
+        false
+    };
+
+    // Bind every TCP address up front so activated sockets can join them
+    let mut tcp_sockets = Vec::new();
+    for listener in args.listeners(has_socket_listeners)? {
+        let tcp_listener = bind_tcp(listener.address, listener.v6_only)?;
+        tcp_sockets.push((listener, tcp_listener));
+    }
+
+    #[cfg(unix)]
+    let mut unix_sockets = Vec::new();
+    #[cfg(unix)]
+    {
+        if let Some(path) = &args.unix_socket {
+            let unix_listener = utils::unix::bind_unix_socket(
+                path,
+                args.unix_socket_mode,
+                args.unix_socket_owner.as_deref(),
+            )?;
+            unix_sockets.push((path.display().to_string(), unix_listener));
+        }
+
+        for socket in activated_sockets {
+            match socket {
+                ActivatedSocket::Tcp(tcp_listener) => {
+                    let listener = Listener {
+                        tls: args.tls,
+                        address: tcp_listener.local_addr()?,
+                        v6_only: false,
+                    };
+                    tcp_sockets.push((listener, tcp_listener));
+                }
+                ActivatedSocket::Unix(unix_listener) => {
+                    let name = unix_listener
+                        .local_addr()?
+                        .as_pathname()
+                        .map(|path| path.display().to_string())
+                        .unwrap_or_else(|| "(unnamed)".to_string());
+                    unix_sockets.push((name, unix_listener));
+                }
+            }
+        }
+    }
 
-    let server = HttpServer::new(move || {
+    let listeners: Vec<Listener> = tcp_sockets.iter().map(|(listener, _)| *listener).collect();
+    let https_redirect = web::Data::new(args.https_redirect(&listeners)?);
+
+    let mut server = HttpServer::new(move || {
         // Initialize Tera template engine
         let mut tera_instance = Tera::default();
         tera_instance
             .add_raw_template(DIR_LISTING_TEMPLATE_NAME, DIR_LISTING_TEMPLATE_CONTENT)
             .unwrap();
-
-        let base_path: &Path = Path::new(&args.directory);
-        let canonical_base_path: PathBuf = base_path.canonicalize().unwrap();
+        tera_instance
+            .add_raw_template(ERROR_TEMPLATE_NAME, ERROR_TEMPLATE_CONTENT)
+            .unwrap();
+        tera_instance
+            .add_raw_template(
+                SHARE_PASSWORD_TEMPLATE_NAME,
+                SHARE_PASSWORD_TEMPLATE_CONTENT,
+            )
+            .unwrap();
+        tera_instance
+            .add_raw_template(PASTE_TEMPLATE_NAME, PASTE_TEMPLATE_CONTENT)
+            .unwrap();
+        tera_instance
+            .add_raw_template(PREVIEW_TEMPLATE_NAME, PREVIEW_TEMPLATE_CONTENT)
+            .unwrap();
 
         let state = State {
-            base_path: canonical_base_path,
+            base_path: base_path.clone(),
             tera: tera_instance,
+            mode,
+            upload_limits: upload_limits.clone(),
+            decompression: decompression.clone(),
+            extract_limits,
+            data_dir: data_dir.clone(),
+            quotas: quotas.clone(),
+            download_bandwidth: download_bandwidth.clone(),
+            upload_bandwidth: upload_bandwidth.clone(),
+            base_url: base_url.clone(),
+            redirect_not_found: args.redirect_not_found,
+            cache_control: args.cache_control.clone(),
+            precompressed: args.precompressed,
+            shares: shares.clone(),
+            one_time: one_time.clone(),
+            inboxes: inboxes.clone(),
+            paste_dir: paste_dir.clone(),
+            max_paste_size: args.max_paste_size,
+            thumbnails: thumbnails.clone(),
         };
 
         App::new()
-            .wrap(Logger::default())
+            .wrap(from_fn(https_redirect::https_redirect))
+            .wrap(from_fn(rate_limit::rate_limit))
+            .wrap(from_fn(access::access_control))
+            .wrap(from_fn(error_pages::error_pages))
+            .wrap(from_fn(compression::compression))
+            // Default format, with the client address resolved through trusted proxies
+            .wrap(
+                Logger::new(r#"%{client}xi "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T"#)
+                    .custom_request_replace("client", |req| {
+                        proxy::client_ip_display(req.request())
+                    }),
+            )
+            .app_data(access_list.clone())
+            .app_data(compression.clone())
+            .app_data(https_redirect.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(trusted_proxies.clone())
             .app_data(web::Data::new(state))
-            .configure(app::register_urls)
+            // "/files" would not reach the scope's routes, send it to "/files/"
+            .configure(|cfg| {
+                if !base_url.is_empty() {
+                    cfg.service(web::redirect(base_url.clone(), format!("{}/", base_url)));
+                }
+            })
+            .service(
+                web::scope(&base_url).configure(|cfg| app::register_urls(cfg, mode, args.shares)),
+            )
     })
     .workers(1);
 
     // Print startup messages before starting the server
-    print_startup_messages(&Args::parse());
+    #[cfg(unix)]
+    let unix_socket_names: Vec<String> =
+        unix_sockets.iter().map(|(name, _)| name.clone()).collect();
+    #[cfg(not(unix))]
+    let unix_socket_names: Vec<String> = Vec::new();
+    print_startup_messages(
+        &listeners,
+        &unix_socket_names,
+        &args.base_url,
+        mode,
+        args.shares,
+        !args.inbox.is_empty(),
+    );
 
-    // CHeck if TLS is enabled
-    if args.tls {
+    // Issue one certificate for every TLS address, --host may be an interface name
+    let cert_names: Vec<String> = listeners
+        .iter()
+        .filter(|listener| listener.tls)
+        .map(|listener| listener.address.ip().to_string())
+        .collect();
+    let cert = if cert_names.is_empty() {
+        None
+    } else {
         // Generate self-signed certificate | Unwrap here cause we cannot continue if it fails
-        let cert = utils::utils::generate_self_signed_cert(&args.host).unwrap();
+        Some(utils::utils::generate_self_signed_cert(&cert_names).unwrap())
+    };
 
-        // Create bind address
-        let bind_address = format!("{}:{}", args.host, args.port);
+    for (listener, tcp_listener) in tcp_sockets {
+        server = match &cert {
+            Some(cert) if listener.tls => server.listen_rustls_0_23(tcp_listener, cert.clone())?,
+            _ => server.listen(tcp_listener)?,
+        };
+    }
 
-        server.bind_rustls_0_23(bind_address, cert)?.run().await
-    } else {
-        server.bind((args.host, args.port))?.run().await
+    #[cfg(unix)]
+    for (_, unix_listener) in unix_sockets {
+        server = server.listen_uds(unix_listener)?;
+    }
+
+    server.run().await
+}
+
+#[cfg(test)]
+impl State {
+    // Plain defaults for handler tests: every feature off, `base_path` as the root
+    pub fn for_tests(base_path: &Path) -> Self {
+        let mut tera = Tera::default();
+        tera.add_raw_templates([
+            (DIR_LISTING_TEMPLATE_NAME, DIR_LISTING_TEMPLATE_CONTENT),
+            (ERROR_TEMPLATE_NAME, ERROR_TEMPLATE_CONTENT),
+            (
+                SHARE_PASSWORD_TEMPLATE_NAME,
+                SHARE_PASSWORD_TEMPLATE_CONTENT,
+            ),
+            (PASTE_TEMPLATE_NAME, PASTE_TEMPLATE_CONTENT),
+            (PREVIEW_TEMPLATE_NAME, PREVIEW_TEMPLATE_CONTENT),
+        ])
+        .unwrap();
+
+        let base_path = base_path.canonicalize().unwrap();
+        let data_dir = base_path.join(".holonet");
+        State {
+            tera,
+            mode: ServerMode::Normal,
+            upload_limits: UploadLimits::default(),
+            decompression: Decompression::default(),
+            extract_limits: ExtractLimits {
+                max_size: 1024 * 1024,
+                max_entries: 100,
+            },
+            quotas: None,
+            download_bandwidth: Bandwidth::default(),
+            upload_bandwidth: Bandwidth::default(),
+            base_url: String::new(),
+            redirect_not_found: false,
+            cache_control: None,
+            precompressed: false,
+            shares: None,
+            one_time: Arc::new(
+                OneTimeFiles::load(base_path.clone(), data_dir.join("one_time.json")).unwrap(),
+            ),
+            inboxes: Inboxes::default(),
+            paste_dir: base_path.join("pastes"),
+            max_paste_size: 1024 * 1024,
+            thumbnails: None,
+            data_dir,
+            base_path,
+        }
     }
 }
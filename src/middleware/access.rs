@@ -0,0 +1,240 @@
+use actix_web::{
+    Error, ResponseError,
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web,
+};
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+use crate::State;
+use crate::app::RESERVED_SEGMENT;
+use crate::app::errors::AppError;
+use crate::utils::net::find_interface;
+use crate::utils::proxy::client_ip;
+
+// Client address filter built from CIDR ranges, single addresses and interface names
+#[derive(Clone, Debug, Default)]
+pub struct AccessList {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl AccessList {
+    pub fn new(allow: &[String], deny: &[String]) -> Result<Self, String> {
+        Ok(AccessList {
+            allow: resolve_rules(allow)?,
+            deny: resolve_rules(deny)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    // Deny rules win, and once an allow rule exists everything else is denied
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        if self.is_denied(ip) {
+            return false;
+        }
+        let ip = ip.to_canonical();
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+
+    // Only the deny rules, for requests that need not match an allow rule
+    pub fn is_denied(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.deny.iter().any(|net| net.contains(&ip))
+    }
+}
+
+fn resolve_rules(rules: &[String]) -> Result<Vec<IpNet>, String> {
+    let mut nets = Vec::new();
+    for rule in rules {
+        nets.extend(resolve_rule(rule)?);
+    }
+    Ok(nets)
+}
+
+// Turn "10.0.0.0/8", "192.168.1.5" or "tun0" into networks
+pub fn resolve_rule(rule: &str) -> Result<Vec<IpNet>, String> {
+    if let Ok(net) = rule.parse::<IpNet>() {
+        return Ok(vec![net.trunc()]);
+    }
+    if let Ok(ip) = rule.parse::<IpAddr>() {
+        return Ok(vec![IpNet::from(ip)]);
+    }
+
+    let iface = find_interface(rule)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| {
+            format!(
+                "'{}' is neither an address, a CIDR range nor an interface",
+                rule
+            )
+        })?;
+
+    let nets: Vec<IpNet> = iface
+        .addresses
+        .iter()
+        .filter_map(|addr_info| {
+            let ip = addr_info.address?.ip();
+            let net = match addr_info.mask {
+                Some(mask) => IpNet::with_netmask(ip, mask.ip()).ok()?.trunc(),
+                None => IpNet::from(ip),
+            };
+            Some(net)
+        })
+        .collect();
+
+    if nets.is_empty() {
+        return Err(format!("Interface '{}' has no addresses", rule));
+    }
+
+    log::debug!("Interface {} resolved to {:?}", rule, nets);
+    Ok(nets)
+}
+
+pub async fn access_control(
+    access_list: web::Data<AccessList>,
+    state: web::Data<State>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    if access_list.is_empty() {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    }
+
+    // Share links are meant for outsiders and carry their own signature, so they
+    // need not match an allow rule. Denied clients stay locked out of them.
+    let is_share_link = state.shares.is_some()
+        && req
+            .path()
+            .strip_prefix(state.base_url.as_str())
+            .is_some_and(|path| path.starts_with(&format!("/{}/s/", RESERVED_SEGMENT)));
+
+    let peer = client_ip(req.request());
+    let allowed = match peer {
+        Some(ip) if is_share_link => !access_list.is_denied(ip),
+        Some(ip) => access_list.is_allowed(ip),
+        // An unknown address can only be let through if nobody is denied
+        None => is_share_link && access_list.deny.is_empty(),
+    };
+
+    if allowed {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    }
+
+    log::warn!(
+        "Denied {} {} from {}",
+        req.method(),
+        req.path(),
+        peer.map(|ip| ip.to_string())
+            .unwrap_or_else(|| "unknown address".to_string())
+    );
+    Ok(req.into_response(AppError::AddressDenied.error_response()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(allow: &[&str], deny: &[&str]) -> AccessList {
+        let strings = |rules: &[&str]| {
+            rules
+                .iter()
+                .map(|rule| rule.to_string())
+                .collect::<Vec<_>>()
+        };
+        AccessList::new(&strings(allow), &strings(deny)).unwrap()
+    }
+
+    #[test]
+    fn resolves_rules() {
+        let cases = [
+            ("10.0.0.0/8", "10.0.0.0/8"),
+            ("10.1.2.3/8", "10.0.0.0/8"),
+            ("192.168.1.5", "192.168.1.5/32"),
+            ("fd00::/8", "fd00::/8"),
+            ("fd12:3456::1/64", "fd12:3456::/64"),
+            ("::1", "::1/128"),
+        ];
+        for (rule, expected) in cases {
+            assert_eq!(
+                resolve_rule(rule).unwrap(),
+                vec![expected.parse::<IpNet>().unwrap()],
+                "{}",
+                rule
+            );
+        }
+
+        for rule in ["10.0.0.0/33", "300.1.2.3", "no-such-interface0", ""] {
+            assert!(resolve_rule(rule).is_err(), "{}", rule);
+        }
+    }
+
+    // Allow and deny rules, client address, then whether it is allowed and whether it is denied
+    type AccessCase = (
+        &'static [&'static str],
+        &'static [&'static str],
+        &'static str,
+        bool,
+        bool,
+    );
+
+    #[test]
+    fn resolves_access() {
+        let cases: &[AccessCase] = &[
+            // No rules at all
+            (&[], &[], "203.0.113.7", true, false),
+            // Allow rules shut out everybody else
+            (&["10.0.0.0/8"], &[], "10.20.30.40", true, false),
+            (&["10.0.0.0/8"], &[], "11.0.0.1", false, false),
+            (&["192.168.1.5"], &[], "192.168.1.6", false, false),
+            // Deny rules alone let everybody else in
+            (&[], &["10.0.0.0/8"], "10.0.0.1", false, true),
+            (&[], &["10.0.0.0/8"], "192.168.0.1", true, false),
+            // Deny wins over allow
+            (&["10.0.0.0/8"], &["10.1.0.0/16"], "10.1.2.3", false, true),
+            (&["10.0.0.0/8"], &["10.1.0.0/16"], "10.2.0.1", true, false),
+            (&["10.0.0.5"], &["10.0.0.0/24"], "10.0.0.5", false, true),
+            // IPv6
+            (&["fd00::/8"], &[], "fd12::1", true, false),
+            (&["fd00::/8"], &[], "2001:db8::1", false, false),
+            (&[], &["2001:db8::/32"], "2001:db8:1::1", false, true),
+            // IPv4-mapped IPv6 clients match IPv4 rules
+            (&["10.0.0.0/8"], &[], "::ffff:10.0.0.1", true, false),
+            (&[], &["10.0.0.0/8"], "::ffff:10.0.0.1", false, true),
+            (&["127.0.0.1"], &[], "::1", false, false),
+        ];
+
+        for (allow, deny, ip, allowed, denied) in cases {
+            let access = list(allow, deny);
+            let ip: IpAddr = ip.parse().unwrap();
+            assert_eq!(
+                access.is_allowed(ip),
+                *allowed,
+                "allow {:?} deny {:?} {}",
+                allow,
+                deny,
+                ip
+            );
+            assert_eq!(
+                access.is_denied(ip),
+                *denied,
+                "allow {:?} deny {:?} {}",
+                allow,
+                deny,
+                ip
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        assert!(AccessList::new(&["10.0.0.0/8".to_string()], &["bogus/rule".to_string()]).is_err());
+        assert!(list(&[], &[]).is_empty());
+        assert!(!list(&[], &["10.0.0.1"]).is_empty());
+    }
+}
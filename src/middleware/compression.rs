@@ -0,0 +1,266 @@
+use actix_http::encoding::Encoder;
+use actix_web::{
+    Error, HttpMessage,
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{
+        StatusCode,
+        header::{self, AcceptEncoding, ContentEncoding, Encoding, HeaderValue},
+    },
+    middleware::Next,
+    web,
+};
+
+// Used when compression is enabled without naming any content types
+pub const DEFAULT_COMPRESS_TYPES: &[&str] = &[
+    "text/*",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "application/xhtml+xml",
+    "image/svg+xml",
+    "application/wasm",
+];
+
+// Encodings offered to clients, identity included so it can be preferred
+const SUPPORTED_ENCODINGS: &[Encoding] = &[
+    Encoding::identity(),
+    Encoding::brotli(),
+    Encoding::zstd(),
+    Encoding::gzip(),
+];
+
+// Content types eligible for compression, empty disables it
+#[derive(Clone, Debug, Default)]
+pub struct Compression {
+    content_types: Vec<String>,
+}
+
+impl Compression {
+    pub fn new(content_types: &[String]) -> Self {
+        Compression {
+            content_types: content_types
+                .iter()
+                .map(|content_type| content_type.trim().to_ascii_lowercase())
+                .collect(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.content_types.is_empty()
+    }
+
+    // "text/*" matches every text type, anything else must match exactly
+    fn is_compressible(&self, content_type: Option<&HeaderValue>) -> bool {
+        let Some(content_type) = content_type.and_then(|h| h.to_str().ok()) else {
+            return false;
+        };
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_ascii_lowercase();
+
+        self.content_types
+            .iter()
+            .any(|allowed| match allowed.strip_suffix("/*") {
+                Some(top_level) => essence
+                    .split_once('/')
+                    .is_some_and(|(essence_top, _)| essence_top == top_level),
+                None => *allowed == essence,
+            })
+    }
+}
+
+// Best encoding the client accepts, None when it asked for none or only identity
+fn negotiate(req: &ServiceRequest) -> Option<ContentEncoding> {
+    match req
+        .get_header::<AcceptEncoding>()?
+        .negotiate(SUPPORTED_ENCODINGS.iter())?
+    {
+        Encoding::Known(ContentEncoding::Identity) => None,
+        Encoding::Known(encoding) => Some(encoding),
+        _ => None,
+    }
+}
+
+// Drop an existing "Vary: accept-encoding", such as the one of precompressed file responses
+fn remove_vary_accept_encoding(res: &mut ServiceResponse<BoxBody>) {
+    let vary: Vec<HeaderValue> = res
+        .headers()
+        .get_all(header::VARY)
+        .filter(|value| !value.as_bytes().eq_ignore_ascii_case(b"accept-encoding"))
+        .cloned()
+        .collect();
+    res.headers_mut().remove(header::VARY);
+    for value in vary {
+        res.headers_mut().append(header::VARY, value);
+    }
+}
+
+pub async fn compression(
+    compression: web::Data<Compression>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    if !compression.is_enabled() {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    }
+
+    let encoding = negotiate(&req);
+    let mut res = next.call(req).await?.map_into_boxed_body();
+
+    // Already encoded responses, such as precompressed files, pass through unchanged.
+    // Partial content stays a byte range of the original file.
+    if res.headers().contains_key(header::CONTENT_ENCODING)
+        || res.status() == StatusCode::PARTIAL_CONTENT
+        || res.status() == StatusCode::NOT_MODIFIED
+        || !compression.is_compressible(res.headers().get(header::CONTENT_TYPE))
+    {
+        return Ok(res);
+    }
+
+    let Some(encoding) = encoding else {
+        // Caches must not hand the identity response to clients that accept compression
+        remove_vary_accept_encoding(&mut res);
+        res.headers_mut()
+            .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+        return Ok(res);
+    };
+
+    // Byte ranges would refer to the compressed stream, which differs from the file on disk
+    res.headers_mut().remove(header::ACCEPT_RANGES);
+
+    // The compressed body is a different representation of the same content
+    if let Some(etag) = res.headers().get(header::ETAG).cloned()
+        && etag.as_bytes().starts_with(b"\"")
+    {
+        let mut weak = b"W/".to_vec();
+        weak.extend_from_slice(etag.as_bytes());
+        if let Ok(weak) = HeaderValue::from_bytes(&weak) {
+            res.headers_mut().insert(header::ETAG, weak);
+        }
+    }
+
+    // The encoder adds its own Vary entry
+    remove_vary_accept_encoding(&mut res);
+
+    Ok(res.map_body(|head, body| BoxBody::new(Encoder::response(encoding, head, body))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{
+        App, HttpResponse,
+        middleware::from_fn,
+        test::{TestRequest, call_service, init_service, read_body},
+    };
+
+    fn default_types() -> Compression {
+        let types: Vec<String> = DEFAULT_COMPRESS_TYPES
+            .iter()
+            .map(|t| t.to_string())
+            .collect();
+        Compression::new(&types)
+    }
+
+    #[test]
+    fn matches_content_types() {
+        let compression =
+            Compression::new(&[" Text/* ".to_string(), "application/json".to_string()]);
+        let cases = [
+            (Some("text/html"), true),
+            (Some("text/plain; charset=utf-8"), true),
+            (Some("TEXT/CSS"), true),
+            (Some("application/json"), true),
+            (Some("application/json; charset=utf-8"), true),
+            (Some("application/jsonp"), false),
+            (Some("textual/plain"), false),
+            (Some("image/png"), false),
+            (None, false),
+        ];
+        for (content_type, expected) in cases {
+            let header = content_type.map(HeaderValue::from_static);
+            assert_eq!(
+                compression.is_compressible(header.as_ref()),
+                expected,
+                "{:?}",
+                content_type
+            );
+        }
+        assert!(!Compression::default().is_enabled());
+    }
+
+    async fn respond(
+        compression: Compression,
+        content_type: &'static str,
+        accept_encoding: Option<&str>,
+    ) -> ServiceResponse {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(compression))
+                .wrap(from_fn(super::compression))
+                .default_service(web::to(move || async move {
+                    HttpResponse::Ok()
+                        .content_type(content_type)
+                        .insert_header((header::ETAG, "\"abc\""))
+                        .insert_header((header::ACCEPT_RANGES, "bytes"))
+                        .body("hello ".repeat(100))
+                })),
+        )
+        .await;
+        let mut req = TestRequest::get();
+        if let Some(accept_encoding) = accept_encoding {
+            req = req.insert_header((header::ACCEPT_ENCODING, accept_encoding));
+        }
+        call_service(&app, req.to_request()).await
+    }
+
+    #[actix_web::test]
+    async fn compresses_negotiated_responses() {
+        let cases = [
+            (Some("gzip"), Some("gzip")),
+            (Some("gzip, br"), Some("br")),
+            (Some("zstd;q=1, gzip;q=0.5"), Some("zstd")),
+            (Some("identity"), None),
+            (Some("deflate"), None),
+            (None, None),
+        ];
+
+        for (accept_encoding, expected) in cases {
+            let res = respond(default_types(), "text/plain", accept_encoding).await;
+            let headers = res.headers();
+            assert_eq!(
+                headers
+                    .get(header::CONTENT_ENCODING)
+                    .map(|value| value.to_str().unwrap()),
+                expected,
+                "{:?}",
+                accept_encoding
+            );
+            // Every variant tells caches it depends on Accept-Encoding, exactly once
+            let vary: Vec<_> = headers.get_all(header::VARY).collect();
+            assert_eq!(vary.len(), 1, "{:?}", accept_encoding);
+
+            if expected.is_some() {
+                assert_eq!(headers.get(header::ETAG).unwrap(), "W/\"abc\"");
+                assert!(!headers.contains_key(header::ACCEPT_RANGES));
+            } else {
+                assert_eq!(headers.get(header::ETAG).unwrap(), "\"abc\"");
+                assert_eq!(read_body(res).await, "hello ".repeat(100));
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn leaves_other_responses_alone() {
+        let res = respond(default_types(), "image/png", Some("gzip")).await;
+        assert!(!res.headers().contains_key(header::CONTENT_ENCODING));
+
+        let res = respond(Compression::default(), "text/plain", Some("gzip")).await;
+        assert!(!res.headers().contains_key(header::CONTENT_ENCODING));
+        assert!(!res.headers().contains_key(header::VARY));
+    }
+}
@@ -0,0 +1,187 @@
+use actix_web::{
+    Error, HttpRequest,
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{StatusCode, header},
+    middleware::Next,
+    web,
+};
+use serde::Serialize;
+
+use crate::app::errors::ErrorInfo;
+use crate::{ERROR_TEMPLATE_NAME, State};
+
+enum ErrorFormat {
+    Html,
+    Json,
+}
+
+// Struct for Tera context and JSON body of an error page
+#[derive(Serialize)]
+struct ErrorContext {
+    status: u16,
+    reason: &'static str,
+    code: String,
+    message: String,
+    base_url: String,
+}
+
+// The first of HTML or JSON named in Accept. Scripts sending "*/*" keep the plain text body.
+fn preferred_format(req: &HttpRequest) -> Option<ErrorFormat> {
+    let accept = req.headers().get(header::ACCEPT)?.to_str().ok()?;
+    accept
+        .split(',')
+        .map(|part| part.split(';').next().unwrap_or("").trim())
+        .find_map(|mime| match mime {
+            "text/html" | "application/xhtml+xml" => Some(ErrorFormat::Html),
+            "application/json" => Some(ErrorFormat::Json),
+            _ => None,
+        })
+}
+
+// "Not Found" becomes "not_found"
+fn default_code(status: StatusCode) -> String {
+    status
+        .canonical_reason()
+        .unwrap_or("error")
+        .to_lowercase()
+        .replace([' ', '-'], "_")
+}
+
+pub async fn error_pages(
+    state: web::Data<State>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let res = next.call(req).await?.map_into_boxed_body();
+
+    let status = res.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        return Ok(res);
+    }
+
+    let Some(format) = preferred_format(res.request()) else {
+        return Ok(res);
+    };
+
+    // Pages rendered by the handler itself, such as a password prompt, are kept
+    let is_html = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("text/html"));
+    if is_html {
+        return Ok(res);
+    }
+
+    let info = res.response().extensions().get::<ErrorInfo>().cloned();
+
+    let (req, response) = res.into_parts();
+    let (head, body) = response.into_parts();
+
+    let (code, message) = match info {
+        // Raised as AppError, which knows its code
+        Some(info) => (info.code.to_string(), info.message),
+        // Other handlers put a short explanation into the plain text body
+        None => (
+            default_code(status),
+            body.try_into_bytes()
+                .ok()
+                .and_then(|bytes| String::from_utf8(bytes.to_vec()).ok())
+                .filter(|message| !message.trim().is_empty())
+                .unwrap_or_else(|| status.canonical_reason().unwrap_or("Error").to_string()),
+        ),
+    };
+
+    let context = ErrorContext {
+        status: status.as_u16(),
+        reason: status.canonical_reason().unwrap_or("Error"),
+        code,
+        message,
+        base_url: state.base_url.clone(),
+    };
+
+    let (content_type, rendered) = match format {
+        ErrorFormat::Json => (
+            "application/json",
+            serde_json::json!({
+                "status": context.status,
+                "code": context.code,
+                "message": context.message,
+            })
+            .to_string(),
+        ),
+        ErrorFormat::Html => match state.tera.render(
+            ERROR_TEMPLATE_NAME,
+            &tera::Context::from_serialize(&context).unwrap(),
+        ) {
+            Ok(html) => ("text/html; charset=utf-8", html),
+            Err(e) => {
+                log::error!("Tera rendering error for error page: {}", e);
+                ("text/plain; charset=utf-8", context.message)
+            }
+        },
+    };
+
+    // Keep headers such as Retry-After or Location, only the body changes
+    let mut response = head.set_body(BoxBody::new(rendered));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static(content_type),
+    );
+
+    Ok(ServiceResponse::new(req, response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn picks_the_first_known_format() {
+        let cases = [
+            (None, None),
+            (Some("*/*"), None),
+            (Some("text/plain"), None),
+            (Some("text/html"), Some("html")),
+            (
+                Some("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"),
+                Some("html"),
+            ),
+            (Some("application/xhtml+xml"), Some("html")),
+            (Some("application/json"), Some("json")),
+            (Some("application/json;q=0.9, text/html"), Some("json")),
+            (Some("image/webp, text/html;q=0.5"), Some("html")),
+        ];
+
+        for (accept, expected) in cases {
+            let mut req = TestRequest::default();
+            if let Some(accept) = accept {
+                req = req.insert_header((header::ACCEPT, accept));
+            }
+            let format = preferred_format(&req.to_http_request()).map(|format| match format {
+                ErrorFormat::Html => "html",
+                ErrorFormat::Json => "json",
+            });
+            assert_eq!(format, expected, "{:?}", accept);
+        }
+    }
+
+    #[test]
+    fn derives_codes_from_the_status() {
+        let cases = [
+            (StatusCode::NOT_FOUND, "not_found"),
+            (StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large"),
+            (StatusCode::TOO_MANY_REQUESTS, "too_many_requests"),
+            (
+                StatusCode::NON_AUTHORITATIVE_INFORMATION,
+                "non_authoritative_information",
+            ),
+            (StatusCode::from_u16(599).unwrap(), "error"),
+        ];
+        for (status, expected) in cases {
+            assert_eq!(default_code(status), expected, "{}", status);
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use actix_web::{
+    Error, HttpResponse,
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{Method, header},
+    middleware::Next,
+    web,
+};
+
+use crate::utils::proxy::{client_host, client_scheme};
+
+// Port browsers on a plain HTTP listener are sent to, None disables the redirect
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HttpsRedirect {
+    pub port: Option<u16>,
+}
+
+// Browsers ask for HTML, scripts like curl send "*/*" and keep working over plain HTTP
+fn is_browser_navigation(req: &ServiceRequest) -> bool {
+    (req.method() == Method::GET || req.method() == Method::HEAD)
+        && req
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|h| h.to_str().ok())
+            .is_some_and(|accept| accept.contains("text/html"))
+}
+
+// Host part of a Host header value, keeping IPv6 brackets
+fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        match host.find(']') {
+            Some(end) => &host[..=end],
+            None => host,
+        }
+    } else {
+        host.split(':').next().unwrap_or(host)
+    }
+}
+
+pub async fn https_redirect(
+    redirect: web::Data<HttpsRedirect>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let Some(port) = redirect.port else {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    };
+
+    if client_scheme(req.request()) == "https" || !is_browser_navigation(&req) {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    }
+
+    let location = {
+        let host = client_host(req.request());
+        let host = strip_port(&host);
+        let path_and_query = req
+            .uri()
+            .path_and_query()
+            .map(|p| p.as_str())
+            .unwrap_or("/");
+        format!("https://{}:{}{}", host, port, path_and_query)
+    };
+
+    log::debug!("Redirecting {} to {}", req.path(), location);
+    Ok(req.into_response(
+        HttpResponse::Found()
+            .append_header((header::LOCATION, location))
+            .finish(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{
+        App,
+        http::StatusCode,
+        middleware::from_fn,
+        test::{TestRequest, call_service, init_service},
+    };
+
+    #[test]
+    fn strips_ports_from_hosts() {
+        let cases = [
+            ("example.com", "example.com"),
+            ("example.com:8080", "example.com"),
+            ("192.0.2.1:80", "192.0.2.1"),
+            ("[::1]:7070", "[::1]"),
+            ("[2001:db8::1]", "[2001:db8::1]"),
+            ("[::1", "[::1"),
+        ];
+        for (host, expected) in cases {
+            assert_eq!(strip_port(host), expected, "{}", host);
+        }
+    }
+
+    // Status and Location of a request to a plain HTTP server redirecting to `port`
+    async fn redirect(port: Option<u16>, req: TestRequest) -> (StatusCode, Option<String>) {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(HttpsRedirect { port }))
+                .wrap(from_fn(https_redirect))
+                .default_service(web::to(HttpResponse::Ok)),
+        )
+        .await;
+        let res = call_service(&app, req.to_request()).await;
+        let location = res
+            .headers()
+            .get(header::LOCATION)
+            .map(|location| location.to_str().unwrap().to_string());
+        (res.status(), location)
+    }
+
+    #[actix_web::test]
+    async fn redirects_browsers_only() {
+        let browser = || {
+            TestRequest::get()
+                .uri("/docs/a.txt?preview=1")
+                .insert_header((header::HOST, "files.example.com:7070"))
+                .insert_header((header::ACCEPT, "text/html,application/xhtml+xml"))
+        };
+
+        assert_eq!(
+            redirect(Some(8443), browser()).await,
+            (
+                StatusCode::FOUND,
+                Some("https://files.example.com:8443/docs/a.txt?preview=1".to_string())
+            )
+        );
+        assert_eq!(redirect(None, browser()).await, (StatusCode::OK, None));
+
+        let others = [
+            TestRequest::get().insert_header((header::ACCEPT, "*/*")),
+            TestRequest::get(),
+            TestRequest::post().insert_header((header::ACCEPT, "text/html")),
+        ];
+        for req in others {
+            assert_eq!(redirect(Some(8443), req).await, (StatusCode::OK, None));
+        }
+    }
+}
@@ -0,0 +1,5 @@
+pub mod access;
+pub mod compression;
+pub mod error_pages;
+pub mod https_redirect;
+pub mod rate_limit;
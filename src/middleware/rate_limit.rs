@@ -0,0 +1,387 @@
+use actix_web::{
+    Error, HttpResponse, ResponseError,
+    body::{BodySize, BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::Method,
+    middleware::Next,
+    web,
+};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use crate::app::errors::AppError;
+use crate::utils::proxy::client_ip;
+
+// Seconds a client is asked to wait when all transfer slots are taken
+const TRANSFER_RETRY_AFTER_SECS: u64 = 5;
+
+// Drop idle buckets once this many clients are tracked
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+// Marker placed in response extensions by handlers that stream a file
+#[derive(Clone, Copy)]
+pub struct Download;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum TransferKind {
+    Upload,
+    Download,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RateLimitConfig {
+    pub requests_per_sec: Option<f64>,
+    pub burst: Option<f64>,
+    pub max_uploads_per_ip: Option<usize>,
+    pub max_downloads_per_ip: Option<usize>,
+    pub max_uploads: Option<usize>,
+    pub max_downloads: Option<usize>,
+    pub upload_path: String, // Route of file uploads, the only requests taking an upload slot
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+#[derive(Default)]
+struct Transfers {
+    per_ip: HashMap<(IpAddr, TransferKind), usize>,
+    global: HashMap<TransferKind, usize>,
+}
+
+// Token bucket request limiter and concurrent transfer counter, shared by all workers
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+    transfers: Arc<Mutex<Transfers>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        RateLimiter {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+            transfers: Arc::new(Mutex::new(Transfers::default())),
+        }
+    }
+
+    // Take one token for `ip`, or return the number of seconds until one is available
+    fn check_request(&self, ip: IpAddr) -> Result<(), u64> {
+        let Some(rate) = self.config.requests_per_sec else {
+            return Ok(());
+        };
+        let capacity = self.config.burst.unwrap_or(rate).max(1.0);
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > MAX_TRACKED_CLIENTS {
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.last_refill).as_secs_f64() * rate
+                    < capacity
+            });
+        }
+
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: capacity,
+            last_refill: now,
+        });
+
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / rate).ceil().max(1.0) as u64)
+        }
+    }
+
+    // Reserve a transfer slot, released when the returned guard is dropped
+    fn acquire_transfer(&self, ip: IpAddr, kind: TransferKind) -> Option<TransferGuard> {
+        let (per_ip_limit, global_limit) = match kind {
+            TransferKind::Upload => (self.config.max_uploads_per_ip, self.config.max_uploads),
+            TransferKind::Download => (self.config.max_downloads_per_ip, self.config.max_downloads),
+        };
+
+        let mut transfers = self.transfers.lock().unwrap();
+        let per_ip = transfers.per_ip.get(&(ip, kind)).copied().unwrap_or(0);
+        let global = transfers.global.get(&kind).copied().unwrap_or(0);
+
+        if per_ip_limit.is_some_and(|limit| per_ip >= limit)
+            || global_limit.is_some_and(|limit| global >= limit)
+        {
+            return None;
+        }
+
+        *transfers.per_ip.entry((ip, kind)).or_insert(0) += 1;
+        *transfers.global.entry(kind).or_insert(0) += 1;
+
+        Some(TransferGuard {
+            transfers: self.transfers.clone(),
+            ip,
+            kind,
+        })
+    }
+}
+
+struct TransferGuard {
+    transfers: Arc<Mutex<Transfers>>,
+    ip: IpAddr,
+    kind: TransferKind,
+}
+
+impl Drop for TransferGuard {
+    fn drop(&mut self) {
+        let mut transfers = self.transfers.lock().unwrap();
+        if let Some(count) = transfers.per_ip.get_mut(&(self.ip, self.kind)) {
+            *count -= 1;
+            if *count == 0 {
+                transfers.per_ip.remove(&(self.ip, self.kind));
+            }
+        }
+        if let Some(count) = transfers.global.get_mut(&self.kind) {
+            *count = count.saturating_sub(1);
+        }
+    }
+}
+
+// Response body which holds on to a transfer slot until it is fully sent or dropped
+struct GuardedBody {
+    body: BoxBody,
+    _guard: TransferGuard,
+}
+
+impl MessageBody for GuardedBody {
+    type Error = Box<dyn std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<web::Bytes, Self::Error>>> {
+        Pin::new(&mut self.body).poll_next(cx)
+    }
+}
+
+fn too_many_requests(retry_after: u64) -> HttpResponse {
+    AppError::RateLimited { retry_after }.error_response()
+}
+
+pub async fn rate_limit(
+    limiter: web::Data<RateLimiter>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let Some(ip) = client_ip(req.request()) else {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    };
+
+    if let Err(retry_after) = limiter.check_request(ip) {
+        log::debug!("Rate limit hit for {}", ip);
+        return Ok(req.into_response(too_many_requests(retry_after)));
+    }
+
+    // Uploads are known up front, refuse them before the body is read
+    let _upload_guard = if req.method() == Method::POST && req.path() == limiter.config.upload_path
+    {
+        match limiter.acquire_transfer(ip, TransferKind::Upload) {
+            Some(guard) => Some(guard),
+            None => {
+                log::debug!("Concurrent upload limit hit for {}", ip);
+                return Ok(req.into_response(too_many_requests(TRANSFER_RETRY_AFTER_SECS)));
+            }
+        }
+    } else {
+        None
+    };
+
+    let res = next.call(req).await?.map_into_boxed_body();
+
+    // Downloads are only known once the handler has resolved the path
+    if !res.response().extensions().contains::<Download>() {
+        return Ok(res);
+    }
+
+    match limiter.acquire_transfer(ip, TransferKind::Download) {
+        Some(guard) => Ok(res.map_body(|_, body| {
+            BoxBody::new(GuardedBody {
+                body,
+                _guard: guard,
+            })
+        })),
+        None => {
+            log::debug!("Concurrent download limit hit for {}", ip);
+            let (req, _) = res.into_parts();
+            Ok(ServiceResponse::new(
+                req,
+                too_many_requests(TRANSFER_RETRY_AFTER_SECS),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{
+        App,
+        http::StatusCode,
+        middleware::from_fn,
+        test::{TestRequest, call_service, init_service},
+    };
+    use std::time::Duration;
+
+    const CLIENT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 2));
+
+    // Pretend the last request of `ip` was `secs` seconds ago
+    fn rewind(limiter: &RateLimiter, ip: IpAddr, secs: f64) {
+        let mut buckets = limiter.buckets.lock().unwrap();
+        let bucket = buckets.get_mut(&ip).unwrap();
+        bucket.last_refill -= Duration::from_secs_f64(secs);
+    }
+
+    #[test]
+    fn unlimited_without_rate() {
+        let limiter = RateLimiter::new(RateLimitConfig::default());
+        for _ in 0..1000 {
+            assert_eq!(limiter.check_request(CLIENT), Ok(()));
+        }
+    }
+
+    #[test]
+    fn allows_bursts_then_asks_to_wait() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            requests_per_sec: Some(0.5),
+            burst: Some(3.0),
+            ..RateLimitConfig::default()
+        });
+
+        for _ in 0..3 {
+            assert_eq!(limiter.check_request(CLIENT), Ok(()));
+        }
+        // One token every two seconds
+        assert_eq!(limiter.check_request(CLIENT), Err(2));
+        // Other clients have their own bucket
+        assert_eq!(limiter.check_request(OTHER), Ok(()));
+
+        rewind(&limiter, CLIENT, 1.0);
+        assert_eq!(limiter.check_request(CLIENT), Err(1));
+        rewind(&limiter, CLIENT, 1.0);
+        assert_eq!(limiter.check_request(CLIENT), Ok(()));
+
+        // The bucket never fills beyond the burst
+        rewind(&limiter, CLIENT, 3600.0);
+        for _ in 0..3 {
+            assert_eq!(limiter.check_request(CLIENT), Ok(()));
+        }
+        assert!(limiter.check_request(CLIENT).is_err());
+    }
+
+    #[test]
+    fn burst_defaults_to_the_rate() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            requests_per_sec: Some(2.0),
+            ..RateLimitConfig::default()
+        });
+        assert_eq!(limiter.check_request(CLIENT), Ok(()));
+        assert_eq!(limiter.check_request(CLIENT), Ok(()));
+        assert_eq!(limiter.check_request(CLIENT), Err(1));
+    }
+
+    #[test]
+    fn limits_concurrent_transfers() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            max_uploads_per_ip: Some(1),
+            max_uploads: Some(2),
+            max_downloads_per_ip: Some(2),
+            ..RateLimitConfig::default()
+        });
+
+        let first = limiter.acquire_transfer(CLIENT, TransferKind::Upload);
+        assert!(first.is_some());
+        // Per client limit
+        assert!(
+            limiter
+                .acquire_transfer(CLIENT, TransferKind::Upload)
+                .is_none()
+        );
+        // Global limit
+        let second = limiter.acquire_transfer(OTHER, TransferKind::Upload);
+        assert!(second.is_some());
+        let third = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 3));
+        assert!(
+            limiter
+                .acquire_transfer(third, TransferKind::Upload)
+                .is_none()
+        );
+
+        // Downloads are counted separately
+        let downloads: Vec<_> = (0..2)
+            .map(|_| limiter.acquire_transfer(CLIENT, TransferKind::Download))
+            .collect();
+        assert!(downloads.iter().all(Option::is_some));
+        assert!(
+            limiter
+                .acquire_transfer(CLIENT, TransferKind::Download)
+                .is_none()
+        );
+
+        // Finished transfers give their slot back
+        drop(first);
+        assert!(
+            limiter
+                .acquire_transfer(CLIENT, TransferKind::Upload)
+                .is_some()
+        );
+        drop(downloads);
+        let transfers = limiter.transfers.lock().unwrap();
+        assert!(
+            !transfers
+                .per_ip
+                .contains_key(&(CLIENT, TransferKind::Download))
+        );
+        assert_eq!(transfers.global.get(&TransferKind::Download), Some(&0));
+    }
+
+    #[actix_web::test]
+    async fn takes_upload_slots_for_uploads_only() {
+        let limiter = web::Data::new(RateLimiter::new(RateLimitConfig {
+            max_uploads: Some(0),
+            upload_path: "/files/upload".to_string(),
+            ..RateLimitConfig::default()
+        }));
+        let app = init_service(
+            App::new()
+                .wrap(from_fn(rate_limit))
+                .app_data(limiter)
+                .default_service(web::to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let cases = [
+            ("/files/upload", StatusCode::TOO_MANY_REQUESTS),
+            ("/files/paste", StatusCode::OK),
+            ("/files/share", StatusCode::OK),
+            ("/files/.holonet/s/token", StatusCode::OK),
+            ("/upload", StatusCode::OK),
+        ];
+        for (path, status) in cases {
+            let req = TestRequest::post()
+                .uri(path)
+                .peer_addr("192.0.2.1:1234".parse().unwrap())
+                .to_request();
+            assert_eq!(call_service(&app, req).await.status(), status, "{}", path);
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use ifcfg::IfCfgError;
 use rcgen::Error as RcgenError;
 use rustls::Error as RustlsError;
 use std::fmt;
@@ -7,6 +8,8 @@ pub enum UtilsError {
     RustlsError(RustlsError),
     RcgenEror(RcgenError),
     StdIoError(std::io::Error),
+    IfCfgError(IfCfgError),
+    NoUsableAddress(String),
     UnknownType(String),
 }
 
@@ -28,6 +31,21 @@ impl From<std::io::Error> for UtilsError {
     }
 }
 
+impl From<IfCfgError> for UtilsError {
+    fn from(err: IfCfgError) -> Self {
+        UtilsError::IfCfgError(err)
+    }
+}
+
+impl From<UtilsError> for std::io::Error {
+    fn from(err: UtilsError) -> Self {
+        match err {
+            UtilsError::StdIoError(err) => err,
+            err => std::io::Error::other(err.to_string()),
+        }
+    }
+}
+
 impl fmt::Display for UtilsError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -40,6 +58,12 @@ impl fmt::Display for UtilsError {
             UtilsError::StdIoError(msg) => {
                 write!(f, "IO error: {}", msg)
             }
+            UtilsError::IfCfgError(err) => {
+                write!(f, "Could not get network interfaces: {}", err)
+            }
+            UtilsError::NoUsableAddress(name) => {
+                write!(f, "'{}' has no usable address to bind to", name)
+            }
             UtilsError::UnknownType(msg) => {
                 write!(f, "Unknown listener type: {}", msg)
             }
@@ -1,2 +1,8 @@
 pub mod errors;
+pub mod net;
+pub mod proxy;
+pub mod throttle;
+#[cfg(unix)]
+pub mod unix;
+#[allow(clippy::module_inception)]
 pub mod utils;
@@ -0,0 +1,319 @@
+use ifcfg::IfCfg;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+
+use super::errors::UtilsError;
+
+// Look up a network interface by name
+pub fn find_interface(name: &str) -> Result<Option<IfCfg>, UtilsError> {
+    Ok(IfCfg::get()?.into_iter().find(|iface| iface.name == name))
+}
+
+// Resolve the --host value, which may be an address, an interface name or a host name.
+// For interfaces the first IPv4 address is used (or the first IPv6 one if there is none)
+// unless `all_addresses` asks for every IPv4 and IPv6 address.
+pub fn resolve_host(
+    host: &str,
+    port: u16,
+    all_addresses: bool,
+) -> Result<Vec<SocketAddr>, UtilsError> {
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(vec![SocketAddr::new(ip, port)]);
+    }
+
+    if let Some(iface) = find_interface(host)? {
+        let mut addresses: Vec<SocketAddr> = iface
+            .addresses
+            .iter()
+            .filter_map(|addr_info| addr_info.address)
+            .map(|mut address| {
+                address.set_port(port);
+                address
+            })
+            .collect();
+
+        if !all_addresses {
+            let preferred = addresses
+                .iter()
+                .find(|address| address.is_ipv4())
+                .or_else(|| addresses.first())
+                .copied();
+            addresses = preferred.into_iter().collect();
+        }
+
+        if addresses.is_empty() {
+            return Err(UtilsError::NoUsableAddress(host.to_string()));
+        }
+
+        log::debug!("Interface {} resolved to {:?}", host, addresses);
+        return Ok(addresses);
+    }
+
+    // Neither an address nor an interface, treat it as a host name
+    let addresses: Vec<SocketAddr> = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| {
+            std::io::Error::new(
+                e.kind(),
+                format!(
+                    "'{}' is neither an address, an interface nor a known host name: {}",
+                    host, e
+                ),
+            )
+        })?
+        .collect();
+    if addresses.is_empty() {
+        return Err(UtilsError::NoUsableAddress(host.to_string()));
+    }
+    Ok(addresses)
+}
+
+// A --listen value before its host has been resolved
+#[derive(Clone, Debug)]
+pub struct ListenSpec {
+    pub tls: Option<bool>, // None when no scheme was given
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+// A resolved address the server binds to
+#[derive(Clone, Copy, Debug)]
+pub struct Listener {
+    pub tls: bool,
+    pub address: SocketAddr,
+    pub v6_only: bool, // IPv6 wildcard which leaves IPv4 to another listener on the same port
+}
+
+impl Listener {
+    pub fn scheme(&self) -> &'static str {
+        if self.tls { "https" } else { "http" }
+    }
+}
+
+// Parse "[http://|https://]HOST[:PORT]", IPv6 addresses with a port need brackets
+pub fn parse_listen(value: &str) -> Result<ListenSpec, String> {
+    let (tls, rest) = if let Some(rest) = value.strip_prefix("https://") {
+        (Some(true), rest)
+    } else if let Some(rest) = value.strip_prefix("http://") {
+        (Some(false), rest)
+    } else {
+        (None, value)
+    };
+    let rest = rest.trim_end_matches('/');
+
+    let parse_port = |port: &str| {
+        port.parse::<u16>()
+            .map_err(|_| format!("Invalid port '{}' in '{}'", port, value))
+    };
+
+    let (host, port) = if let Some(bracketed) = rest.strip_prefix('[') {
+        // [v6addr] or [v6addr]:port
+        let (host, after) = bracketed
+            .split_once(']')
+            .ok_or_else(|| format!("Missing ']' in '{}'", value))?;
+        match after.strip_prefix(':') {
+            Some(port) => (host, Some(parse_port(port)?)),
+            None if after.is_empty() => (host, None),
+            None => return Err(format!("Unexpected '{}' in '{}'", after, value)),
+        }
+    } else if rest.matches(':').count() == 1 {
+        let (host, port) = rest.split_once(':').unwrap();
+        (host, Some(parse_port(port)?))
+    } else {
+        // Host name, IPv4 address or bare IPv6 address without a port
+        (rest, None)
+    };
+
+    if host.is_empty() {
+        return Err(format!("Missing host in '{}'", value));
+    }
+
+    Ok(ListenSpec {
+        tls,
+        host: host.to_string(),
+        port,
+    })
+}
+
+// Host and port part of a URL, IPv6 addresses are bracketed and
+// link-local ones carry their zone (RFC 6874), e.g. [fe80::1%25eth0]:7070
+pub fn url_authority(address: &SocketAddr) -> String {
+    match address {
+        SocketAddr::V4(v4) => v4.to_string(),
+        SocketAddr::V6(v6) if v6.scope_id() != 0 => {
+            let zone = interface_name_for_scope(v6.scope_id())
+                .unwrap_or_else(|| v6.scope_id().to_string());
+            format!("[{}%25{}]:{}", v6.ip(), zone, v6.port())
+        }
+        SocketAddr::V6(v6) => format!("[{}]:{}", v6.ip(), v6.port()),
+    }
+}
+
+// Find the interface whose IPv6 addresses use `scope_id`
+fn interface_name_for_scope(scope_id: u32) -> Option<String> {
+    IfCfg::get().ok()?.into_iter().find_map(|iface| {
+        iface
+            .addresses
+            .iter()
+            .any(|addr_info| {
+                matches!(addr_info.address, Some(SocketAddr::V6(v6)) if v6.scope_id() == scope_id)
+            })
+            .then(|| iface.name.clone())
+    })
+}
+
+// Create a listening socket. An IPv6 wildcard socket is dual-stack unless `v6_only` is
+// set, which is needed when an IPv4 listener shares its port.
+pub fn bind_tcp(address: SocketAddr, v6_only: bool) -> std::io::Result<std::net::TcpListener> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    socket.set_reuse_address(true)?;
+    if address.is_ipv6() {
+        socket.set_only_v6(v6_only)?;
+    }
+    socket.bind(&address.into())?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_addresses() {
+        let cases = [
+            ("0.0.0.0", "0.0.0.0:7070"),
+            ("127.0.0.1", "127.0.0.1:7070"),
+            ("::", "[::]:7070"),
+            ("::1", "[::1]:7070"),
+        ];
+        for (host, expected) in cases {
+            let addresses = resolve_host(host, 7070, false).unwrap();
+            assert_eq!(
+                addresses,
+                vec![expected.parse::<SocketAddr>().unwrap()],
+                "{}",
+                host
+            );
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn resolves_interfaces() {
+        let addresses = resolve_host("lo", 7070, false).unwrap();
+        assert_eq!(addresses, vec!["127.0.0.1:7070".parse().unwrap()]);
+
+        let all = resolve_host("lo", 7070, true).unwrap();
+        assert!(all.contains(&"127.0.0.1:7070".parse().unwrap()));
+        assert!(all.iter().all(|address| address.port() == 7070));
+    }
+
+    #[test]
+    fn rejects_unknown_hosts() {
+        assert!(find_interface("no-such-interface0").unwrap().is_none());
+        assert!(resolve_host("no-such-interface.invalid", 7070, false).is_err());
+    }
+
+    #[test]
+    fn parses_listen_values() {
+        // Value, then the expected scheme, host and port
+        let cases: &[(&str, Option<bool>, &str, Option<u16>)] = &[
+            ("0.0.0.0", None, "0.0.0.0", None),
+            ("0.0.0.0:8080", None, "0.0.0.0", Some(8080)),
+            ("localhost:80", None, "localhost", Some(80)),
+            ("eth0", None, "eth0", None),
+            (
+                "http://127.0.0.1:7070",
+                Some(false),
+                "127.0.0.1",
+                Some(7070),
+            ),
+            (
+                "https://files.example.com/",
+                Some(true),
+                "files.example.com",
+                None,
+            ),
+            (
+                "https://example.com:8443/",
+                Some(true),
+                "example.com",
+                Some(8443),
+            ),
+            // IPv6 without a port may go without brackets
+            ("::", None, "::", None),
+            ("::1", None, "::1", None),
+            ("fe80::1%eth0", None, "fe80::1%eth0", None),
+            ("[::]", None, "::", None),
+            ("[::1]:7070", None, "::1", Some(7070)),
+            (
+                "https://[2001:db8::1]:443",
+                Some(true),
+                "2001:db8::1",
+                Some(443),
+            ),
+            (
+                "http://[fe80::1%eth0]:80/",
+                Some(false),
+                "fe80::1%eth0",
+                Some(80),
+            ),
+        ];
+
+        for (value, tls, host, port) in cases {
+            let spec = parse_listen(value).unwrap_or_else(|e| panic!("{}: {}", value, e));
+            assert_eq!(spec.tls, *tls, "{}", value);
+            assert_eq!(spec.host, *host, "{}", value);
+            assert_eq!(spec.port, *port, "{}", value);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_listen_values() {
+        let cases = [
+            "",
+            "https://",
+            ":8080",
+            "[]:8080",
+            "[::1",
+            "[::1]8080",
+            "[::1]:",
+            "[::1]:port",
+            "127.0.0.1:",
+            "127.0.0.1:65536",
+            "127.0.0.1:-1",
+            "http://localhost:http",
+        ];
+        for value in cases {
+            assert!(parse_listen(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn formats_url_authorities() {
+        let cases = [
+            ("127.0.0.1:7070", "127.0.0.1:7070"),
+            ("[::]:80", "[::]:80"),
+            ("[2001:db8::1]:443", "[2001:db8::1]:443"),
+        ];
+        for (address, expected) in cases {
+            let address: SocketAddr = address.parse().unwrap();
+            assert_eq!(url_authority(&address), expected);
+        }
+
+        // Zones of unknown interfaces fall back to the numeric scope id
+        let address = SocketAddr::V6(std::net::SocketAddrV6::new(
+            "fe80::1".parse().unwrap(),
+            7070,
+            0,
+            4_000_000,
+        ));
+        assert_eq!(url_authority(&address), "[fe80::1%254000000]:7070");
+    }
+}
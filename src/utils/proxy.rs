@@ -0,0 +1,225 @@
+use actix_web::{HttpRequest, http::header, web};
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+use crate::middleware::access::resolve_rule;
+
+// Reverse proxies whose X-Forwarded-* headers are believed
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies {
+    nets: Vec<IpNet>,
+    unix: bool, // Trust connections over Unix domain sockets, which carry no peer address
+}
+
+impl TrustedProxies {
+    // Entries are addresses, CIDR ranges, interface names or "unix"
+    pub fn new(rules: &[String]) -> Result<Self, String> {
+        let mut proxies = TrustedProxies::default();
+        for rule in rules {
+            if rule == "unix" {
+                proxies.unix = true;
+            } else {
+                proxies.nets.extend(resolve_rule(rule)?);
+            }
+        }
+        Ok(proxies)
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.nets.iter().any(|net| net.contains(&ip))
+    }
+
+    // Whether the direct peer of `req` is a trusted proxy
+    fn trusts_peer(&self, req: &HttpRequest) -> bool {
+        match req.peer_addr() {
+            Some(addr) => self.contains(addr.ip()),
+            None => self.unix,
+        }
+    }
+}
+
+fn trusted_proxies(req: &HttpRequest) -> Option<&TrustedProxies> {
+    req.app_data::<web::Data<TrustedProxies>>()
+        .map(|proxies| proxies.get_ref())
+}
+
+fn header_value<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers()
+        .get(name)
+        .and_then(|h| h.to_str().ok())
+        .map(|value| value.split(',').next().unwrap_or(value).trim())
+        .filter(|value| !value.is_empty())
+}
+
+// Address of the client, taken from X-Forwarded-For when the request came through a trusted proxy.
+// The chain is walked from the right and the first address which is not a trusted proxy wins.
+pub fn client_ip(req: &HttpRequest) -> Option<IpAddr> {
+    let peer = req.peer_addr().map(|addr| addr.ip());
+
+    let Some(proxies) = trusted_proxies(req).filter(|proxies| proxies.trusts_peer(req)) else {
+        return peer;
+    };
+
+    let chain: Vec<IpAddr> = req
+        .headers()
+        .get_all("X-Forwarded-For")
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|entry| entry.trim().parse::<IpAddr>().ok())
+        .collect();
+
+    chain
+        .iter()
+        .rev()
+        .find(|ip| !proxies.contains(**ip))
+        .or(chain.first())
+        .copied()
+        .or(peer)
+}
+
+// Scheme the client used, honouring X-Forwarded-Proto from a trusted proxy
+pub fn client_scheme(req: &HttpRequest) -> String {
+    if trusted_proxies(req).is_some_and(|proxies| proxies.trusts_peer(req))
+        && let Some(proto) = header_value(req, "X-Forwarded-Proto")
+    {
+        return proto.to_ascii_lowercase();
+    }
+    if req.app_config().secure() {
+        "https".to_string()
+    } else {
+        "http".to_string()
+    }
+}
+
+// Host the client asked for, honouring X-Forwarded-Host from a trusted proxy
+pub fn client_host(req: &HttpRequest) -> String {
+    if trusted_proxies(req).is_some_and(|proxies| proxies.trusts_peer(req))
+        && let Some(host) = header_value(req, "X-Forwarded-Host")
+    {
+        return host.to_string();
+    }
+    // ConnectionInfo would believe forwarding headers from anyone, so read Host directly
+    req.headers()
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .map(|host| host.to_string())
+        .or_else(|| req.uri().authority().map(|a| a.to_string()))
+        .unwrap_or_else(|| req.app_config().host().to_string())
+}
+
+// Address shown in the access log
+pub fn client_ip_display(req: &HttpRequest) -> String {
+    client_ip(req)
+        .map(|ip| ip.to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    const PROXY: &str = "10.0.0.1:40000";
+
+    fn request(proxies: &[&str], peer: Option<&str>, headers: &[(&str, &str)]) -> HttpRequest {
+        let rules: Vec<String> = proxies.iter().map(|rule| rule.to_string()).collect();
+        let mut req =
+            TestRequest::default().app_data(web::Data::new(TrustedProxies::new(&rules).unwrap()));
+        if let Some(peer) = peer {
+            req = req.peer_addr(peer.parse().unwrap());
+        }
+        for (name, value) in headers {
+            req = req.insert_header((*name, *value));
+        }
+        req.to_http_request()
+    }
+
+    // Trusted proxies, peer, X-Forwarded-For, then the client address
+    type ClientCase = (
+        &'static [&'static str],
+        Option<&'static str>,
+        Option<&'static str>,
+        Option<&'static str>,
+    );
+
+    #[test]
+    fn finds_client_addresses() {
+        let cases: &[ClientCase] = &[
+            (&[], Some(PROXY), None, Some("10.0.0.1")),
+            // Forwarding headers from untrusted peers are ignored
+            (&[], Some(PROXY), Some("203.0.113.7"), Some("10.0.0.1")),
+            (
+                &["10.0.0.2"],
+                Some(PROXY),
+                Some("203.0.113.7"),
+                Some("10.0.0.1"),
+            ),
+            (
+                &["10.0.0.0/8"],
+                Some(PROXY),
+                Some("203.0.113.7"),
+                Some("203.0.113.7"),
+            ),
+            (&["10.0.0.0/8"], Some(PROXY), None, Some("10.0.0.1")),
+            // The rightmost address which is not a trusted proxy wins
+            (
+                &["10.0.0.0/8"],
+                Some(PROXY),
+                Some("198.51.100.1, 203.0.113.7, 10.0.0.5"),
+                Some("203.0.113.7"),
+            ),
+            // A chain made only of proxies gives its first entry
+            (
+                &["10.0.0.0/8"],
+                Some(PROXY),
+                Some("10.0.0.9, 10.0.0.5"),
+                Some("10.0.0.9"),
+            ),
+            // Garbage is skipped
+            (
+                &["10.0.0.0/8"],
+                Some(PROXY),
+                Some("unknown, 203.0.113.7"),
+                Some("203.0.113.7"),
+            ),
+            // Unix domain sockets have no peer address
+            (&[], None, Some("203.0.113.7"), None),
+            (&["unix"], None, Some("203.0.113.7"), Some("203.0.113.7")),
+        ];
+
+        for (proxies, peer, forwarded, expected) in cases {
+            let headers: Vec<(&str, &str)> = forwarded
+                .map(|value| ("X-Forwarded-For", value))
+                .into_iter()
+                .collect();
+            let req = request(proxies, *peer, &headers);
+            assert_eq!(
+                client_ip(&req),
+                expected.map(|ip| ip.parse().unwrap()),
+                "{:?} {:?} {:?}",
+                proxies,
+                peer,
+                forwarded
+            );
+        }
+    }
+
+    #[test]
+    fn honours_forwarded_scheme_and_host_from_proxies() {
+        let headers = [
+            ("Host", "10.0.0.2:7070"),
+            ("X-Forwarded-Proto", "HTTPS"),
+            ("X-Forwarded-Host", "files.example.com, other.example.com"),
+        ];
+
+        let trusted = request(&["10.0.0.0/8"], Some(PROXY), &headers);
+        assert_eq!(client_scheme(&trusted), "https");
+        assert_eq!(client_host(&trusted), "files.example.com");
+
+        let untrusted = request(&[], Some(PROXY), &headers);
+        assert_eq!(client_scheme(&untrusted), "http");
+        assert_eq!(client_host(&untrusted), "10.0.0.2:7070");
+        assert_eq!(client_ip_display(&request(&[], None, &[])), "-");
+    }
+}
@@ -0,0 +1,206 @@
+use actix_web::{
+    body::{BodySize, BoxBody, MessageBody},
+    rt::time::{Sleep, sleep},
+    web::Bytes,
+};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+// Token bucket measured in bytes. Transfers may go into debt and then wait it off.
+#[derive(Debug)]
+struct RateBucket {
+    rate: f64,
+    available: f64,
+    last_refill: Instant,
+}
+
+impl RateBucket {
+    fn new(bytes_per_sec: u64) -> Self {
+        RateBucket {
+            rate: bytes_per_sec as f64,
+            available: 0.0,
+            last_refill: Instant::now(),
+        }
+    }
+
+    // Take `bytes` out of the bucket and return how long to wait before sending more
+    fn reserve(&mut self, bytes: usize) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        // Allow at most one second worth of burst
+        self.available = (self.available + elapsed * self.rate).min(self.rate);
+        self.last_refill = now;
+        self.available -= bytes as f64;
+
+        if self.available < 0.0 {
+            Duration::from_secs_f64(-self.available / self.rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+// Bandwidth cap for one direction: shared by every transfer and per connection
+#[derive(Clone, Debug, Default)]
+pub struct Bandwidth {
+    global: Option<Arc<Mutex<RateBucket>>>,
+    per_connection: Option<u64>,
+}
+
+impl Bandwidth {
+    pub fn new(global: Option<u64>, per_connection: Option<u64>) -> Self {
+        Bandwidth {
+            global: global.map(|rate| Arc::new(Mutex::new(RateBucket::new(rate)))),
+            per_connection,
+        }
+    }
+
+    pub fn is_limited(&self) -> bool {
+        self.global.is_some() || self.per_connection.is_some()
+    }
+
+    // Throttle for a single transfer
+    pub fn connection(&self) -> Throttle {
+        Throttle {
+            global: self.global.clone(),
+            local: self.per_connection.map(RateBucket::new),
+        }
+    }
+}
+
+pub struct Throttle {
+    global: Option<Arc<Mutex<RateBucket>>>,
+    local: Option<RateBucket>,
+}
+
+impl Throttle {
+    // Account for `bytes` sent or received and return the required pause
+    fn delay(&mut self, bytes: usize) -> Duration {
+        let global = self
+            .global
+            .as_ref()
+            .map(|bucket| bucket.lock().unwrap().reserve(bytes))
+            .unwrap_or_default();
+        let local = self
+            .local
+            .as_mut()
+            .map(|bucket| bucket.reserve(bytes))
+            .unwrap_or_default();
+        global.max(local)
+    }
+
+    pub async fn consume(&mut self, bytes: usize) {
+        let delay = self.delay(bytes);
+        if !delay.is_zero() {
+            sleep(delay).await;
+        }
+    }
+}
+
+// Response body that paces its chunks through a throttle
+pub struct ThrottledBody {
+    body: BoxBody,
+    throttle: Throttle,
+    pause: Option<Pin<Box<Sleep>>>,
+}
+
+impl ThrottledBody {
+    pub fn new(body: BoxBody, throttle: Throttle) -> Self {
+        ThrottledBody {
+            body,
+            throttle,
+            pause: None,
+        }
+    }
+}
+
+impl MessageBody for ThrottledBody {
+    type Error = Box<dyn std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        if let Some(pause) = self.pause.as_mut() {
+            if pause.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.pause = None;
+        }
+
+        let chunk = Pin::new(&mut self.body).poll_next(cx);
+        if let Poll::Ready(Some(Ok(bytes))) = &chunk {
+            let delay = self.throttle.delay(bytes.len());
+            if !delay.is_zero() {
+                self.pause = Some(Box::pin(sleep(delay)));
+            }
+        }
+        chunk
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Delays are computed from the clock, allow for the time the test itself takes
+    fn assert_about(actual: Duration, expected_secs: f64) {
+        let actual = actual.as_secs_f64();
+        assert!(
+            (actual - expected_secs).abs() < 0.05,
+            "expected about {}s, got {}s",
+            expected_secs,
+            actual
+        );
+    }
+
+    #[test]
+    fn buckets_go_into_debt() {
+        let mut bucket = RateBucket::new(1000);
+        assert_about(bucket.reserve(500), 0.5);
+        assert_about(bucket.reserve(1000), 1.5);
+
+        // Idle time refills at most one second worth of bytes
+        let mut bucket = RateBucket::new(1000);
+        bucket.last_refill -= Duration::from_secs(60);
+        assert_eq!(bucket.reserve(1000), Duration::ZERO);
+        assert_about(bucket.reserve(250), 0.25);
+    }
+
+    #[test]
+    fn throttles_wait_for_the_slower_bucket() {
+        assert!(!Bandwidth::default().is_limited());
+        let mut unlimited = Bandwidth::default().connection();
+        assert_eq!(unlimited.delay(usize::MAX), Duration::ZERO);
+
+        let bandwidth = Bandwidth::new(Some(1000), Some(100));
+        assert!(bandwidth.is_limited());
+        assert_about(bandwidth.connection().delay(50), 0.5);
+
+        // Connections share the global bucket but not the per connection one
+        let bandwidth = Bandwidth::new(Some(100), Some(1000));
+        let mut first = bandwidth.connection();
+        let mut second = bandwidth.connection();
+        assert_about(first.delay(50), 0.5);
+        assert_about(second.delay(50), 1.0);
+    }
+
+    #[actix_web::test]
+    async fn throttled_bodies_keep_their_content() {
+        let content = vec![7u8; 4096];
+        let body = ThrottledBody::new(
+            BoxBody::new(content.clone()),
+            Bandwidth::new(None, Some(1 << 20)).connection(),
+        );
+        assert_eq!(body.size(), BodySize::Sized(4096));
+        let bytes = actix_web::body::to_bytes(body).await.unwrap();
+        assert_eq!(bytes.as_ref(), content.as_slice());
+    }
+}
@@ -0,0 +1,219 @@
+use nix::unistd::{Group, User};
+use socket2::Socket;
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+
+// First file descriptor passed by systemd socket activation
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+// A listening socket handed over by systemd
+pub enum ActivatedSocket {
+    Tcp(std::net::TcpListener),
+    Unix(UnixListener),
+}
+
+// Resolve "USER[:GROUP]" given as names or numeric ids
+fn resolve_owner(owner: &str) -> std::io::Result<(Option<u32>, Option<u32>)> {
+    let not_found = |what: &str, name: &str| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("Unknown {} '{}'", what, name),
+        )
+    };
+
+    let (user, group) = match owner.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (owner, None),
+    };
+
+    let uid = match user {
+        "" => None,
+        user => Some(match user.parse::<u32>() {
+            Ok(uid) => uid,
+            Err(_) => User::from_name(user)?
+                .ok_or_else(|| not_found("user", user))?
+                .uid
+                .as_raw(),
+        }),
+    };
+
+    let gid = match group {
+        None | Some("") => None,
+        Some(group) => Some(match group.parse::<u32>() {
+            Ok(gid) => gid,
+            Err(_) => Group::from_name(group)?
+                .ok_or_else(|| not_found("group", group))?
+                .gid
+                .as_raw(),
+        }),
+    };
+
+    Ok((uid, gid))
+}
+
+// Bind a Unix domain socket, replacing a stale socket file from a previous run.
+// The socket is bound in a private folder next to it and moved into place once its
+// mode and owner are set, so nobody can connect while it still has the default mode.
+pub fn bind_unix_socket(
+    path: &Path,
+    mode: Option<u32>,
+    owner: Option<&str>,
+) -> std::io::Result<UnixListener> {
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+
+    let file_name = path.file_name().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} is not a socket path", path.display()),
+        )
+    })?;
+    let private_dir = path.with_file_name(format!(
+        ".{}.{:016x}",
+        file_name.to_string_lossy(),
+        getrandom::u64().map_err(std::io::Error::other)?
+    ));
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&private_dir)?;
+
+    let result = (|| {
+        let private_path = private_dir.join(file_name);
+        let listener = UnixListener::bind(&private_path)?;
+
+        if let Some(mode) = mode {
+            std::fs::set_permissions(&private_path, std::fs::Permissions::from_mode(mode))?;
+        }
+
+        if let Some(owner) = owner {
+            let (uid, gid) = resolve_owner(owner)?;
+            std::os::unix::fs::chown(&private_path, uid, gid)?;
+        }
+
+        std::fs::rename(&private_path, path)?;
+        listener.set_nonblocking(true)?;
+        Ok(listener)
+    })();
+
+    if let Err(e) = std::fs::remove_dir_all(&private_dir) {
+        log::warn!("Failed to remove {}: {}", private_dir.display(), e);
+    }
+    result
+}
+
+// Take over the sockets passed through LISTEN_PID / LISTEN_FDS, if they are meant for us
+pub fn systemd_sockets() -> std::io::Result<Vec<ActivatedSocket>> {
+    let for_us = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    if !for_us {
+        return Ok(Vec::new());
+    }
+
+    let count: RawFd = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(0);
+
+    let mut sockets = Vec::new();
+    for fd in SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count {
+        // SAFETY: systemd hands these descriptors to this process and nothing else owns them
+        let socket = unsafe { Socket::from_raw_fd(fd) };
+        socket.set_cloexec(true)?;
+        socket.set_nonblocking(true)?;
+
+        let local_addr = socket.local_addr()?;
+        if local_addr.is_unix() {
+            sockets.push(ActivatedSocket::Unix(UnixListener::from(OwnedFd::from(
+                socket,
+            ))));
+        } else if local_addr.as_socket().is_some() {
+            sockets.push(ActivatedSocket::Tcp(socket.into()));
+        } else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("Unsupported socket type passed by systemd on fd {}", fd),
+            ));
+        }
+    }
+
+    // Child processes must not take the sockets for theirs
+    // SAFETY: called at startup, before any other thread reads the environment
+    unsafe {
+        std::env::remove_var("LISTEN_PID");
+        std::env::remove_var("LISTEN_FDS");
+        std::env::remove_var("LISTEN_FDNAMES");
+    }
+
+    log::debug!("Received {} socket(s) from systemd", sockets.len());
+    Ok(sockets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn resolves_owners() {
+        let cases = [
+            ("0", (Some(0), None)),
+            ("0:0", (Some(0), Some(0))),
+            (":0", (None, Some(0))),
+            ("0:", (Some(0), None)),
+            ("1000:1000", (Some(1000), Some(1000))),
+            ("root", (Some(0), None)),
+        ];
+        for (owner, expected) in cases {
+            assert_eq!(resolve_owner(owner).unwrap(), expected, "{}", owner);
+        }
+
+        for owner in ["no-such-user-holonet", "0:no-such-group-holonet"] {
+            assert_eq!(
+                resolve_owner(owner).unwrap_err().kind(),
+                std::io::ErrorKind::NotFound,
+                "{}",
+                owner
+            );
+        }
+    }
+
+    #[test]
+    fn binds_unix_sockets() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("holonet.sock");
+
+        let listener = bind_unix_socket(&path, Some(0o660), None).unwrap();
+        let metadata = std::fs::symlink_metadata(&path).unwrap();
+        assert!(metadata.file_type().is_socket());
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o660);
+
+        // A socket left behind by a previous run is replaced
+        drop(listener);
+        bind_unix_socket(&path, None, None).unwrap();
+
+        // Nothing is left of the private folder it was bound in
+        let names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["holonet.sock"]);
+
+        // Anything else is not
+        let file = dir.path().join("file");
+        std::fs::write(&file, "keep").unwrap();
+        let err = bind_unix_socket(&file, None, None).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "keep");
+    }
+}
@@ -1,12 +1,13 @@
+use actix_web::http::header::HeaderValue;
 use rcgen::generate_simple_self_signed;
 use rustls::ServerConfig;
 use std::io::BufReader;
 
 use super::errors::UtilsError;
 
-pub fn generate_self_signed_cert(ip: &String) -> Result<ServerConfig, UtilsError> {
+pub fn generate_self_signed_cert(names: &[String]) -> Result<ServerConfig, UtilsError> {
     // Generate a self-signed certificate
-    let cert = generate_simple_self_signed(vec![ip.into()])?;
+    let cert = generate_simple_self_signed(names.to_vec())?;
 
     let cert_der = cert.cert.pem();
     let private_key_der = cert.key_pair.serialize_pem();
@@ -30,3 +31,230 @@ pub fn generate_self_signed_cert(ip: &String) -> Result<ServerConfig, UtilsError
         .with_no_client_auth()
         .with_single_cert(tls_certs, rustls::pki_types::PrivateKeyDer::Pkcs8(tls_key))?)
 }
+
+// Parse a human readable size such as "512", "10K", "200M" or "4G" into bytes
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split_at = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split_at);
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid size '{}'", value))?;
+
+    let multiplier: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1024,
+        "M" | "MB" | "MIB" => 1024 * 1024,
+        "G" | "GB" | "GIB" => 1024 * 1024 * 1024,
+        "T" | "TB" | "TIB" => 1024 * 1024 * 1024 * 1024,
+        _ => return Err(format!("Unknown size unit '{}'", unit)),
+    };
+
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("Size '{}' is too large", value))
+}
+
+// Parse a duration such as "90", "30m", "24h" or "7d" into seconds
+pub fn parse_duration(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split_at = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split_at);
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration '{}'", value))?;
+
+    let multiplier: u64 = match unit.to_ascii_lowercase().as_str() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("Unknown duration unit '{}'", unit)),
+    };
+
+    number
+        .checked_mul(multiplier)
+        .filter(|seconds| *seconds > 0)
+        .ok_or_else(|| format!("Invalid duration '{}'", value))
+}
+
+// Parse a positive rate such as "2" or "0.5", NaN and infinity make no sense as a limit
+pub fn parse_rate(value: &str) -> Result<f64, String> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|rate| rate.is_finite() && *rate > 0.0)
+        .ok_or_else(|| format!("Invalid rate '{}', expected a positive number", value))
+}
+
+// Parse an octal permission mode such as "660"
+pub fn parse_mode(value: &str) -> Result<u32, String> {
+    u32::from_str_radix(value.trim_start_matches("0o"), 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| format!("Invalid octal mode '{}'", value))
+}
+
+// Normalize a URL path prefix such as "files/" into "/files", the root becomes ""
+pub fn parse_base_url(value: &str) -> Result<String, String> {
+    let trimmed = value.trim().trim_matches('/');
+    if trimmed.contains(['?', '#', ' ']) {
+        return Err(format!("Invalid base URL '{}'", value));
+    }
+    if trimmed.is_empty() {
+        Ok(String::new())
+    } else {
+        Ok(format!("/{}", trimmed))
+    }
+}
+
+// Check that a value such as "public, max-age=3600" can be sent as a header
+pub fn parse_header_value(value: &str) -> Result<HeaderValue, String> {
+    HeaderValue::from_str(value.trim()).map_err(|_| format!("Invalid header value '{}'", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        let cases = [
+            ("0", 0),
+            ("512", 512),
+            (" 512 ", 512),
+            ("512B", 512),
+            ("10K", 10 * 1024),
+            ("10kb", 10 * 1024),
+            ("10KiB", 10 * 1024),
+            ("200M", 200 * 1024 * 1024),
+            ("4G", 4 * 1024 * 1024 * 1024),
+            ("2T", 2 * 1024 * 1024 * 1024 * 1024),
+            ("16777215T", 16777215 * 1024 * 1024 * 1024 * 1024),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_size(value), Ok(expected), "{}", value);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        let cases = [
+            "",
+            "G",
+            "-1",
+            "1.5G",
+            "10X",
+            "10 M",
+            "0x10",
+            // Overflows, of the number itself and after applying the unit
+            "18446744073709551616",
+            "16777216T",
+            "18446744073709551615K",
+        ];
+        for value in cases {
+            assert!(parse_size(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn parses_modes() {
+        let cases = [
+            ("660", 0o660),
+            ("0o600", 0o600),
+            ("0755", 0o755),
+            ("7777", 0o7777),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_mode(value), Ok(expected), "{}", value);
+        }
+
+        for value in ["", "rw", "680", "10000", "-600", "0x1ff", "99999999999"] {
+            assert!(parse_mode(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn parses_base_urls() {
+        let cases = [
+            ("", ""),
+            ("/", ""),
+            ("files", "/files"),
+            ("/files/", "/files"),
+            ("//files//", "/files"),
+            ("a/b", "/a/b"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_base_url(value), Ok(expected.to_string()), "{}", value);
+        }
+
+        for value in ["files?x=1", "files#top", "my files"] {
+            assert!(parse_base_url(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn parses_header_values() {
+        for value in ["no-cache", " public, max-age=3600 "] {
+            assert_eq!(
+                parse_header_value(value).unwrap(),
+                value.trim(),
+                "{}",
+                value
+            );
+        }
+        assert!(parse_header_value("max-age=60\r\nX-Injected: 1").is_err());
+    }
+
+    #[test]
+    fn parses_durations() {
+        let cases = [
+            ("90", 90),
+            ("90s", 90),
+            (" 15m ", 15 * 60),
+            ("12h", 12 * 60 * 60),
+            ("7d", 7 * 24 * 60 * 60),
+            ("2W", 2 * 7 * 24 * 60 * 60),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_duration(value), Ok(expected), "{}", value);
+        }
+
+        let cases = [
+            "",
+            "h",
+            "0",
+            "0d",
+            "-1h",
+            "1.5h",
+            "10y",
+            "1 h",
+            // Overflows, of the number itself and after applying the unit
+            "18446744073709551616",
+            "30500568904943667w",
+        ];
+        for value in cases {
+            assert!(parse_duration(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn parses_rates() {
+        let cases = [("2", 2.0), (" 0.5 ", 0.5), ("1e3", 1000.0)];
+        for (value, expected) in cases {
+            assert_eq!(parse_rate(value), Ok(expected), "{}", value);
+        }
+
+        for value in ["", "0", "-1", "0.0", "NaN", "inf", "-inf", "1e400", "fast"] {
+            assert!(parse_rate(value).is_err(), "{}", value);
+        }
+    }
+}